@@ -0,0 +1,94 @@
+//! 搜索函数的基准测试，需要开启 `bench` 特性：
+//!
+//! ```sh
+//! cargo bench --features bench
+//! ```
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use ilearn::{
+    execute_search,
+    matcher::{required_literal, search_with, PrefilterMatcher, RegexMatcher},
+    search_case_insensitive_right, search_right, Config,
+};
+use regex::Regex;
+
+/// 生成的测试语料的行数
+const LINES: usize = 100_000;
+
+/**
+ * 在代码中生成测试语料，不依赖外部文件
+ *
+ * - hit-heavy：大约一半的行包含 `needle`
+ * - miss-heavy：只有最后一行包含 `needle`
+ */
+fn corpus(hit_every: usize) -> String {
+    let mut content = String::with_capacity(LINES * 48);
+    for i in 0..LINES {
+        if i % hit_every == 0 {
+            content.push_str("the quick brown fox found a needle in line ");
+        } else {
+            content.push_str("the quick brown fox jumps over the lazy dog ");
+        }
+        content.push_str(&i.to_string());
+        content.push('\n');
+    }
+    content.push_str("needle at the very end\n");
+    content
+}
+
+fn regex_config(pattern: &str) -> Config {
+    let args: Vec<String> = ["minigrep", "-E", "corpus.txt", pattern]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    Config::build(&args).unwrap()
+}
+
+fn bench_search(c: &mut Criterion) {
+    let inputs = [("hit-heavy", corpus(2)), ("miss-heavy", corpus(usize::MAX))];
+    let regex = regex_config(r"needle in line \d+");
+
+    let mut group = c.benchmark_group("search");
+    for (name, content) in &inputs {
+        group.bench_with_input(
+            BenchmarkId::new("search_right", name),
+            content,
+            |b, content| b.iter(|| search_right(black_box("needle"), black_box(content))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("search_case_insensitive_right", name),
+            content,
+            |b, content| {
+                b.iter(|| search_case_insensitive_right(black_box("needle"), black_box(content)))
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("regex", name), content, |b, content| {
+            b.iter(|| execute_search(&regex, black_box(content)))
+        });
+    }
+    group.finish();
+}
+
+/**
+ * 正则的字面量预过滤：绝大多数行不匹配时，先用 `contains` 排除它们可以省掉几乎所有的正则匹配
+ */
+fn bench_prefilter(c: &mut Criterion) {
+    let content = corpus(usize::MAX);
+    let pattern = r"needle in line \d+";
+    let literal = required_literal(pattern).unwrap();
+
+    let mut group = c.benchmark_group("prefilter");
+    group.bench_function("regex-only", |b| {
+        let matcher = RegexMatcher::new(Regex::new(pattern).unwrap());
+        b.iter(|| search_with(&matcher, black_box(&content)))
+    });
+    group.bench_function("literal-prefilter", |b| {
+        let inner = Box::new(RegexMatcher::new(Regex::new(pattern).unwrap()));
+        let matcher = PrefilterMatcher::new(&literal, false, inner);
+        b.iter(|| search_with(&matcher, black_box(&content)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_search, bench_prefilter);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+use ilearn::{exit_code, run, Config};
+use std::{env, process};
+
+fn main() {
+    // 通过类型注释，Rust编译器会将collect方法读取成指定类型
+    let args: Vec<String> = env::args().collect();
+    // 解构结构体，用unwrap取出Ok的内容，或者在闭包中拿到err错误信息
+    let config: Config = Config::build(&args).unwrap_or_else(|err| {
+        // 闭包读取err错误信息
+        eprintln!("Problem parsing arguments: {err}");
+        process::exit(exit_code(false, true));
+    });
+    let stats = config.stats();
+    let summary = config.summary();
+
+    // 与 grep 一致，通过退出码告诉调用方是否找到了匹配，方便在 shell 条件判断中使用
+    let code = match run(config) {
+        Ok(report) => {
+            if stats {
+                println!("{report}");
+                println!("{} lines scanned", report.lines_scanned);
+            }
+            // 汇总输出到标准错误，不会混进通过管道传给其它程序的结果中
+            if summary {
+                eprintln!("{}", report.summary());
+            }
+            exit_code(report.found(), report.errors > 0)
+        }
+        Err(e) => {
+            eprintln!("Application error: {e}");
+            exit_code(false, true)
+        }
+    };
+    process::exit(code);
+}
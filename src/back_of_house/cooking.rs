@@ -1 +1,21 @@
-pub fn cooking() {}
+use super::{MenuItem, Order};
+
+/**
+ * 做好的一桌菜
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Meal {
+    pub table: usize,
+    pub dishes: Vec<MenuItem>,
+    pub total: u32,
+}
+
+/// 按照订单备餐，并计算总价
+pub fn prepare(order: Order) -> Meal {
+    let total = order.items.iter().map(|item| item.price()).sum();
+    Meal {
+        table: order.table,
+        dishes: order.items,
+        total,
+    }
+}
@@ -1 +1,33 @@
+// 餐厅后厨，负责菜单和备餐
+
+/**
+ * 菜单上的菜品
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuItem {
+    Soup,
+    Salad,
+    Steak,
+}
+
+impl MenuItem {
+    /// 价格，以分为单位
+    pub fn price(self) -> u32 {
+        match self {
+            MenuItem::Soup => 800,
+            MenuItem::Salad => 1200,
+            MenuItem::Steak => 3600,
+        }
+    }
+}
+
+/**
+ * 前厅交给后厨的订单
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Order {
+    pub table: usize,
+    pub items: Vec<MenuItem>,
+}
+
 pub mod cooking;
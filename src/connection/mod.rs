@@ -0,0 +1,372 @@
+use std::io::{self, Cursor, Write as _};
+
+use bytes::{Buf, BytesMut};
+use mini_redis::{frame::Error::Incomplete, Frame};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter},
+    net::TcpStream,
+};
+
+/**
+ * 在字节流上按照 redis 协议读写数据帧
+ *
+ * - 读取：数据先从 socket 读取到 `buffer` 中，凑够一个完整的帧之后再解析，剩余的数据留给下一次 `read_frame`
+ * - 写入：数据帧先写入 `BufWriter` 的缓冲区，调用 [`Connection::flush`] 时才真正写到 socket 中，
+ *   连续写入多个小帧时只需要 flush 一次，减少系统调用的次数
+ *
+ * 默认包装的是 `TcpStream`，任何实现了 `AsyncRead + AsyncWrite` 的类型都可以使用，测试中使用的是内存管道
+ */
+pub struct Connection<S = TcpStream> {
+    stream: BufWriter<S>,
+    buffer: BytesMut,
+    /// 从 socket 读取的字节数，包括协议中的类型标识和 `\r\n` 分隔符
+    bytes_read: u64,
+    /// 写入的字节数，按照编码之后的帧计算
+    bytes_written: u64,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    pub fn new(stream: S) -> Connection<S> {
+        Connection {
+            stream: BufWriter::new(stream),
+            buffer: BytesMut::with_capacity(4 * 1024),
+            bytes_read: 0,
+            bytes_written: 0,
+        }
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /**
+     * 读取一个完整的数据帧，对端关闭连接时返回 `None`
+     *
+     * 如果关闭连接时缓冲区中还有不完整的帧，说明对端在发送数据的途中断开了，返回错误
+     */
+    pub async fn read_frame(&mut self) -> mini_redis::Result<Option<Frame>> {
+        match self.fill_frame().await? {
+            Some((frame, len)) => {
+                // 解析完成，将缓冲区中该帧的数据移除
+                self.buffer.advance(len);
+                Ok(Some(frame))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /**
+     * 查看下一个数据帧但不从缓冲区中移除，之后的 `read_frame` 仍然会返回同一个帧
+     *
+     * 可以先根据帧的类型决定交给谁处理，再真正读取
+     */
+    pub async fn peek_frame(&mut self) -> mini_redis::Result<Option<Frame>> {
+        Ok(self.fill_frame().await?.map(|(frame, _)| frame))
+    }
+
+    /// 读取直到缓冲区中有一个完整的帧，返回解析出的帧和它占用的字节数，不移动缓冲区
+    async fn fill_frame(&mut self) -> mini_redis::Result<Option<(Frame, usize)>> {
+        loop {
+            if let Some(frame) = self.parse_frame()? {
+                return Ok(Some(frame));
+            }
+
+            // 缓冲区的数据不足以解析出一个帧，继续从 socket 中读取
+            let n = self.stream.read_buf(&mut self.buffer).await?;
+            if n == 0 {
+                return if self.buffer.is_empty() {
+                    Ok(None)
+                } else {
+                    Err("connection reset by peer".into())
+                };
+            }
+            self.bytes_read += n as u64;
+        }
+    }
+
+    fn parse_frame(&self) -> mini_redis::Result<Option<(Frame, usize)>> {
+        let mut buf = Cursor::new(&self.buffer[..]);
+        match Frame::check(&mut buf) {
+            Ok(_) => {
+                // check 之后游标的位置就是这个帧的长度
+                let len = buf.position() as usize;
+                buf.set_position(0);
+                let frame = Frame::parse(&mut buf)?;
+                Ok(Some((frame, len)))
+            }
+            Err(Incomplete) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /**
+     * 将数据帧写入写缓冲区，不会立即发送
+     *
+     * 写完需要发送的帧之后调用 [`Connection::flush`]，否则数据可能一直停留在缓冲区中
+     */
+    pub async fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        match frame {
+            Frame::Array(values) => {
+                self.write_bytes(b"*").await?;
+                self.write_decimal(values.len() as u64).await?;
+                for value in values {
+                    self.write_value(value).await?;
+                }
+            }
+            _ => self.write_value(frame).await?,
+        }
+        Ok(())
+    }
+
+    /// 将写缓冲区中的数据全部发送到 socket
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush().await
+    }
+
+    /**
+     * 清空读缓冲区，保留已经分配的容量
+     *
+     * 连接池复用连接时调用，避免长期存活的连接不断累积内存或者每次复用都重新分配。
+     * 注意：缓冲区中尚未解析完的半个帧也会被丢弃
+     */
+    pub fn clear_buffer(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// 写入数组中的一项，嵌套的数组递归地交给 `write_frame`，比如 `SCAN` 的回复
+    async fn write_value(&mut self, frame: &Frame) -> io::Result<()> {
+        match frame {
+            Frame::Simple(val) => {
+                self.write_bytes(b"+").await?;
+                self.write_bytes(val.as_bytes()).await?;
+                self.write_bytes(b"\r\n").await?;
+            }
+            Frame::Error(val) => {
+                self.write_bytes(b"-").await?;
+                self.write_bytes(val.as_bytes()).await?;
+                self.write_bytes(b"\r\n").await?;
+            }
+            Frame::Integer(val) => {
+                self.write_bytes(b":").await?;
+                self.write_decimal(*val).await?;
+            }
+            Frame::Null => {
+                self.write_bytes(b"$-1\r\n").await?;
+            }
+            Frame::Bulk(val) => {
+                self.write_bytes(b"$").await?;
+                self.write_decimal(val.len() as u64).await?;
+                self.write_bytes(val).await?;
+                self.write_bytes(b"\r\n").await?;
+            }
+            // async fn 递归调用需要装箱，否则 future 的大小无法确定
+            Frame::Array(_) => Box::pin(self.write_frame(frame)).await?,
+        }
+        Ok(())
+    }
+
+    /// 整数和 bulk 长度的编码：先格式化到栈上的缓冲区中，再连同 `\r\n` 一次写入，不需要分配字符串
+    async fn write_decimal(&mut self, val: u64) -> io::Result<()> {
+        // u64 最多 20 位，再加上 `\r\n`
+        let mut buf = [0u8; 22];
+        let mut cursor = Cursor::new(&mut buf[..]);
+        write!(cursor, "{val}\r\n")?;
+        let len = cursor.position() as usize;
+        self.write_bytes(&buf[..len]).await
+    }
+
+    /// 所有写入都经过这里，方便统计写入的字节数
+    async fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.stream.write_all(bytes).await?;
+        self.bytes_written += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+/// [`describe`] 最多显示 bulk 数据的这么多个字节，更长的只显示开头和总长度
+pub const DESCRIBE_BULK_LIMIT: usize = 32;
+
+/**
+ * 把数据帧渲染成便于阅读的一行文本，用于日志，例如 `Array[Bulk("SET"), Bulk("k"), Bulk("v")]`
+ *
+ * bulk 数据中不可打印的字节按照 `\xNN` 的形式转义，超过 [`DESCRIBE_BULK_LIMIT`] 的部分截断，
+ * 后面附上完整的字节数，二进制的大数据不会刷满日志
+ */
+pub fn describe(frame: &Frame) -> String {
+    match frame {
+        Frame::Simple(val) => format!("Simple({val:?})"),
+        Frame::Error(val) => format!("Error({val:?})"),
+        Frame::Integer(val) => format!("Integer({val})"),
+        Frame::Null => String::from("Null"),
+        Frame::Bulk(val) if val.len() > DESCRIBE_BULK_LIMIT => format!(
+            "Bulk(\"{}\"… {} bytes)",
+            val[..DESCRIBE_BULK_LIMIT].escape_ascii(),
+            val.len()
+        ),
+        Frame::Bulk(val) => format!("Bulk(\"{}\")", val.escape_ascii()),
+        Frame::Array(frames) => {
+            let items: Vec<String> = frames.iter().map(describe).collect();
+            format!("Array[{}]", items.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn describe_renders_nested_frames() {
+        let bulk = |s: &str| Frame::Bulk(Bytes::from(s.to_string()));
+        let frame = Frame::Array(vec![
+            bulk("SET"),
+            bulk("k"),
+            Frame::Array(vec![Frame::Integer(7), Frame::Null]),
+            Frame::Simple("OK".to_string()),
+            Frame::Bulk(Bytes::from_static(b"\x00\xff\"")),
+        ]);
+        assert_eq!(
+            r#"Array[Bulk("SET"), Bulk("k"), Array[Integer(7), Null], Simple("OK"), Bulk("\x00\xff\"")]"#,
+            describe(&frame)
+        );
+
+        let long = Frame::Bulk(Bytes::from(vec![b'a'; 100]));
+        assert_eq!(
+            format!("Bulk(\"{}\"… 100 bytes)", "a".repeat(DESCRIBE_BULK_LIMIT)),
+            describe(&long)
+        );
+    }
+
+    #[tokio::test]
+    async fn peek_does_not_consume() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let mut writer = Connection::new(client);
+        let mut reader = Connection::new(server);
+        writer
+            .write_frame(&Frame::Simple("first".to_string()))
+            .await
+            .unwrap();
+        writer.write_frame(&Frame::Integer(2)).await.unwrap();
+        writer.flush().await.unwrap();
+        drop(writer);
+
+        let peeked = reader.peek_frame().await.unwrap().unwrap();
+        assert!(matches!(&peeked, Frame::Simple(s) if s == "first"));
+        // 多次查看结果相同
+        let again = reader.peek_frame().await.unwrap().unwrap();
+        assert_eq!(peeked.to_string(), again.to_string());
+
+        let read = reader.read_frame().await.unwrap().unwrap();
+        assert_eq!(peeked.to_string(), read.to_string());
+        assert!(matches!(
+            reader.read_frame().await.unwrap(),
+            Some(Frame::Integer(2))
+        ));
+        assert!(reader.peek_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn byte_counters_include_delimiters() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let mut writer = Connection::new(client);
+        let mut reader = Connection::new(server);
+
+        // "$5\r\nhello\r\n"
+        writer
+            .write_frame(&Frame::Bulk(Bytes::from("hello")))
+            .await
+            .unwrap();
+        assert_eq!(11, writer.bytes_written());
+        writer.write_frame(&Frame::Null).await.unwrap();
+        assert_eq!(16, writer.bytes_written());
+        writer.flush().await.unwrap();
+
+        reader.read_frame().await.unwrap().unwrap();
+        reader.read_frame().await.unwrap().unwrap();
+        assert_eq!(16, reader.bytes_read());
+        assert_eq!(0, reader.bytes_written());
+    }
+
+    #[tokio::test]
+    async fn integers_round_trip_through_parse_frame() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let mut writer = Connection::new(client);
+        let mut reader = Connection::new(server);
+
+        for val in [0, 42, u64::MAX] {
+            writer.write_frame(&Frame::Integer(val)).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+        // ":18446744073709551615\r\n" 是最长的情况
+        assert_eq!(4 + 5 + 23, writer.bytes_written());
+        drop(writer);
+
+        // 直接读到缓冲区中，绕过 read_frame 单独检查解析
+        while reader.stream.read_buf(&mut reader.buffer).await.unwrap() > 0 {}
+        for expected in [0, 42, u64::MAX] {
+            let (frame, len) = reader.parse_frame().unwrap().unwrap();
+            assert!(matches!(frame, Frame::Integer(val) if val == expected));
+            reader.buffer.advance(len);
+        }
+        assert!(reader.parse_frame().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn clear_buffer_keeps_capacity() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let mut connection = Connection::new(server);
+        connection.buffer.extend_from_slice(&[b'x'; 1024]);
+        let capacity = connection.buffer.capacity();
+
+        connection.clear_buffer();
+        assert_eq!(0, connection.buffer.len());
+        assert_eq!(capacity, connection.buffer.capacity());
+
+        // 清空之后可以继续正常读取
+        let mut writer = Connection::new(client);
+        writer.write_frame(&Frame::Integer(7)).await.unwrap();
+        writer.flush().await.unwrap();
+        let frame = connection.read_frame().await.unwrap().unwrap();
+        assert_eq!("7", frame.to_string());
+    }
+
+    #[tokio::test]
+    async fn deferred_flush_keeps_every_frame() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let mut writer = Connection::new(client);
+        let mut reader = Connection::new(server);
+
+        let frames = vec![
+            Frame::Simple("OK".to_string()),
+            Frame::Integer(42),
+            Frame::Bulk(Bytes::from("hello")),
+            Frame::Null,
+            Frame::Array(vec![
+                Frame::Bulk(Bytes::from("get")),
+                Frame::Bulk(Bytes::from("key")),
+            ]),
+            // SCAN 的回复中嵌套了数组
+            Frame::Array(vec![
+                Frame::Bulk(Bytes::from("0")),
+                Frame::Array(vec![Frame::Bulk(Bytes::from("key"))]),
+            ]),
+        ];
+        for frame in &frames {
+            writer.write_frame(frame).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+        drop(writer);
+
+        let mut received = Vec::new();
+        while let Some(frame) = reader.read_frame().await.unwrap() {
+            received.push(frame.to_string());
+        }
+        let expected: Vec<String> = frames.iter().map(Frame::to_string).collect();
+        assert_eq!(expected, received);
+    }
+}
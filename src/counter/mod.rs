@@ -0,0 +1,125 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+
+/**
+ * 线程安全的计数器，适合用来统计请求数、处理的文件数等跨线程的指标
+ *
+ * 所有操作使用同一个内存顺序，默认是 `Ordering::SeqCst`：
+ * - `SeqCst`：最保守，所有线程看到的操作顺序都相同。计数器的值被用来判断其它数据是否已经就绪时使用
+ * - `AcqRel` / `Acquire` / `Release`：一个线程先写数据再增加计数，另一个线程读到计数后再读数据，
+ *   只需要这一对线程之间同步时使用，比 `SeqCst` 开销小
+ * - `Relaxed`：只保证计数本身不会丢失更新，不对其它内存的读写做任何同步。
+ *   纯统计用途（例如请求数）可以使用，但不能根据计数结果去读取其它线程写入的数据
+ *
+ * 读取操作不能使用 `Release` 语义，因此 `get` 会把 `Release` 降为 `Relaxed`、`AcqRel` 降为 `Acquire`
+ */
+#[derive(Debug)]
+pub struct Counter {
+    value: AtomicI64,
+    ordering: Ordering,
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Counter::new(0)
+    }
+}
+
+impl Counter {
+    pub const fn new(value: i64) -> Counter {
+        Counter::with_ordering(value, Ordering::SeqCst)
+    }
+
+    /// 指定所有操作使用的内存顺序
+    pub const fn with_ordering(value: i64, ordering: Ordering) -> Counter {
+        Counter {
+            value: AtomicI64::new(value),
+            ordering,
+        }
+    }
+
+    pub fn ordering(&self) -> Ordering {
+        self.ordering
+    }
+
+    /// 加 1，返回加之前的值
+    pub fn inc(&self) -> i64 {
+        self.add(1)
+    }
+
+    /// 加上 n（可以是负数），返回加之前的值
+    pub fn add(&self, n: i64) -> i64 {
+        // fetch_add 是一次原子的“读-改-写”，多个线程同时调用也不会丢失更新
+        self.value.fetch_add(n, self.ordering)
+    }
+
+    pub fn get(&self) -> i64 {
+        let ordering = match self.ordering {
+            Ordering::Release => Ordering::Relaxed,
+            Ordering::AcqRel => Ordering::Acquire,
+            ordering => ordering,
+        };
+        self.value.load(ordering)
+    }
+
+    /// 归零，返回归零之前的值
+    pub fn reset(&self) -> i64 {
+        self.value.swap(0, self.ordering)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn concurrent_adds_are_not_lost() {
+        let counter = Arc::new(Counter::default());
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let counter = Arc::clone(&counter);
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        counter.add(3);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(8 * 1000 * 3, counter.get());
+
+        counter.inc();
+        assert_eq!(24001, counter.reset());
+        assert_eq!(0, counter.get());
+    }
+
+    #[test]
+    fn relaxed_counter_reaches_total() {
+        let counter = Arc::new(Counter::with_ordering(0, Ordering::Relaxed));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let counter = Arc::clone(&counter);
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        counter.inc();
+                    }
+                })
+            })
+            .collect();
+        // join 本身会建立同步关系，之后读取到的一定是最终结果
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(8000, counter.get());
+        assert_eq!(Ordering::Relaxed, counter.ordering());
+    }
+
+    #[test]
+    fn release_ordering_can_be_read() {
+        let counter = Counter::with_ordering(5, Ordering::Release);
+        counter.add(-2);
+        assert_eq!(3, counter.get());
+    }
+}
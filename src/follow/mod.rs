@@ -0,0 +1,166 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{Config, SearchError};
+
+/// 两次检查文件是否有新内容之间的间隔
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/**
+ * 类似 `tail -f | grep`，持续读取文件中新追加的内容并输出匹配的行
+ *
+ * 只处理完整的行，最后一行还没有写完（没有换行符）时先留在缓冲区中，等下次读取补全后再匹配。
+ * 文件被截断（长度小于已经读取的位置）或者被轮转（路径指向了另一个文件）时重新从头读取
+ */
+pub struct Follower {
+    path: PathBuf,
+    file: File,
+    /// 已经读取到的位置
+    offset: u64,
+    /// 还没有遇到换行符的不完整的行
+    pending: Vec<u8>,
+    /// 已经读取的完整的行数，用于输出行号
+    line_number: usize,
+}
+
+impl Follower {
+    /**
+     * 打开文件并读出已有的内容，返回跟踪器和其中完整的行，第一次搜索由调用方使用这些内容完成
+     *
+     * 之后的 `poll` 从同一个文件句柄继续读取，两者之间追加的内容不会遗漏。
+     * 最后一行还没有写完时不包含在返回的内容中，留给之后的 `poll` 补全
+     */
+    pub fn open(path: &Path) -> io::Result<(Follower, Vec<u8>)> {
+        let mut file = File::open(path)?;
+        let mut existing = Vec::new();
+        file.read_to_end(&mut existing)?;
+        let offset = existing.len() as u64;
+        let complete = existing
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        let pending = existing.split_off(complete);
+        let follower = Follower {
+            path: path.to_path_buf(),
+            file,
+            offset,
+            line_number: existing.iter().filter(|&&b| b == b'\n').count(),
+            pending,
+        };
+        Ok((follower, existing))
+    }
+
+    /**
+     * 读取新追加的内容，输出其中匹配的完整的行，返回匹配的行数
+     *
+     * 轮转过程中文件暂时不存在时当作没有新内容，下次再检查
+     */
+    pub fn poll<W: Write>(&mut self, config: &Config, out: &mut W) -> Result<usize, SearchError> {
+        match self.replaced() {
+            Ok(true) => self.reopen()?,
+            Ok(false) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut appended = Vec::new();
+        self.file.read_to_end(&mut appended)?;
+        self.offset += appended.len() as u64;
+        self.pending.extend(appended);
+
+        let matcher = config.matcher();
+        let mut matched = 0;
+        while let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            self.line_number += 1;
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            // 每次只拿到一行，行号范围需要按照在整个文件中的行号判断
+            if !config.in_line_range(self.line_number) || !matcher.is_match(line) {
+                continue;
+            }
+            matched += 1;
+            if config.line_number {
+                writeln!(out, "{}:{line}", self.line_number)?;
+            } else {
+                writeln!(out, "{line}")?;
+            }
+        }
+        // 每次都刷新，新的匹配可以立刻显示出来
+        out.flush()?;
+        Ok(matched)
+    }
+
+    /// 文件是否被截断或者被替换成了另一个文件
+    fn replaced(&self) -> io::Result<bool> {
+        let current = fs::metadata(&self.path)?;
+        if current.len() < self.offset {
+            return Ok(true);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if current.ino() != self.file.metadata()?.ino() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        self.file = File::open(&self.path)?;
+        self.offset = 0;
+        self.line_number = 0;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_dir;
+    use std::fs::OpenOptions;
+
+    fn append(path: &Path, text: &str) {
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn appended_lines_are_searched() {
+        let dir = temp_dir("appended_lines_are_searched");
+        let path = dir.join("app.log");
+        fs::write(&path, "old needle\nhalf nee").unwrap();
+        let config = Config {
+            queries: vec![String::from("needle")],
+            line_number: true,
+            ..Config::default()
+        };
+        let (mut follower, existing) = Follower::open(&path).unwrap();
+        // 没有写完的最后一行留给之后的 poll
+        assert_eq!(b"old needle\n".to_vec(), existing);
+        let mut out = Vec::new();
+
+        // 打开之后、第一次 poll 之前追加的内容也会被读到
+        append(&path, "dle\nnew needle\nskip\npartial nee");
+        assert_eq!(2, follower.poll(&config, &mut out).unwrap());
+        append(&path, "dle\n");
+        assert_eq!(1, follower.poll(&config, &mut out).unwrap());
+        assert_eq!(0, follower.poll(&config, &mut out).unwrap());
+        assert_eq!(
+            "2:half needle\n3:new needle\n5:partial needle\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        // 截断之后从头开始读取，行号也重新计算
+        fs::write(&path, "needle\n").unwrap();
+        let mut out = Vec::new();
+        assert_eq!(1, follower.poll(&config, &mut out).unwrap());
+        assert_eq!("1:needle\n", String::from_utf8(out).unwrap());
+    }
+}
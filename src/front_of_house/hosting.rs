@@ -1,6 +1,34 @@
-pub fn add_to_waitlist() {}
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
 
-fn seat_at_table() {
+// 所有招待员共用一份等位名单
+static WAITLIST: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static NEXT_TABLE: AtomicUsize = AtomicUsize::new(1);
+
+/// 等位名单最多能容纳的客人数
+pub const WAITLIST_CAPACITY: usize = 16;
+
+/// 加入等位名单，返回前面还有几位客人在等；名单已满时返回 None
+pub fn add_to_waitlist(guest: &str) -> Option<usize> {
+    let mut waitlist = WAITLIST.lock().unwrap();
+    if waitlist.len() >= WAITLIST_CAPACITY {
+        return None;
+    }
+    waitlist.push_back(guest.to_string());
+    Some(waitlist.len() - 1)
+}
+
+/// 收拾好餐桌，把客人从等位名单中带到餐桌，返回桌号；客人不在名单中时返回 None
+pub fn seat_at_table(guest: &str) -> Option<usize> {
     super::clean();
-    self::add_to_waitlist();
+
+    let mut waitlist = WAITLIST.lock().unwrap();
+    let position = waitlist.iter().position(|waiting| waiting == guest)?;
+    waitlist.remove(position);
+    Some(NEXT_TABLE.fetch_add(1, Ordering::SeqCst))
 }
@@ -1,11 +1,55 @@
-use super::hosting;
-// use crate::front_of_house::hosting;
+use crate::back_of_house::{cooking::Meal, MenuItem, Order};
 
-fn take_order() {
-    crate::front_of_house::hosting::add_to_waitlist();
-    hosting::add_to_waitlist();
+/**
+ * 结账的小票，金额都以分为单位
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    pub table: usize,
+    pub items: Vec<MenuItem>,
+    pub total: u32,
+    pub paid: u32,
+    pub change: u32,
 }
 
-fn serve_order() {}
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum PaymentError {
+    #[error("nothing was ordered")]
+    EmptyOrder,
+    #[error("insufficient payment: {paid} paid, {due} due")]
+    Insufficient { due: u32, paid: u32 },
+}
+
+/// 点餐，只有已经入座的客人才能点餐
+pub fn take_order(table: usize, items: &[MenuItem]) -> Order {
+    Order {
+        table,
+        items: items.to_vec(),
+    }
+}
 
-pub fn take_payment() {}
+/// 把订单交给后厨，上菜
+pub fn serve_order(order: Order) -> Meal {
+    crate::back_of_house::cooking::prepare(order)
+}
+
+/// 结账：付款不足时返回错误，否则找零并开出小票
+pub fn take_payment(meal: &Meal, amount: u32) -> Result<Receipt, PaymentError> {
+    if meal.dishes.is_empty() {
+        return Err(PaymentError::EmptyOrder);
+    }
+    if amount < meal.total {
+        return Err(PaymentError::Insufficient {
+            due: meal.total,
+            paid: amount,
+        });
+    }
+
+    Ok(Receipt {
+        table: meal.table,
+        items: meal.dishes.clone(),
+        total: meal.total,
+        paid: amount,
+        change: amount - meal.total,
+    })
+}
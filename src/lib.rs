@@ -1,4 +1,26 @@
-use std::{env, error::Error, fs};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, HashSet, VecDeque},
+    env, fmt, fs,
+    io::{self, IsTerminal, Read, Write},
+    mem,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    str,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, SyncSender},
+        Arc, OnceLock,
+    },
+    thread,
+};
+
+use matcher::{
+    required_literal, AllMatcher, AnchoredMatcher, InvertMatcher, Matcher, NormalizedMatcher,
+    PrefilterMatcher, RegexMatcher, SubstringMatcher, WordMatcher,
+};
+use regex::{Regex, RegexBuilder};
 
 use crate::front_of_house::hosting;
 use front_of_house::serving;
@@ -9,6 +31,22 @@ mod back_of_house;
 
 fn cleanTable() {}
 
+pub use back_of_house::{cooking::Meal, MenuItem, Order};
+pub use front_of_house::serving::{PaymentError, Receipt};
+
+/**
+ * 在餐厅用餐时可能出现的错误
+ */
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum RestaurantError {
+    #[error("the waitlist is full")]
+    WaitlistFull,
+    #[error("{0} is not on the waitlist")]
+    NotWaiting(String),
+    #[error("{0}")]
+    Payment(#[from] PaymentError),
+}
+
 /**
  * # Example
  * eat_at_restaurant
@@ -16,16 +54,25 @@ fn cleanTable() {}
  * let a = 1;
  * println!("{}", a);
  * ```
+ *
+ * 客人从等位、入座、点餐、上菜到结账的完整流程，结账成功时返回小票；
+ * 任何一步失败都会以 `Err` 返回，不会继续后面的步骤
  */
-pub fn eat_at_restaurant() {
+pub fn eat_at_restaurant(
+    guest: &str,
+    items: &[MenuItem],
+    amount: u32,
+) -> Result<Receipt, RestaurantError> {
     // 绝对路径使用模块（方法）
-    crate::front_of_house::hosting::add_to_waitlist();
-    // 相对路径使用模块（方法）
-    front_of_house::hosting::add_to_waitlist();
+    crate::front_of_house::hosting::add_to_waitlist(guest).ok_or(RestaurantError::WaitlistFull)?;
     // use绝对路径导入并使用模块（方法）
-    hosting::add_to_waitlist();
+    let table = hosting::seat_at_table(guest)
+        .ok_or_else(|| RestaurantError::NotWaiting(guest.to_string()))?;
+    // 相对路径使用模块（方法）
+    let order = front_of_house::serving::take_order(table, items);
+    let meal = serving::serve_order(order);
     // use相对路径导入并使用模块（方法）
-    serving::take_payment();
+    Ok(serving::take_payment(&meal, amount)?)
 }
 
 pub mod compute {
@@ -71,6 +118,39 @@ pub mod compute {
         let a = 3;
         x + a
     }
+
+    /// 对整数做一次变换，多个变换可以通过 [`pipeline`] 串联起来
+    pub trait Transform {
+        fn apply(&self, x: i32) -> i32;
+    }
+
+    /// 闭包可以直接作为变换使用，例如 `|x| x * 2`
+    impl<F: Fn(i32) -> i32> Transform for F {
+        fn apply(&self, x: i32) -> i32 {
+            self(x)
+        }
+    }
+
+    /// 加上固定值 N 的变换，`AddN(1)` 等价于 [`add_one`]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct AddN(pub i32);
+
+    impl Transform for AddN {
+        fn apply(&self, x: i32) -> i32 {
+            x + self.0
+        }
+    }
+
+    /// 依次执行每一个变换，上一步的结果作为下一步的输入
+    /// ```rust
+    /// use ilearn::compute::{pipeline, AddN, Transform};
+    ///
+    /// let steps: Vec<Box<dyn Transform>> = vec![Box::new(AddN(1)), Box::new(|x| x * 10)];
+    /// assert_eq!(40, pipeline(3, &steps));
+    /// ```
+    pub fn pipeline(x: i32, steps: &[Box<dyn Transform>]) -> i32 {
+        steps.iter().fold(x, |acc, step| step.apply(acc))
+    }
 }
 
 /// 直接指定跳转标准库：`add_one` 返回一个[`Option`]类型
@@ -94,154 +174,5262 @@ macro_rules! foo {
 /**
  * 定义配置数据结构体
  */
+#[derive(Default, Clone)]
 pub struct Config {
-    query: String,
+    /// 多个查询字符串之间是“或”的关系，任意一个命中即可
+    queries: Vec<String>,
+    /// 使用 `-E` 时在 build 阶段编译好的正则表达式，所有文件和所有行共用这一个
+    regex: Option<Regex>,
+    /// 逐行使用正则匹配时，行中必须包含这个字面量才会运行正则，见 [`PrefilterMatcher`]
+    prefilter: Option<String>,
+    /// `prefilter` 由 `--literal-prefilter` 指定，重新编译时保留，不再自动分析
+    fixed_prefilter: bool,
+    /// 由查询字符串和各个匹配选项组合出的匹配器，只构建一次，所有文件和所有行共用，见 [`Config::matcher`]
+    matcher: OnceLock<Arc<dyn Matcher>>,
+    /// 跨行匹配：对整个文件内容进行匹配，而不是逐行匹配
+    multiline: bool,
     file_path: String,
+    /// 开启 `glob` 特性时，`file_path` 中的通配符展开之后得到的文件列表，为空表示没有使用通配符
+    files: Vec<PathBuf>,
     ignore_case: bool,
+    binary: BinaryPolicy,
+    /// 使用 `--no-ignore` 时为 true，递归搜索不读取 `.gitignore`；默认为 false，与命令行的默认行为一致
+    no_ignore: bool,
+    follow_symlinks: bool,
+    /// 递归搜索的最大深度，相对于起始目录计算，0 表示只搜索起始目录下直接包含的文件
+    max_depth: Option<usize>,
+    /// 递归搜索时只读取这些扩展名的文件，为空表示不限制
+    extensions: Vec<String>,
+    /// 超过这个大小（字节）的文件直接跳过，计入跳过的文件数
+    max_filesize: Option<u64>,
+    /// 输出的匹配行最多显示这么多个字符，超出的部分用 `…` 代替，匹配仍然在完整的行上进行
+    max_line_width: Option<usize>,
+    /// 只搜索这个范围内的行，两端都包含，行号从 1 开始；输出的行号仍然是在整个文件中的行号
+    line_range: Option<(usize, usize)>,
+    before_context: usize,
+    after_context: usize,
+    /// 每个匹配之前先输出它所在的函数、类等的标题行，标题行由这个正则识别
+    context_header: Option<Regex>,
+    /// 不相邻的上下文分组之间的分隔行，`None` 使用默认的 `--`，空字符串表示不输出分隔行
+    group_separator: Option<String>,
+    anchor: Anchor,
+    unique_consecutive: bool,
+    /// 去掉所有重复的匹配行（不要求相邻），只保留第一次出现的
+    unique_lines: bool,
+    /// 匹配之前先规范化每一行的空白，见 [`normalize_whitespace`]，输出的仍然是原始的行
+    normalize_ws: bool,
+    /// 反向匹配，只保留不匹配的行
+    invert: bool,
+    /// 只匹配完整的单词，查询字符串两侧不能紧挨着字母、数字或下划线
+    word: bool,
+    /// 有多个查询字符串时要求每一个都出现在行中，默认只要出现任意一个即可
+    match_all: bool,
+    /// 每个文件最多输出的匹配行数
+    max_count: Option<usize>,
+    /// 只输出所有输入中的第 n 个匹配（从 1 开始），搜索多个文件时跨文件计数，匹配不足 n 个时什么也不输出
+    nth: Option<usize>,
+    quiet: bool,
+    /**
+     * 只输出匹配的行数，不输出行的内容
+     *
+     * 与其它选项组合时的优先级：先按照 `invert` 选出行（反向匹配时统计的就是不匹配的行数），
+     * 再按照 `unique_consecutive`、`unique_lines` 和 `max_count` 处理，最后计数。`quiet` 优先于计数，不输出任何内容
+     */
+    count: bool,
+    /**
+     * 与 ripgrep 的 `--count-matches` 相同：统计查询在匹配行中出现的总次数，而不是匹配的行数
+     *
+     * 一行中出现两次时 `--count` 计为 1 行，`--count-matches` 计为 2 次。出现的位置来自配置的匹配器，
+     * 与搜索时一样遵循 `-w`、锚定、`--and` 等选项，多个查询字符串命中同一段内容时只计一次；跨行匹配时每个匹配计为一次
+     */
+    count_occurrences: bool,
+    /// 只输出没有任何匹配的文件路径，每个文件一行
+    files_without_match: bool,
+    /// 普通输出时在每一行前面加上行号
+    line_number: bool,
+    /// 把匹配的部分替换成这个字符串后再输出，使用正则时可以通过 `$1` 引用分组
+    replace: Option<String>,
+    /// 与 `replace` 一起使用，把替换后的内容写回原文件，类似 `sed -i`
+    in_place: bool,
+    /// 写回之前先把原文件复制为 `文件名.bak`
+    backup: bool,
+    /// 递归搜索时不输出匹配的行，而是按匹配行数从多到少列出每个文件
+    count_by_file: bool,
+    /// 与 grep 的 `-h` 相同：即使搜索多个文件，输出的行也不带 `路径:` 前缀
+    no_filename: bool,
+    /// 与 grep 的 `-H` 相同：只搜索一个文件时也带上 `路径:` 前缀
+    with_filename: bool,
+    /// 搜索多个文件时每个文件的路径只作为标题输出一次，下面是缩进的匹配行，文件之间空一行，类似 ripgrep
+    heading: bool,
+    /// 递归搜索时同时匹配文件路径（相对于起始目录），路径命中的文件即使内容不匹配也会输出路径
+    match_path: bool,
+    /// 搜索结束后输出统计信息
+    stats: bool,
+    /// 搜索结束后在标准错误中输出一行匹配、搜索、跳过的文件数量
+    summary: bool,
+    /// 搜索到 0 字节的文件时在标准错误中输出提示
+    warn_empty: bool,
+    /// 只输出会被搜索的文件列表，不读取文件内容，用来检查扩展名、gitignore、深度等过滤条件
+    dry_run: bool,
+    /// 不读取文件，而是监听这个地址，搜索客户端通过 TCP 发送的文本并把结果写回去
+    listen: Option<String>,
+    /// 类似 `tail -f`：搜索完已有的内容后继续等待文件追加新的行，只支持单个文件
+    follow: bool,
+    /// 交互模式：文件只读取一次，之后从标准输入反复读取查询，见 [`repl::run`]
+    interactive: bool,
+    /// 递归搜索目录时使用这么多个线程并行地搜索文件，输出与单线程搜索相同，见 [`search_tree_parallel`]
+    threads: Option<usize>,
+    format: OutputFormat,
+    color: ColorChoice,
+}
+
+/// `--context-header` 默认识别的标题行：常见语言中函数、类、类型定义的开头
+pub const DEFAULT_HEADER_PATTERN: &str =
+    r"^\s*(pub(\([^)]*\))?\s+)?(async\s+)?(fn|def|class|impl|struct|enum|trait|func|function)\b";
+
+/**
+ * 遇到二进制文件时的处理策略
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BinaryPolicy {
+    /// 直接跳过二进制文件，不做任何输出
+    Skip,
+    /// 与 grep 一致，命中时只输出 `Binary file X matches`，不打印内容
+    #[default]
+    Report,
+}
+
+/**
+ * 锚定匹配的方式，覆盖正则中最常用的 `^` 和 `$`
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Anchor {
+    /// 行中任意位置包含即可
+    #[default]
+    None,
+    /// 行以查询字符串开头，相当于 `^query`
+    Start,
+    /// 行以查询字符串结尾，相当于 `query$`
+    End,
+    /// 整行与查询字符串相同，相当于 `^query$`
+    Exact,
+}
+
+/**
+ * 搜索结果的输出格式
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// 直接输出匹配的行
+    #[default]
+    Plain,
+    /// 每个匹配输出一行 JSON 对象（NDJSON），方便其它工具解析
+    Json,
+    /**
+     * 所有匹配组成一个 JSON 数组，整个输出是一个完整的 JSON 文档，每个元素与 `Json` 的一行相同
+     *
+     * 数组必须等所有文件搜索完才能闭合，所以匹配会先缓存在内存中，最后一次性输出，不能像 NDJSON 那样边搜索边处理
+     */
+    JsonArray,
+}
+
+/**
+ * 是否使用 ANSI 颜色高亮匹配的内容
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// 只有输出到终端时才高亮，重定向到文件或管道时不输出转义码
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// 根据输出目标是否为终端决定是否真正启用颜色
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => is_terminal,
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/**
+ * 解析命令行参数时可能出现的错误
+ */
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("not enough arguments")]
+    NotEnoughArguments,
+    #[error("invalid flag: {0}")]
+    InvalidFlag(String),
+    #[error("option {0} requires a value")]
+    MissingValue(String),
+    #[error("invalid value {1:?} for option {0}")]
+    InvalidValue(String, String),
+    #[error("could not read pattern file {path}: {source}")]
+    PatternFile { path: String, source: io::Error },
+    #[error("invalid regex: {0}")]
+    InvalidRegex(#[from] regex::Error),
+    #[error("invalid glob pattern {0:?}: {1}")]
+    InvalidGlob(String, String),
+    #[error("no files match {0}")]
+    NoMatchingFiles(String),
+    #[error("option {0} requires {1}")]
+    MissingOption(&'static str, &'static str),
+    #[error("options {0} and {1} cannot be used together")]
+    ConflictingOptions(&'static str, &'static str),
+    #[error("replacement refers to unknown capture group {0:?}")]
+    UnknownCaptureGroup(String),
+    #[error("empty query matches every line; use --allow-empty if that is intended")]
+    EmptyQuery,
+    #[error("unterminated quote in {0:?}")]
+    UnterminatedQuote(String),
 }
 
 /**
  * impl 为 Config 实现自定义的方法
  */
 impl Config {
+    /**
+     * 从命令行参数构建配置，第一个参数是程序名
+     *
+     * 查询可以有多个，保存在 `queries` 中，任意一个匹配即可：`-e PATTERN` 可以重复使用，
+     * 也可以用来指定以 `-` 开头的查询；`-f FILE` 从文件中每行读取一个查询。
+     * 两者都没有时，文件路径之后的位置参数就是查询。
+     * 错误以 [`ConfigError`] 返回，调用方可以按种类处理，不再是原来的 `&'static str`
+     */
     // 返回Result对象，
-    pub fn build(args: &[String]) -> Result<Config, &'static str> {
-        if args.len() < 3 {
-            return Err("not enough arguments");
+    pub fn build(args: &[String]) -> Result<Config, ConfigError> {
+        Self::parse(args, &[], |name| env::var(name).ok())
+    }
+
+    /**
+     * 从一整条命令行字符串构建配置，适合把 minigrep 嵌入到其它程序中、只拿到一个命令字符串的情况
+     *
+     * 与 shell 一样按空白切分单词，单引号中的内容原样保留，双引号和引号外可以用 `\` 转义。
+     * 与 `build` 一样，第一个单词是程序名。以引号或转义开头的单词总是作为位置参数，
+     * 即使它以 `-` 开头，例如 `minigrep notes.txt '-v'` 搜索的是 `-v` 而不是反向匹配
+     */
+    pub fn from_shell_words(input: &str) -> Result<Config, ConfigError> {
+        let (args, literal): (Vec<String>, Vec<bool>) = shell_words(input)?.into_iter().unzip();
+        Self::parse(&args, &literal, |name| env::var(name).ok())
+    }
+
+    /// `literal` 与 `args` 一一对应，为 true 的参数不会被当作选项；比 `args` 短时缺少的部分按 false 处理
+    ///
+    /// 环境变量通过 `var` 读取，测试中可以替换成固定的值，不需要修改进程的环境变量
+    fn parse(
+        args: &[String],
+        literal: &[bool],
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Config, ConfigError> {
+        let mut positional = Vec::new();
+        let mut queries = Vec::new();
+        let mut binary = BinaryPolicy::Report;
+        let mut no_ignore = false;
+        let mut follow_symlinks = false;
+        let mut max_depth = None;
+        let mut max_filesize = None;
+        let mut max_line_width = None;
+        let mut line_range = None;
+        let mut extensions = Vec::new();
+        let mut before_context = 0;
+        let mut after_context = 0;
+        let mut group_separator = None;
+        let mut anchor = Anchor::None;
+        let mut unique_consecutive = false;
+        let mut unique_lines = false;
+        let mut context_header = None;
+        let mut normalize_ws = false;
+        let mut invert = false;
+        let mut word = false;
+        let mut match_all = false;
+        let mut max_count = None;
+        let mut nth = None;
+        let mut threads = None;
+        let mut quiet = false;
+        let mut count = false;
+        let mut files_without_match = false;
+        let mut line_number = false;
+        let mut ignore_case = false;
+        let mut replace = None;
+        let mut in_place = false;
+        let mut backup = false;
+        let mut count_by_file = false;
+        let mut count_occurrences = false;
+        let mut heading = false;
+        let mut no_filename = false;
+        let mut with_filename = false;
+        let mut allow_empty = false;
+        let mut match_path = false;
+        let mut stats = false;
+        let mut summary = false;
+        let mut warn_empty = false;
+        let mut dry_run = false;
+        let mut follow = false;
+        let mut interactive = false;
+        let mut listen = None;
+        let mut format = OutputFormat::Plain;
+        let mut color = ColorChoice::Auto;
+        let mut use_regex = false;
+        let mut literal_prefilter = None;
+        let mut multiline = false;
+        let mut escape = false;
+
+        // 第一个参数是程序名，从第二个参数开始区分选项和位置参数
+        let args = args.get(1..).unwrap_or_default();
+        let literal = literal.get(1..).unwrap_or_default();
+        let mut iter = args.iter();
+        // 组合短选项拆开之后还没有处理的单个选项
+        let mut short_flags = VecDeque::new();
+        loop {
+            let split;
+            let arg = match short_flags.pop_front() {
+                Some(flag) => {
+                    split = flag;
+                    &split
+                }
+                None => {
+                    let Some(arg) = iter.next() else {
+                        break;
+                    };
+                    // 选项的值在各自的分支中通过 iter.next() 取走，这里只会遇到选项和位置参数
+                    let index = args.len() - iter.len() - 1;
+                    if literal.get(index).copied().unwrap_or(false) {
+                        positional.push(arg.clone());
+                        continue;
+                    }
+                    // 只在选项的位置上拆开组合短选项，作为值的 `-in`（例如 `-e -in`）原样交给前面的选项
+                    if let Some(flags) = split_short_flags(arg)? {
+                        short_flags.extend(flags);
+                        continue;
+                    }
+                    arg
+                }
+            };
+            match arg.as_str() {
+                "--skip-binary" => binary = BinaryPolicy::Skip,
+                "--no-ignore" => no_ignore = true,
+                "--follow" => follow_symlinks = true,
+                "--max-depth" => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    let n = value
+                        .parse::<usize>()
+                        .map_err(|_| ConfigError::InvalidValue(arg.clone(), value.clone()))?;
+                    max_depth = Some(n);
+                }
+                "--max-filesize" => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    max_filesize = Some(parse_size(value)?);
+                }
+                "--max-line-width" => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    // 宽度为 0 时每一行都只剩下省略号，没有意义
+                    let width = value
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| ConfigError::InvalidValue(arg.clone(), value.clone()))?;
+                    max_line_width = Some(width);
+                }
+                "--lines" => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    line_range = Some(parse_line_range(value)?);
+                }
+                "--start" => anchor = Anchor::Start,
+                "--end" => anchor = Anchor::End,
+                "--exact" => anchor = Anchor::Exact,
+                "--unique-consecutive" => unique_consecutive = true,
+                "--unique-lines" => unique_lines = true,
+                "--context-header" => {
+                    context_header.get_or_insert_with(|| String::from(DEFAULT_HEADER_PATTERN));
+                }
+                "--header-pattern" => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    context_header = Some(value.clone());
+                }
+                "--normalize-ws" => normalize_ws = true,
+                "-v" | "--invert" => invert = true,
+                "-w" | "--word" => word = true,
+                "--and" => match_all = true,
+                "-m" | "--max-count" => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    let n = value
+                        .parse::<usize>()
+                        .map_err(|_| ConfigError::InvalidValue(arg.clone(), value.clone()))?;
+                    max_count = Some(n);
+                }
+                "--nth" => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    let n = value
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| ConfigError::InvalidValue(arg.clone(), value.clone()))?;
+                    nth = Some(n);
+                }
+                "--threads" => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    let n = value
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| ConfigError::InvalidValue(arg.clone(), value.clone()))?;
+                    threads = Some(n);
+                }
+                "-q" | "--quiet" => quiet = true,
+                "-c" | "--count" => count = true,
+                "-L" | "--files-without-match" => files_without_match = true,
+                "-n" | "--line-number" => line_number = true,
+                "-i" | "--ignore-case" => ignore_case = true,
+                "--replace" => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    replace = Some(value.clone());
+                }
+                "--in-place" => in_place = true,
+                "--backup" => backup = true,
+                "--count-by-file" => count_by_file = true,
+                "--count-matches" => count_occurrences = true,
+                "--heading" => heading = true,
+                // 两个选项同时出现时以后出现的为准
+                "-h" | "--no-filename" => (no_filename, with_filename) = (true, false),
+                "-H" | "--with-filename" => (no_filename, with_filename) = (false, true),
+                "--allow-empty" => allow_empty = true,
+                "--match-path" => match_path = true,
+                "--stats" => stats = true,
+                "--summary" => summary = true,
+                "--warn-empty" => warn_empty = true,
+                "--dry-run" => dry_run = true,
+                "--tail" => follow = true,
+                "--interactive" => interactive = true,
+                "--listen" => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    listen = Some(value.clone());
+                }
+                "--json" => format = OutputFormat::Json,
+                "--json-array" => format = OutputFormat::JsonArray,
+                "-E" | "--regex" => use_regex = true,
+                // 与 grep 一样，-E 和 -F 以最后出现的为准
+                "-F" | "--fixed-strings" => use_regex = false,
+                "--literal-prefilter" => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    literal_prefilter = Some(value.clone());
+                }
+                "-U" | "--multiline" => multiline = true,
+                "--escape" => escape = true,
+                "--color" => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    color = parse_color(value)?;
+                }
+                flag if flag.starts_with("--color=") => {
+                    color = parse_color(&flag["--color=".len()..])?;
+                }
+                "-e" => {
+                    let pattern = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    queries.push(pattern.clone());
+                }
+                "-f" => {
+                    let path = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    queries.extend(read_patterns(path)?);
+                }
+                // 可以重复指定，也可以用逗号分隔：--type rs,toml
+                "--type" => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    extensions.extend(
+                        value
+                            .split(',')
+                            .map(|ext| ext.trim().trim_start_matches('.'))
+                            .filter(|ext| !ext.is_empty())
+                            .map(String::from),
+                    );
+                }
+                "--group-separator" => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    group_separator = Some(value.clone());
+                }
+                flag if flag.starts_with("--group-separator=") => {
+                    group_separator = Some(flag["--group-separator=".len()..].to_string());
+                }
+                "--no-group-separator" => group_separator = Some(String::new()),
+                "-A" | "-B" | "-C" => {
+                    let n = iter
+                        .next()
+                        .and_then(|value| value.parse::<usize>().ok())
+                        .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+                    match arg.as_str() {
+                        "-A" => after_context = n,
+                        "-B" => before_context = n,
+                        _ => (before_context, after_context) = (n, n),
+                    }
+                }
+                flag if flag.starts_with("--") => {
+                    return Err(ConfigError::InvalidFlag(flag.to_string()))
+                }
+                _ => positional.push(arg.clone()),
+            }
+        }
+
+        // 通过 -e/-f 指定了查询字符串时，只需要文件路径这一个位置参数
+        let mut positional = positional.into_iter();
+        // 监听模式下输入来自网络，不需要文件路径
+        let file_path = if listen.is_some() {
+            String::new()
+        } else {
+            positional.next().ok_or(ConfigError::NotEnoughArguments)?
+        };
+        if queries.is_empty() {
+            // 命令行中没有查询字符串时，与 IGNORE_CASE 一样从环境变量中读取，方便在 CI 中使用
+            let query = positional.next().or_else(|| var("MINIGREP_QUERY"));
+            match query {
+                Some(query) => queries.push(query),
+                // 交互模式中查询字符串在运行时输入
+                None if interactive => {}
+                None => return Err(ConfigError::NotEnoughArguments),
+            }
+        }
+        // 交互模式中每次查询都要重新编译正则，跨行匹配的正则还需要先转义，这里不支持
+        // 交互模式从标准输入读取查询，文件内容不能再来自标准输入
+        if interactive && file_path == STDIN_PATH {
+            return Err(ConfigError::InvalidValue(
+                String::from("--interactive"),
+                file_path,
+            ));
+        }
+        if interactive && multiline {
+            return Err(ConfigError::ConflictingOptions(
+                "--interactive",
+                "--multiline",
+            ));
+        }
+        // 空字符串包含在每一行中，多半是脚本中的变量没有赋值，而不是真的想输出整个文件
+        if !allow_empty && queries.iter().any(String::is_empty) {
+            return Err(ConfigError::EmptyQuery);
+        }
+
+        let context_header = context_header
+            .map(|pattern| Regex::new(&pattern))
+            .transpose()?;
+
+        if in_place && replace.is_none() {
+            return Err(ConfigError::MissingOption("--in-place", "--replace"));
+        }
+        // 正则把所有查询字符串合并成一个表达式，无法要求每一个都匹配
+        if match_all && use_regex {
+            return Err(ConfigError::ConflictingOptions("--and", "--regex"));
+        }
+        if match_all && multiline {
+            return Err(ConfigError::ConflictingOptions("--and", "--multiline"));
+        }
+        // 反向匹配选出的行中没有任何出现，统计出现次数没有意义
+        if count_occurrences && invert {
+            return Err(ConfigError::ConflictingOptions(
+                "--count-matches",
+                "--invert",
+            ));
+        }
+        // JSON 中的每个对象都是一个匹配，计数、文件列表、上下文和标题这些按文件或者按组的输出无法表示
+        if format != OutputFormat::Plain {
+            let json = if format == OutputFormat::Json {
+                "--json"
+            } else {
+                "--json-array"
+            };
+            let conflicts = [
+                (before_context > 0 || after_context > 0, "-C"),
+                (count, "--count"),
+                (count_occurrences, "--count-matches"),
+                (count_by_file, "--count-by-file"),
+                (files_without_match, "--files-without-match"),
+                (heading, "--heading"),
+                (context_header.is_some(), "--context-header"),
+                (match_path, "--match-path"),
+                (in_place, "--in-place"),
+            ];
+            if let Some((_, flag)) = conflicts.iter().find(|(set, _)| *set) {
+                return Err(ConfigError::ConflictingOptions(json, flag));
+            }
+        }
+        // 标准输入没有可以写回的文件
+        if in_place && file_path == STDIN_PATH {
+            return Err(ConfigError::InvalidValue(
+                String::from("--in-place"),
+                file_path,
+            ));
+        }
+
+        let files = expand_glob(&file_path)?;
+        // 只能跟踪一个普通文件，标准输入、目录和通配符展开的多个文件都不支持
+        if follow
+            && (file_path == STDIN_PATH || !files.is_empty() || Path::new(&file_path).is_dir())
+        {
+            return Err(ConfigError::InvalidValue(String::from("--tail"), file_path));
         }
 
-        let file_path = args[1].clone();
-        let query = args[2].clone();
+        if escape {
+            queries = queries
+                .iter()
+                .map(|query| interpret_escapes(query))
+                .collect();
+        }
 
         // Rust 的 env 包提供了相应的方法读取环境变量
-        let ignore_case = env::var("IGNORE_CASE").is_ok();
+        let ignore_case = ignore_case || var("IGNORE_CASE").is_some();
+
+        // 在读取任何文件之前编译正则，错误的表达式可以尽早报错
+        let regex = if use_regex {
+            Some(compile_regex(
+                &queries,
+                anchor,
+                ignore_case,
+                multiline,
+                word,
+            )?)
+        } else if multiline {
+            // 跨行匹配统一使用正则实现，普通的查询字符串需要先转义
+            let escaped: Vec<String> = queries.iter().map(|query| regex::escape(query)).collect();
+            Some(compile_regex(
+                &escaped,
+                anchor,
+                ignore_case,
+                multiline,
+                word,
+            )?)
+        } else {
+            None
+        };
+
+        if literal_prefilter.is_some() && !use_regex {
+            return Err(ConfigError::MissingOption("--literal-prefilter", "--regex"));
+        }
+        // 跨行匹配不逐行运行正则，用不上预过滤
+        let fixed_prefilter = literal_prefilter.is_some();
+        let prefilter = match (use_regex && !multiline, literal_prefilter) {
+            (false, _) => None,
+            (true, Some(literal)) => Some(literal),
+            (true, None) => auto_prefilter(&queries, ignore_case),
+        };
 
-        Ok(Config {
+        // 引用了不存在的分组时 `Regex::replace_all` 会把它替换成空字符串，在这里提前报错
+        if let (true, Some(regex), Some(replace)) = (use_regex, &regex, &replace) {
+            check_replacement(regex, replace)?;
+        }
+
+        let config = Config {
             file_path,
-            query,
+            files,
+            queries,
+            regex,
+            prefilter,
+            fixed_prefilter,
+            multiline,
             ignore_case,
-        })
+            binary,
+            no_ignore,
+            follow_symlinks,
+            max_depth,
+            extensions,
+            max_filesize,
+            max_line_width,
+            line_range,
+            before_context,
+            after_context,
+            context_header,
+            group_separator,
+            anchor,
+            unique_consecutive,
+            unique_lines,
+            normalize_ws,
+            invert,
+            word,
+            match_all,
+            max_count,
+            nth,
+            quiet,
+            count,
+            count_occurrences,
+            files_without_match,
+            line_number,
+            replace,
+            in_place,
+            backup,
+            count_by_file,
+            no_filename,
+            with_filename,
+            heading,
+            match_path,
+            stats,
+            summary,
+            warn_empty,
+            dry_run,
+            follow,
+            interactive,
+            listen,
+            threads,
+            format,
+            color,
+            ..Config::default()
+        };
+        // 所有选项都确定之后构建一次匹配器，之后的搜索都使用这一个
+        config.matcher();
+        Ok(config)
+    }
+
+    /// 第一个查询字符串，只有一个查询字符串时就是它本身
+    pub fn query(&self) -> &str {
+        self.queries.first().map_or("", String::as_str)
+    }
+
+    /// 所有的查询字符串，包括 `-e` 和 `-f` 指定的
+    pub fn queries(&self) -> &[String] {
+        &self.queries
+    }
+
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    pub fn ignore_case(&self) -> bool {
+        self.ignore_case
+    }
+
+    pub fn stats(&self) -> bool {
+        self.stats
+    }
+
+    pub fn summary(&self) -> bool {
+        self.summary
+    }
+
+    /// 第 `line_number` 行（从 1 开始）是否在 `--lines` 指定的范围内，没有指定范围时总是返回 true
+    pub fn in_line_range(&self, line_number: usize) -> bool {
+        self.line_range
+            .is_none_or(|(start, end)| (start..=end).contains(&line_number))
+    }
+
+    /**
+     * 替换查询字符串，使用正则时重新编译，交互模式中每输入一次查询调用一次
+     *
+     * 表达式无效时返回错误，原来的查询字符串和正则保持不变
+     */
+    fn set_queries(&mut self, queries: Vec<String>) -> Result<(), regex::Error> {
+        let previous = std::mem::replace(&mut self.queries, queries);
+        self.recompile().inspect_err(|_| self.queries = previous)
+    }
+
+    /// 查询字符串或者大小写等选项改变之后重新编译正则和预过滤的字面量，并重新构建匹配器
+    fn recompile(&mut self) -> Result<(), regex::Error> {
+        if self.regex.is_some() {
+            self.regex = Some(compile_regex(
+                &self.queries,
+                self.anchor,
+                self.ignore_case,
+                self.multiline,
+                self.word,
+            )?);
+            // 与 build 一致：跨行匹配用不上预过滤，用户指定的字面量保持不变
+            if !self.multiline && !self.fixed_prefilter {
+                self.prefilter = auto_prefilter(&self.queries, self.ignore_case);
+            }
+        }
+        self.matcher = OnceLock::new();
+        self.matcher();
+        Ok(())
+    }
+
+    /**
+     * 逐行匹配使用的匹配器
+     *
+     * `build` 中构建一次之后缓存起来，每次调用只是克隆一个 `Arc`。直接构造的配置（例如测试中）在第一次调用时构建；
+     * 之后再修改查询字符串或者匹配选项时需要调用 `recompile`，否则仍然使用原来的匹配器
+     */
+    pub fn matcher(&self) -> Arc<dyn Matcher> {
+        Arc::clone(self.matcher.get_or_init(|| Arc::from(self.build_matcher())))
+    }
+
+    /// 先按照正则、单词、锚定选出基础的匹配器，再按需包装上空白规范化和反向匹配
+    fn build_matcher(&self) -> Box<dyn Matcher> {
+        // 规范化空白时查询字符串也使用同样的规则
+        let queries: Vec<String> = if self.normalize_ws {
+            self.queries
+                .iter()
+                .map(|query| normalize_whitespace(query))
+                .collect()
+        } else {
+            self.queries.clone()
+        };
+
+        let base = |queries: &[String]| -> Box<dyn Matcher> {
+            match (&self.regex, self.anchor) {
+                (Some(regex), _) => {
+                    let matcher = Box::new(RegexMatcher::new(regex.clone()));
+                    match &self.prefilter {
+                        Some(literal) => {
+                            Box::new(PrefilterMatcher::new(literal, self.ignore_case, matcher))
+                        }
+                        None => matcher,
+                    }
+                }
+                (None, Anchor::None) if self.word => {
+                    Box::new(WordMatcher::new(queries, self.ignore_case))
+                }
+                #[cfg(feature = "aho-corasick")]
+                (None, Anchor::None) if queries.len() > 1 => {
+                    match matcher::AhoCorasickMatcher::new(queries, self.ignore_case) {
+                        Ok(matcher) => Box::new(matcher),
+                        Err(_) => Box::new(SubstringMatcher::new(queries, self.ignore_case)),
+                    }
+                }
+                (None, Anchor::None) => Box::new(SubstringMatcher::new(queries, self.ignore_case)),
+                (None, anchor) => Box::new(AnchoredMatcher::new(
+                    queries,
+                    self.ignore_case,
+                    anchor,
+                    self.word,
+                )),
+            }
+        };
+        // 要求全部匹配时每个查询字符串单独一个匹配器，再用 `AllMatcher` 组合起来
+        let mut matcher = if self.match_all && self.regex.is_none() {
+            let matchers = queries
+                .iter()
+                .map(|query| base(std::slice::from_ref(query)))
+                .collect();
+            Box::new(AllMatcher(matchers))
+        } else {
+            base(&queries)
+        };
+        if self.normalize_ws {
+            matcher = Box::new(NormalizedMatcher(matcher));
+        }
+        if self.invert {
+            matcher = Box::new(InvertMatcher(matcher));
+        }
+        matcher
     }
 }
 
 /**
- * Box<dyn Error> 动态特征对象，只要实现了某个特征就可以进行类型转换
+ * 一次搜索的统计结果
  */
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let content =
-        fs::read_to_string(config.file_path).expect("Should have been able to read the file.");
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RunReport {
+    /// 匹配的行数；安静模式下找到第一个匹配就停止，所以最多为 1
+    pub matched_lines: usize,
+    /// 实际读取并搜索过的文件数量
+    pub files_searched: usize,
+    /// 至少有一个匹配的文件数量
+    pub files_matched: usize,
+    /// 按照配置跳过的文件数量，比如 `--skip-binary` 跳过的二进制文件
+    pub files_skipped: usize,
+    /// 读取或者搜索失败的文件数量，加上遍历目录时无法读取的目录和条目数量
+    pub errors: usize,
+    /// 所有搜索过的文件的总行数，空行也计算在内
+    pub lines_scanned: usize,
+    /// 搜索过的文件中大小为 0 字节的文件数量
+    pub empty_files: usize,
+}
+
+/// 单个文件的搜索结果
+#[derive(Debug)]
+struct FileStats {
+    matched_lines: usize,
+    lines_scanned: usize,
+    /// 文件是否为 0 字节
+    empty: bool,
+}
+
+impl RunReport {
+    /// 是否找到了匹配的内容
+    pub fn found(&self) -> bool {
+        self.matched_lines > 0
+    }
+
+    fn record(&mut self, outcome: Option<FileStats>) {
+        match outcome {
+            Some(stats) => {
+                self.files_searched += 1;
+                self.files_matched += usize::from(stats.matched_lines > 0);
+                self.matched_lines += stats.matched_lines;
+                self.lines_scanned += stats.lines_scanned;
+                self.empty_files += usize::from(stats.empty);
+            }
+            None => self.files_skipped += 1,
+        }
+    }
+
+    /// `--summary` 输出的一行汇总，例如：`matched 2 of 5 files (1 skipped)`
+    pub fn summary(&self) -> String {
+        format!(
+            "matched {} of {} files ({} skipped)",
+            self.files_matched, self.files_searched, self.files_skipped
+        )
+    }
+}
 
-    println!("The file content: \n{content}\n");
-    println!("=======================================");
-    println!("The search results: \n");
+/// 例如：`3 matches in 2 files (1 skipped)`，有错误时追加错误数量
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural =
+            |n: usize, one: &'static str, many: &'static str| if n == 1 { one } else { many };
+        write!(
+            f,
+            "{} {} in {} {} ({} skipped",
+            self.matched_lines,
+            plural(self.matched_lines, "match", "matches"),
+            self.files_searched,
+            plural(self.files_searched, "file", "files"),
+            self.files_skipped
+        )?;
+        if self.errors > 0 {
+            write!(
+                f,
+                ", {} {}",
+                self.errors,
+                plural(self.errors, "error", "errors")
+            )?;
+        }
+        write!(f, ")")
+    }
+}
 
-    let results = if config.ignore_case {
-        search_case_insensitive_right(&config.query, &content)
+/**
+ * 把所有查询字符串合并成一个正则：`(?:q1)|(?:q2)`，锚定方式转换成 `^` 和 `$`
+ *
+ * 跨行匹配时 `^` 和 `$` 匹配每一行的开头和结尾，而不是整个内容的开头和结尾，
+ * 匹配单词时在两侧加上 `\b`
+ */
+fn compile_regex(
+    queries: &[String],
+    anchor: Anchor,
+    ignore_case: bool,
+    multiline: bool,
+    word: bool,
+) -> Result<Regex, regex::Error> {
+    let alternation = queries
+        .iter()
+        .map(|query| format!("(?:{query})"))
+        .collect::<Vec<_>>()
+        .join("|");
+    let alternation = if word {
+        format!(r"\b(?:{alternation})\b")
     } else {
-        search_right(&config.query, &content)
+        alternation
+    };
+    let pattern = match anchor {
+        Anchor::None => alternation,
+        Anchor::Start => format!("^(?:{alternation})"),
+        Anchor::End => format!("(?:{alternation})$"),
+        Anchor::Exact => format!("^(?:{alternation})$"),
     };
+    // crlf 让跨行匹配时的 `$` 也能匹配 `\r\n` 之前的位置，逐行匹配时 `str::lines` 已经去掉了行尾的 `\r`
+    RegexBuilder::new(&pattern)
+        .case_insensitive(ignore_case)
+        .multi_line(multiline)
+        .crlf(true)
+        .build()
+}
 
-    for line in results {
-        println!("{line}");
+/// 只有一个查询字符串并且区分大小写时才自动分析预过滤的字面量，正则的大小写折叠规则与 `to_lowercase` 不完全相同
+fn auto_prefilter(queries: &[String], ignore_case: bool) -> Option<String> {
+    match queries {
+        [query] if !ignore_case => required_literal(query),
+        _ => None,
     }
+}
 
-    Ok(())
+/**
+ * 展开路径中的通配符，例如 logs 目录下的 `*.txt`，结果按路径排序
+ *
+ * 不包含通配符时返回空列表，按照普通路径处理；包含通配符但没有匹配到任何文件时返回错误，避免静默地什么都不搜索。
+ * 路径本身已经存在时（例如文件名中带有 `[1]`）也按照普通路径处理，不当作通配符
+ */
+#[cfg(feature = "glob")]
+fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>, ConfigError> {
+    if !pattern.contains(['*', '?', '[']) || Path::new(pattern).exists() {
+        return Ok(Vec::new());
+    }
+    let paths = glob::glob(pattern)
+        .map_err(|e| ConfigError::InvalidGlob(pattern.to_string(), e.to_string()))?;
+    // 无法读取的条目直接跳过，与 shell 的行为一致
+    let mut files: Vec<PathBuf> = paths
+        .filter_map(Result::ok)
+        .filter(|p| p.is_file())
+        .collect();
+    if files.is_empty() {
+        return Err(ConfigError::NoMatchingFiles(pattern.to_string()));
+    }
+    files.sort();
+    Ok(files)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// 未开启 `glob` 特性时路径原样使用，通配符由 shell 负责展开
+#[cfg(not(feature = "glob"))]
+fn expand_glob(_pattern: &str) -> Result<Vec<PathBuf>, ConfigError> {
+    Ok(Vec::new())
+}
 
-    #[test]
-    fn fail_result() {
-        let query = "duct";
-        let contents = "\
-Rust:
-safe, fast, productive.
-Pick three.";
+/// 所有单字母选项，其中需要跟一个值的选项见 [`SHORT_FLAGS_WITH_VALUE`]
+const SHORT_FLAGS: &str = "cinqvwhHELUFefmABC";
+const SHORT_FLAGS_WITH_VALUE: &str = "efmABC";
 
-        assert_eq!(vec!["safe, fast, productive."], search(query, contents));
+/**
+ * 将 `-in` 这样的组合短选项拆成 `-i`、`-n`，不是组合短选项时返回 `None`
+ *
+ * 需要跟一个值的选项只能放在组合的最后，例如 `-im 3`，值仍然由解析循环从下一个参数中取得。
+ * 只在选项的位置上调用，已经被前一个选项当作值取走的参数不会经过这里
+ */
+fn split_short_flags(arg: &str) -> Result<Option<Vec<String>>, ConfigError> {
+    let letters = match arg.strip_prefix('-') {
+        Some(letters)
+            if letters.chars().count() > 1 && letters.chars().all(|c| c.is_ascii_alphabetic()) =>
+        {
+            letters
+        }
+        _ => return Ok(None),
+    };
+
+    let last = letters.len() - 1;
+    letters
+        .char_indices()
+        .map(|(i, letter)| {
+            if !SHORT_FLAGS.contains(letter) {
+                return Err(ConfigError::InvalidFlag(format!("-{letter}")));
+            }
+            if i != last && SHORT_FLAGS_WITH_VALUE.contains(letter) {
+                return Err(ConfigError::MissingValue(format!("-{letter}")));
+            }
+            Ok(format!("-{letter}"))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+/**
+ * 按照 shell 的规则把一条命令切分成单词，返回每个单词以及它是否以引号或转义开头
+ *
+ * 单引号中的内容原样保留；双引号中和引号外的 `\` 转义下一个字符。引号没有闭合时返回错误
+ */
+fn shell_words(input: &str) -> Result<Vec<(String, bool)>, ConfigError> {
+    let mut words = Vec::new();
+    // 当前单词的内容和是否以引号开头，还没有开始一个单词时为 None
+    let mut word: Option<(String, bool)> = None;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let (word, _) = word.get_or_insert_with(|| (String::new(), true));
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(ConfigError::UnterminatedQuote(input.to_string())),
+                    }
+                }
+            }
+            '"' => {
+                let (word, _) = word.get_or_insert_with(|| (String::new(), true));
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => word.push(c),
+                            None => return Err(ConfigError::UnterminatedQuote(input.to_string())),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(ConfigError::UnterminatedQuote(input.to_string())),
+                    }
+                }
+            }
+            '\\' => {
+                let (word, _) = word.get_or_insert_with(|| (String::new(), true));
+                // 末尾单独的反斜杠按普通字符处理
+                word.push(chars.next().unwrap_or('\\'));
+            }
+            c => word.get_or_insert_with(|| (String::new(), false)).0.push(c),
+        }
     }
+    words.extend(word);
+    Ok(words)
+}
 
-    #[test]
-    fn right_result() {
-        let query = "rust";
-        let contents = "\
-Rust:
-safe, fast, productive.
-Pick three.";
+/// 文件大小，可以带 `K`、`M`、`G` 后缀（按 1024 进位），例如 `512K`、`2M`
+fn parse_size(value: &str) -> Result<u64, ConfigError> {
+    let (digits, unit) = match value.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&value[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&value[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| ConfigError::InvalidValue(String::from("--max-filesize"), value.to_string()))
+}
 
-        assert_eq!(vec!["Rust:"], search_right(query, contents));
+/// `M:N` 形式的行范围，两端都从 1 开始并且包含在内，`M` 不能大于 `N`
+fn parse_line_range(value: &str) -> Result<(usize, usize), ConfigError> {
+    value
+        .split_once(':')
+        .and_then(|(start, end)| Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?)))
+        .filter(|&(start, end)| start > 0 && start <= end)
+        .ok_or_else(|| ConfigError::InvalidValue(String::from("--lines"), value.to_string()))
+}
+
+fn parse_color(value: &str) -> Result<ColorChoice, ConfigError> {
+    match value {
+        "auto" => Ok(ColorChoice::Auto),
+        "always" => Ok(ColorChoice::Always),
+        "never" => Ok(ColorChoice::Never),
+        _ => Err(ConfigError::InvalidValue(
+            String::from("--color"),
+            value.to_string(),
+        )),
     }
+}
 
-    #[test]
-    fn case_fail_result() {
-        let query = "duct";
-        let contents = "\
-Rust:
-safe, fast, productive.
-Pick three.";
+/**
+ * 处理查询字符串中的转义：`\t`、`\n`、`\r` 和 `\\`
+ *
+ * 在命令行中很难直接输入制表符，不想为此开启正则时可以使用 `--escape`，其它的 `\` 原样保留
+ */
+pub fn interpret_escapes(pattern: &str) -> String {
+    let mut result = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('\\') => result.push('\\'),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
 
-        assert_eq!(
-            vec!["safe, fast, productive."],
-            search_case_insensitive_fail(query, contents)
-        );
+/**
+ * 从文件中读取查询字符串，每行一个，忽略空行和 `#` 开头的注释行
+ */
+fn read_patterns(path: &str) -> Result<Vec<String>, ConfigError> {
+    let content = fs::read_to_string(path).map_err(|source| ConfigError::PatternFile {
+        path: path.to_string(),
+        source,
+    })?;
+    // 行尾多余的空白很难发现，不去掉的话会导致查询字符串永远匹配不上
+    Ok(content
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/**
+ * 搜索过程中可能出现的错误
+ *
+ * 与 `Box<dyn Error>` 不同，调用方可以区分具体的错误类型，例如文件不存在和表达式错误
+ */
+#[derive(thiserror::Error, Debug)]
+pub enum SearchError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("invalid regex: {0}")]
+    Regex(String),
+    #[error("{} is not valid UTF-8", .0.display())]
+    InvalidUtf8(PathBuf),
+    #[error("no input file given")]
+    NoInput,
+    #[error("{} cannot be edited in place: {1}", .0.display())]
+    NotEditable(PathBuf, &'static str),
+    #[error("search panicked")]
+    Panicked,
+}
+
+impl From<regex::Error> for SearchError {
+    fn from(e: regex::Error) -> Self {
+        SearchError::Regex(e.to_string())
     }
+}
 
-    #[test]
-    fn case_right_result() {
-        let query = "rust";
-        let contents = "\
-Rust:
-safe, fast, productive.
-Pick three.";
+/**
+ * 返回值是这次搜索的统计结果，通过 `RunReport::found` 判断是否找到了匹配的内容
+ *
+ * 使用 `--listen` 时作为网络服务一直运行，见 [`remote::serve`]；使用 `--interactive` 时一直读取查询直到标准输入结束，见 [`repl::run`]。
+ * 开启 `--tail` 时搜索完已有的内容后不会返回，一直输出文件中新追加的匹配行，直到出错或者进程被结束
+ */
+pub fn run(mut config: Config) -> Result<RunReport, SearchError> {
+    let stdout = io::stdout();
+    // 只有 run 知道输出的是标准输出，在这里把 Auto 确定下来
+    if config.color == ColorChoice::Auto {
+        config.color = if stdout.is_terminal() {
+            ColorChoice::Always
+        } else {
+            ColorChoice::Never
+        };
+    }
+    if config.interactive {
+        let mut inputs = Vec::new();
+        for path in candidate_files(&config)?.0 {
+            let content = fs::read_to_string(&path)?;
+            inputs.push((path, content));
+        }
+        repl::run(&mut config, &inputs, io::stdin().lock(), &mut stdout.lock())?;
+        return Ok(RunReport::default());
+    }
+    if let Some(addr) = &config.listen {
+        let listener = std::net::TcpListener::bind(addr)?;
+        remote::serve(listener, &config)?;
+        return Ok(RunReport::default());
+    }
 
-        assert_eq!(
-            vec!["Rust:"],
-            search_case_insensitive_right(query, contents)
-        );
+    if !config.follow {
+        return run_with_writer(&config, &mut stdout.lock());
+    }
+
+    // 已有的内容和之后追加的内容从同一个文件句柄读取，第一次搜索和开始跟踪之间追加的行不会遗漏
+    let path = Path::new(&config.file_path);
+    let (mut follower, existing) = follow::Follower::open(path)?;
+    search_loaded(
+        &config,
+        path,
+        &existing,
+        existing.is_empty(),
+        config.with_filename,
+        &mut stdout.lock(),
+    )?;
+    loop {
+        follower.poll(&config, &mut stdout.lock())?;
+        thread::sleep(follow::POLL_INTERVAL);
     }
 }
 
-/// 增加生命周期提示，让编译器知道在函数调用期间这些引用变量是不会出现问题的
-pub fn search<'a>(query: &'a str, content: &'a str) -> Vec<&'a str> {
-    vec![]
+/**
+ * 与 grep 一致的退出码：出错时为 2，否则找到匹配时为 0，没有找到时为 1
+ *
+ * 出错的优先级最高，即使在出错之前已经找到了匹配也返回 2
+ */
+pub fn exit_code(found: bool, error: bool) -> i32 {
+    match (found, error) {
+        (_, true) => 2,
+        (true, false) => 0,
+        (false, false) => 1,
+    }
 }
 
-pub fn search_right<'a>(query: &'a str, content: &'a str) -> Vec<&'a str> {
-    let mut results = Vec::new();
-    // 遍历迭代每一行
-    for line in content.lines() {
-        // 判断是否包含指定的query字符串
-        if line.contains(query) {
-            // 存储搜索内容
-            results.push(line)
+/// 用这个路径表示从标准输入读取，与 grep 等工具的约定一致
+pub const STDIN_PATH: &str = "-";
+
+/**
+ * 与 `run` 相同，但结果写入到指定的 writer 中，方便测试时捕获输出
+ *
+ * 无法判断 writer 是否为终端，所以 `ColorChoice::Auto` 在这里按照不使用颜色处理
+ */
+pub fn run_with_writer<W: Write>(config: &Config, out: &mut W) -> Result<RunReport, SearchError> {
+    if config.file_path.is_empty() && config.files.is_empty() {
+        return Err(SearchError::NoInput);
+    }
+    if config.format == OutputFormat::JsonArray && !config.dry_run {
+        return write_json_array(config, out);
+    }
+    search_inputs(config, out)
+}
+
+/// 收集所有文件的结构化结果，全部搜索完之后输出成一个 JSON 数组，单个文件出错时记录下来继续搜索其它文件
+fn write_json_array<W: Write>(config: &Config, out: &mut W) -> Result<RunReport, SearchError> {
+    let (results, walk_errors) = structured_inputs(config)?;
+    let mut report = RunReport {
+        errors: walk_errors,
+        ..RunReport::default()
+    };
+    let mut items = Vec::new();
+    for (path, result) in results {
+        match result {
+            Ok(file) => {
+                if let Some(file) = &file {
+                    if file.stats.empty && config.warn_empty {
+                        eprintln!("{}: warning: file is empty", path.display());
+                    }
+                    items.extend(json_items(&path, file));
+                }
+                report.record(file.map(|file| file.stats));
+            }
+            Err(e) => {
+                eprintln!("{}: {e}", path.display());
+                report.errors += 1;
+            }
+        }
+    }
+    if !config.quiet {
+        writeln!(
+            out,
+            "{}",
+            output::json_array(items.iter().map(String::as_str))
+        )?;
+    }
+    Ok(report)
+}
+
+/// 与 [`search_inputs`] 相同的输入，返回每个文件的结构化结果，标准输入使用 `-` 作为路径
+///
+/// 同时返回遍历目录时出错的次数，见 [`candidate_files`]
+fn structured_inputs(config: &Config) -> Result<(Vec<(PathBuf, FileResult)>, usize), SearchError> {
+    if config.file_path == STDIN_PATH && config.files.is_empty() {
+        let mut bytes = Vec::new();
+        maybe_gzip(io::stdin().lock())?.read_to_end(&mut bytes)?;
+        let empty = bytes.is_empty();
+        let path = Path::new(STDIN_PATH);
+        return Ok((
+            vec![(path.to_path_buf(), file_hits(config, path, &bytes, empty))],
+            0,
+        ));
+    }
+    let (files, walk_errors) = candidate_files(config)?;
+    Ok((search_structured(&files, config), walk_errors))
+}
+
+/// 按照配置搜索文件、目录或者标准输入，`run_with_writer` 在这之上处理需要整体输出的格式
+fn search_inputs<W: Write>(config: &Config, out: &mut W) -> Result<RunReport, SearchError> {
+    let root = Path::new(&config.file_path);
+
+    if config.dry_run {
+        let (files, walk_errors) = candidate_files(config)?;
+        for path in files {
+            writeln!(out, "{}", path.display())?;
+        }
+        return Ok(RunReport {
+            errors: walk_errors,
+            ..RunReport::default()
+        });
+    }
+    if config.file_path == STDIN_PATH && config.files.is_empty() {
+        return search_reader(config, io::stdin().lock(), out);
+    }
+
+    // 只有单个文件时直接输出行，通配符展开后的多个文件和目录一样，每一行结果都带上文件路径以便区分，
+    // `-h`、`-H` 可以覆盖这个默认行为
+    if config.files.is_empty() && !root.is_dir() {
+        let mut report = RunReport::default();
+        report.record(search_file(config, root, config.with_filename, out)?);
+        return Ok(report);
+    }
+    match config.threads {
+        // `--nth` 需要按照文件的顺序计数，只能逐个搜索
+        Some(threads) if config.files.is_empty() && config.nth.is_none() => {
+            let pool = threadpool::ThreadPool::builder()
+                .min_threads(threads)
+                .max_threads(threads)
+                .with_worker_init(init_read_buffer)
+                .build();
+            search_tree_parallel(config, root, &pool, MAX_IN_FLIGHT_FILES, out)
+        }
+        _ => {
+            let (files, walk_errors) = candidate_files(config)?;
+            let mut report = search_files(config, &files, out);
+            report.errors += walk_errors;
+            Ok(report)
         }
     }
-    results
 }
 
 /**
- * 失败用例
+ * 把匹配的行逐个发送到有界通道中，不直接输出
+ *
+ * 通道满时 `send` 会阻塞，消费者处理得慢时搜索也会跟着慢下来，内存中最多只缓存一个文件的匹配和通道容量个匹配。
+ * 接收端被丢弃时说明不再需要更多的结果，直接结束搜索。单个文件出错时记录下来继续搜索其它文件。
+ *
+ * 每个文件按照 [`search_structured`] 的规则读取和搜索；有匹配的二进制文件只计入统计，没有可以发送的行
  */
-pub fn search_case_insensitive_fail<'a>(query: &'a str, content: &'a str) -> Vec<&'a str> {
-    vec![]
+pub fn run_to_channel(
+    config: &Config,
+    tx: SyncSender<search_async::Match>,
+) -> Result<RunReport, SearchError> {
+    let (files, walk_errors) = candidate_files(config)?;
+    let mut report = RunReport {
+        errors: walk_errors,
+        ..RunReport::default()
+    };
+    for path in files {
+        let file = match structured_file(config, &path) {
+            Ok(Some(file)) => file,
+            Ok(None) => {
+                report.record(None);
+                continue;
+            }
+            Err(e) => {
+                eprintln!("{}: {e}", path.display());
+                report.errors += 1;
+                continue;
+            }
+        };
+        report.record(Some(file.stats));
+        for hit in file.hits {
+            let item = search_async::Match {
+                path: hit.path,
+                line_number: hit.line,
+                line: hit.text,
+            };
+            if tx.send(item).is_err() {
+                return Ok(report);
+            }
+        }
+    }
+    Ok(report)
 }
 
 /**
- * 成功的用例
+ * 列出需要搜索的文件，只遍历目录，不读取文件内容
+ *
+ * 依次使用通配符展开的结果、递归遍历目录的结果，都没有时就是 `file_path` 本身。
+ * 同时返回遍历时出错的次数，这些错误已经输出到标准错误，调用者只需要计入 [`RunReport::errors`]
  */
-pub fn search_case_insensitive_right<'a>(query: &'a str, content: &'a str) -> Vec<&'a str> {
-    let mut results = Vec::new();
-    // 遍历迭代每一行
-    for line in content.lines() {
-        // 判断是否包含指定的query字符串
-        if line.to_lowercase().contains(&query.to_lowercase()) {
-            // 存储搜索内容
-            results.push(line)
+fn candidate_files(config: &Config) -> Result<(Vec<PathBuf>, usize), SearchError> {
+    let root = Path::new(&config.file_path);
+    if !config.files.is_empty() {
+        Ok((config.files.clone(), 0))
+    } else if root.is_dir() {
+        Ok(walk::collect_files(root, config))
+    } else {
+        Ok((vec![root.to_path_buf()], 0))
+    }
+}
+
+/// 依次搜索多个文件，单个文件出错时记录下来继续搜索其它文件，与 grep 的行为一致
+fn search_files<W: Write>(config: &Config, files: &[PathBuf], out: &mut W) -> RunReport {
+    if let Some(nth) = config.nth {
+        return search_files_nth(config, files, nth, out);
+    }
+    let mut results = FileResults::new(config);
+    for path in files {
+        let mut buffer = Vec::new();
+        // 有匹配时才需要标题，标题模式下先输出到缓冲区，其它情况直接输出
+        let result = if results.heading {
+            search_listed_file(config, path, &mut buffer)
+        } else {
+            search_listed_file(config, path, out)
+        };
+        results.finish(path, &buffer, result, out);
+        // 安静模式只关心有没有匹配，找到第一个就可以结束
+        if results.report.found() && config.quiet {
+            break;
         }
     }
-    results
+    results.into_report(out)
 }
 
-pub mod threadpool;
+/**
+ * `--nth` 跨文件计数：依次数出每个文件中匹配的行数，直到第 n 个匹配所在的文件，只输出其中的那一行
+ *
+ * 找到之后不再搜索后面的文件。前面的文件只计数不输出，统计中它们的匹配行数为 0，没有输出时退出码与没有匹配相同
+ */
+fn search_files_nth<W: Write>(
+    config: &Config,
+    files: &[PathBuf],
+    nth: usize,
+    out: &mut W,
+) -> RunReport {
+    let mut results = FileResults::new(config);
+    // 每个文件先选出第 n 个再按照 `max_count` 截断，计数时两者都不能生效
+    let counting = Config {
+        nth: None,
+        max_count: None,
+        ..config.clone()
+    };
+    let mut remaining = nth;
+    for path in files {
+        match structured_file(&counting, path) {
+            Ok(Some(file)) if file.hits.len() >= remaining => {
+                let target = Config {
+                    nth: Some(remaining),
+                    ..config.clone()
+                };
+                let mut buffer = Vec::new();
+                let result = search_listed_file(&target, path, &mut buffer);
+                results.finish(path, &buffer, result, out);
+                break;
+            }
+            Ok(file) => {
+                let outcome = file.map(|file| {
+                    remaining -= file.hits.len();
+                    FileStats {
+                        matched_lines: 0,
+                        ..file.stats
+                    }
+                });
+                results.finish(path, &[], Ok(outcome), out);
+            }
+            Err(e) => results.finish(path, &[], Err(e), out),
+        }
+    }
+    results.into_report(out)
+}
+
+/// 搜索文件列表中的一个文件，按文件统计时丢弃每一行的输出，标题模式下行前不带路径
+fn search_listed_file<W: Write>(
+    config: &Config,
+    path: &Path,
+    out: &mut W,
+) -> Result<Option<FileStats>, SearchError> {
+    if config.count_by_file {
+        search_file(config, path, true, &mut io::sink())
+    } else {
+        search_file(
+            config,
+            path,
+            !config.no_filename && !uses_heading(config),
+            out,
+        )
+    }
+}
+
+/// 按顺序汇总多个文件的搜索结果，[`search_files`] 和 [`search_tree_parallel`] 共用
+struct FileResults<'a> {
+    config: &'a Config,
+    heading: bool,
+    report: RunReport,
+    /// 按文件统计时每个有匹配的文件的匹配行数，所有文件搜索完之后再排序输出
+    per_file: Vec<(PathBuf, usize)>,
+    /// 已经输出过标题的文件数量，从第二个文件开始在标题前空一行
+    headed: usize,
+}
+
+impl<'a> FileResults<'a> {
+    fn new(config: &'a Config) -> Self {
+        FileResults {
+            config,
+            heading: uses_heading(config),
+            report: RunReport::default(),
+            per_file: Vec::new(),
+            headed: 0,
+        }
+    }
+
+    /// 记录一个文件的结果，`buffer` 是这个文件还没有写出的输出，标题模式下先输出标题
+    fn finish<W: Write>(
+        &mut self,
+        path: &Path,
+        buffer: &[u8],
+        result: Result<Option<FileStats>, SearchError>,
+        out: &mut W,
+    ) {
+        let result = result.and_then(|outcome| {
+            if self.heading && !buffer.is_empty() {
+                write_heading_group(out, path, buffer, self.headed > 0)?;
+                self.headed += 1;
+            } else {
+                out.write_all(buffer)?;
+            }
+            Ok(outcome)
+        });
+        match result {
+            Ok(outcome) => {
+                if let Some(stats) = &outcome {
+                    if stats.matched_lines > 0 {
+                        self.per_file
+                            .push((path.to_path_buf(), stats.matched_lines));
+                    }
+                }
+                self.report.record(outcome);
+            }
+            Err(e) => {
+                eprintln!("{}: {e}", path.display());
+                self.report.errors += 1;
+            }
+        }
+    }
+
+    /// 所有文件都记录完之后输出按文件的统计，返回汇总的结果
+    fn into_report<W: Write>(mut self, out: &mut W) -> RunReport {
+        if self.config.count_by_file && !self.config.quiet {
+            if let Err(e) = write_count_by_file(out, self.per_file) {
+                eprintln!("{e}");
+                self.report.errors += 1;
+            }
+        }
+        self.report
+    }
+}
+
+/// 标题只用于逐行输出匹配的情况，计数、文件列表、JSON 等本来就是每个文件一行的输出不受影响
+fn uses_heading(config: &Config) -> bool {
+    config.heading
+        && !config.no_filename
+        && config.format == OutputFormat::Plain
+        && !config.count
+        && !config.count_occurrences
+        && !config.files_without_match
+        && !config.match_path
+}
+
+/// 输出一个文件的标题和缩进的匹配行，`separate` 为 true 时先输出一个空行与上一个文件分开
+fn write_heading_group<W: Write>(
+    out: &mut W,
+    path: &Path,
+    buffer: &[u8],
+    separate: bool,
+) -> io::Result<()> {
+    if separate {
+        writeln!(out)?;
+    }
+    writeln!(out, "{}", path.display())?;
+    // 按原始字节输出，不经过 UTF-8 转换，非 UTF-8 的内容和行尾的 `\r` 都原样保留
+    for line in buffer.split_inclusive(|&b| b == b'\n') {
+        out.write_all(b"  ")?;
+        out.write_all(line)?;
+    }
+    if !buffer.is_empty() && !buffer.ends_with(b"\n") {
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// 按匹配行数从多到少输出 `路径:行数`，行数相同时按路径排序，保证输出稳定
+fn write_count_by_file<W: Write>(
+    out: &mut W,
+    mut per_file: Vec<(PathBuf, usize)>,
+) -> io::Result<()> {
+    per_file.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    for (path, count) in per_file {
+        writeln!(out, "{}:{count}", path.display())?;
+    }
+    Ok(())
+}
+
+/**
+ * 搜索单个文件并输出结果
+ *
+ * 返回匹配的行数和文件的总行数，文件按照配置被跳过时返回 `None`。
+ * 开启 `match_path` 时路径命中也算作一个匹配，先输出路径再搜索内容
+ */
+fn search_file<W: Write>(
+    config: &Config,
+    path: &Path,
+    with_path: bool,
+    out: &mut W,
+) -> Result<Option<FileStats>, SearchError> {
+    let path_matched = with_path && config.match_path && path_matches(config, path);
+    if path_matched && !config.quiet {
+        writeln!(out, "{}", path.display())?;
+    }
+
+    let stats = search_contents(config, path, with_path, out)?;
+    Ok(match stats {
+        Some(mut stats) => {
+            stats.matched_lines += usize::from(path_matched);
+            Some(stats)
+        }
+        None if path_matched => Some(FileStats {
+            matched_lines: 1,
+            lines_scanned: 0,
+            empty: false,
+        }),
+        None => None,
+    })
+}
+
+/// 文件路径去掉起始目录之后是否命中任意一个查询
+fn path_matches(config: &Config, path: &Path) -> bool {
+    let relative = path.strip_prefix(&config.file_path).unwrap_or(path);
+    let relative = relative.to_string_lossy();
+    match &config.regex {
+        Some(regex) => regex.is_match(&relative),
+        None => config
+            .queries
+            .iter()
+            .any(|query| line_contains(&relative, query, config.ignore_case)),
+    }
+}
+
+/// 只搜索文件的内容，返回值与 [`search_file`] 相同
+fn search_contents<W: Write>(
+    config: &Config,
+    path: &Path,
+    with_path: bool,
+    out: &mut W,
+) -> Result<Option<FileStats>, SearchError> {
+    with_read_buffer(|buffer| match load_file(config, path, buffer)? {
+        Some(empty) => search_loaded(config, path, buffer, empty, with_path, out),
+        None => Ok(None),
+    })
+}
+
+/**
+ * 按照配置把一个文件读入 `buffer`（先清空），解压之后留在其中，返回原始文件是否为 0 字节
+ *
+ * 超过 `--max-filesize` 的文件不读取，返回 `None`；需要写回时拒绝符号链接和压缩文件
+ */
+fn load_file(
+    config: &Config,
+    path: &Path,
+    buffer: &mut Vec<u8>,
+) -> Result<Option<bool>, SearchError> {
+    // 读取之前先检查大小，巨大的文件不需要读入内存
+    if let Some(max_filesize) = config.max_filesize {
+        if fs::metadata(path)?.len() > max_filesize {
+            return Ok(None);
+        }
+    }
+    // 写回时只能写出解压之后的内容，并且 rename 会把符号链接替换成普通文件，这两种情况都不修改
+    if config.in_place && fs::symlink_metadata(path)?.file_type().is_symlink() {
+        return Err(SearchError::NotEditable(
+            path.to_path_buf(),
+            "it is a symlink",
+        ));
+    }
+    buffer.clear();
+    fs::File::open(path)?.read_to_end(buffer)?;
+    if config.in_place && buffer.starts_with(&GZIP_MAGIC) {
+        return Err(SearchError::NotEditable(
+            path.to_path_buf(),
+            "it is compressed",
+        ));
+    }
+    let empty = buffer.is_empty();
+    *buffer = decompress(mem::take(buffer))?;
+    Ok(Some(empty))
+}
+
+/**
+ * 搜索标准输入，输出的格式与搜索单个文件相同，`-H` 时使用 `-` 作为路径
+ *
+ * 开启 `gzip` 特性时根据开头的魔数自动解压，`minigrep - pattern < app.log.gz` 不需要再经过 `zcat`
+ */
+pub fn search_reader<R: Read, W: Write>(
+    config: &Config,
+    reader: R,
+    out: &mut W,
+) -> Result<RunReport, SearchError> {
+    let mut bytes = Vec::new();
+    maybe_gzip(reader)?.read_to_end(&mut bytes)?;
+    let empty = bytes.is_empty();
+    let mut report = RunReport::default();
+    let path = Path::new(STDIN_PATH);
+    report.record(search_loaded(
+        config,
+        path,
+        &bytes,
+        empty,
+        config.with_filename,
+        out,
+    )?);
+    Ok(report)
+}
+
+/// 搜索已经读入内存（并且已经解压）的内容，`empty` 表示原始的输入是否为 0 字节
+fn search_loaded<W: Write>(
+    config: &Config,
+    path: &Path,
+    bytes: &[u8],
+    empty: bool,
+    with_path: bool,
+    out: &mut W,
+) -> Result<Option<FileStats>, SearchError> {
+    // 空文件照常搜索，只是可以提示一下，通常说明文件还没有写入或者生成失败
+    if empty && config.warn_empty {
+        eprintln!("{}: warning: file is empty", path.display());
+    }
+
+    // JSON 的每个对象都来自结构化的结果，二进制文件也序列化成对象，不输出文本的提示
+    if config.format != OutputFormat::Plain {
+        let file = file_hits(config, path, bytes, empty)?;
+        if let (Some(file), false) = (&file, config.quiet) {
+            for item in json_items(path, file) {
+                writeln!(out, "{item}")?;
+            }
+        }
+        return Ok(file.map(|file| file.stats));
+    }
+
+    // 二进制文件直接打印会让终端乱码，按照配置的策略跳过或者只提示命中
+    if is_probably_binary(bytes) {
+        if config.binary == BinaryPolicy::Skip {
+            return Ok(None);
+        }
+        let content = String::from_utf8_lossy(bytes);
+        let matched = has_match(config, &content);
+        if !config.quiet {
+            if config.files_without_match {
+                if !matched {
+                    writeln!(out, "{}", path.display())?;
+                }
+            } else if matched {
+                writeln!(out, "Binary file {} matches", path.display())?;
+            }
+        }
+        return Ok(Some(FileStats {
+            matched_lines: usize::from(matched),
+            lines_scanned: content.lines().count(),
+            empty,
+        }));
+    }
+
+    let content =
+        str::from_utf8(bytes).map_err(|_| SearchError::InvalidUtf8(path.to_path_buf()))?;
+    let lines_scanned = content.lines().count();
+
+    if let (true, Some(replacement)) = (config.in_place, &config.replace) {
+        let (replaced, changed) = replace_content(config, content, replacement);
+        if changed > 0 {
+            write_in_place(path, &replaced, config.backup)?;
+        }
+        return Ok(Some(FileStats {
+            matched_lines: changed,
+            lines_scanned,
+            empty,
+        }));
+    }
+
+    // 需要完整扫描整个文件，不能在第一个匹配处停止，统计结果中的匹配行数才是准确的
+    if config.files_without_match {
+        let matched_lines = if config.multiline {
+            search_multiline(config, content).len()
+        } else {
+            matching_lines(config, content).count()
+        };
+        if matched_lines == 0 && !config.quiet {
+            writeln!(out, "{}", path.display())?;
+        }
+        return Ok(Some(FileStats {
+            matched_lines,
+            lines_scanned,
+            empty,
+        }));
+    }
+
+    if config.multiline {
+        let matches = selected_lines(config, content);
+        if (config.count || config.count_occurrences) && !config.quiet {
+            write_count(out, path, with_path, matches.len())?;
+        } else if !config.quiet {
+            for (line_number, text) in &matches {
+                if with_path {
+                    writeln!(out, "{}:{line_number}:{text}", path.display())?;
+                } else {
+                    writeln!(out, "{line_number}:{text}")?;
+                }
+            }
+        }
+        return Ok(Some(FileStats {
+            matched_lines: matches.len(),
+            lines_scanned,
+            empty,
+        }));
+    }
+
+    if config.quiet {
+        return Ok(Some(FileStats {
+            matched_lines: usize::from(has_match(config, content)),
+            lines_scanned,
+            empty,
+        }));
+    }
+
+    if config.count || config.count_occurrences {
+        let lines = search_lines(config, content);
+        let matched_lines = lines.len();
+        let count = if config.count_occurrences {
+            count_occurrences(config, &lines)
+        } else {
+            matched_lines
+        };
+        write_count(out, path, with_path, count)?;
+        return Ok(Some(FileStats {
+            matched_lines,
+            lines_scanned,
+            empty,
+        }));
+    }
+
+    if config.before_context > 0 || config.after_context > 0 {
+        let lines: Vec<&str> = content.lines().collect();
+        // 与普通输出一样先应用 -m、--nth 和去重，再围绕剩下的匹配行收集上下文
+        let matches: Vec<usize> = search_lines(config, content)
+            .into_iter()
+            .map(|(line_number, _)| line_number)
+            .collect();
+        let groups: Vec<_> = matches
+            .iter()
+            .map(|&line_number| {
+                context_group(
+                    &lines,
+                    line_number - 1,
+                    config.before_context,
+                    config.after_context,
+                )
+            })
+            .collect();
+        let display = path.display().to_string();
+        let format = output::ContextFormat {
+            separator: config
+                .group_separator
+                .as_deref()
+                .unwrap_or(output::GROUP_SEPARATOR),
+            line_number: config.line_number,
+            path: with_path.then_some(display.as_str()),
+        };
+        out.write_all(output::format_context_output_with(groups, &matches, &format).as_bytes())?;
+        return Ok(Some(FileStats {
+            matched_lines: matches.len(),
+            lines_scanned,
+            empty,
+        }));
+    }
+
+    let results = search_lines(config, content);
+
+    // 只有需要输出标题行时才需要按下标访问每一行
+    let lines: Vec<&str> = match config.context_header {
+        Some(_) => content.lines().collect(),
+        None => Vec::new(),
+    };
+    // 同一个标题下的多个匹配只输出一次标题
+    let mut last_header = None;
+    let color = config.color.enabled(false);
+    for (line_number, line) in &results {
+        if let Some(pattern) = &config.context_header {
+            let index = line_number - 1;
+            let header = if pattern.is_match(line) {
+                // 匹配的行本身就是标题行，不需要再单独输出
+                last_header = Some(index);
+                None
+            } else {
+                enclosing_header(&lines, index, pattern)
+                    .filter(|&header| last_header != Some(header))
+            };
+            if let Some(header) = header {
+                last_header = Some(header);
+                let mut text = lines[header].to_string();
+                if config.line_number {
+                    // 与 grep 的上下文行一致，行号后面使用 `-` 区分于匹配行
+                    text = format!("{}-{text}", header + 1);
+                }
+                if with_path {
+                    writeln!(out, "{}:{text}", path.display())?;
+                } else {
+                    writeln!(out, "{text}")?;
+                }
+            }
+        }
+        let mut line = if let Some(replacement) = &config.replace {
+            replace_matches(config, line, replacement)
+        } else if color {
+            highlight_line(config, line)
+        } else {
+            line.to_string()
+        };
+        if let Some(width) = config.max_line_width {
+            line = output::truncate_line(&line, width);
+        }
+        if config.line_number {
+            line = format!("{line_number}:{line}");
+        }
+        if with_path {
+            writeln!(out, "{}:{line}", path.display())?;
+        } else {
+            writeln!(out, "{line}")?;
+        }
+    }
+
+    Ok(Some(FileStats {
+        matched_lines: results.len(),
+        lines_scanned,
+        empty,
+    }))
+}
+
+/**
+ * 从第 `index` 行（从 0 开始）向前查找最近的标题行，返回它的下标
+ *
+ * 只根据正则判断，不解析语法，嵌套的函数或者字符串中的 `fn` 都可能被误认为标题
+ */
+pub fn enclosing_header(lines: &[&str], index: usize, pattern: &Regex) -> Option<usize> {
+    (0..index.min(lines.len()))
+        .rev()
+        .find(|&i| pattern.is_match(lines[i]))
+}
+
+/**
+ * 跨行匹配，返回每个匹配开始的行号（从 1 开始）以及匹配涉及的完整的行
+ *
+ * 直接在整个内容上匹配，不先按 `\n` 切分，所以查询中可以包含换行符
+ */
+pub fn search_multiline<'a>(config: &Config, content: &'a str) -> Vec<(usize, &'a str)> {
+    let fallback;
+    let regex = match &config.regex {
+        Some(regex) => regex,
+        None => {
+            let escaped: Vec<String> = config
+                .queries
+                .iter()
+                .map(|query| regex::escape(query))
+                .collect();
+            fallback = compile_regex(
+                &escaped,
+                config.anchor,
+                config.ignore_case,
+                true,
+                config.word,
+            )
+            .expect("escaped queries are always a valid regex");
+            &fallback
+        }
+    };
+
+    let mut matches = Vec::new();
+    // 记录已经数过的位置和行号，避免每个匹配都从头数一遍换行符
+    let mut counted = 0;
+    let mut line_number = 1;
+    for m in regex.find_iter(content) {
+        // 扩展到匹配开始所在行的行首，以及匹配结束所在行的行尾
+        let start = content[..m.start()].rfind('\n').map_or(0, |i| i + 1);
+        let end = if m.end() > m.start() && content[..m.end()].ends_with('\n') {
+            m.end() - 1
+        } else {
+            content[m.end()..]
+                .find('\n')
+                .map_or(content.len(), |i| m.end() + i)
+        };
+        line_number += content[counted..m.start()].matches('\n').count();
+        counted = m.start();
+        // 跨行的匹配按照它开始的那一行判断是否在范围内
+        if !config.in_line_range(line_number) {
+            continue;
+        }
+        matches.push((line_number, content[start..end].trim_end_matches('\r')));
+    }
+    matches
+}
+
+/**
+ * 把一行中所有匹配的部分替换成 `replacement`
+ *
+ * 替换的范围来自配置的匹配器，与搜索时一样遵循 `-w`、锚定、反向匹配和空白规范化等选项；
+ * 普通查询字符串在同一位置有多个命中时替换最长的那个。使用正则时替换字符串可以引用分组
+ */
+pub fn replace_matches(config: &Config, line: &str, replacement: &str) -> String {
+    replace_spans(config, config.matcher().as_ref(), line, replacement)
+}
+
+/// 按照 `matcher` 给出的范围替换，正则的每个匹配单独展开替换字符串中引用的分组
+fn replace_spans(config: &Config, matcher: &dyn Matcher, line: &str, replacement: &str) -> String {
+    let mut replaced = String::with_capacity(line.len());
+    let mut last = 0;
+    for (start, end) in matcher.find_spans(line) {
+        replaced.push_str(&line[last..start]);
+        let captures = config
+            .regex
+            .as_ref()
+            .and_then(|regex| regex.captures_at(line, start))
+            .filter(|captures| captures.get(0).is_some_and(|m| m.range() == (start..end)));
+        match captures {
+            Some(captures) => captures.expand(replacement, &mut replaced),
+            None => replaced.push_str(replacement),
+        }
+        last = end;
+    }
+    replaced.push_str(&line[last..]);
+    replaced
+}
+
+/**
+ * 列出替换字符串中引用的分组，规则与 `Regex::replace_all` 相同
+ *
+ * `$1`、`$name` 取最长的一串字母、数字和下划线，`${name}` 用花括号明确边界，`$$` 表示 `$` 本身
+ */
+fn replacement_groups(replacement: &str) -> Vec<&str> {
+    let mut groups = Vec::new();
+    let mut rest = replacement;
+    while let Some(i) = rest.find('$') {
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            rest = after;
+        } else if let Some((name, after)) = rest
+            .strip_prefix('{')
+            .and_then(|braced| braced.split_once('}'))
+        {
+            groups.push(name);
+            rest = after;
+        } else {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            if len > 0 {
+                groups.push(&rest[..len]);
+            }
+            rest = &rest[len..];
+        }
+    }
+    groups
+}
+
+/// 替换字符串中引用的分组必须存在于正则中，按编号引用时 `$0` 表示整个匹配
+fn check_replacement(regex: &Regex, replacement: &str) -> Result<(), ConfigError> {
+    for group in replacement_groups(replacement) {
+        let exists = match group.parse::<usize>() {
+            Ok(index) => index < regex.captures_len(),
+            Err(_) => regex.capture_names().flatten().any(|name| name == group),
+        };
+        if !exists {
+            return Err(ConfigError::UnknownCaptureGroup(group.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// 逐行替换整个文件的内容，保留原来的换行符，只替换 `--lines` 范围内的行，返回替换后的内容和发生变化的行数
+fn replace_content(config: &Config, content: &str, replacement: &str) -> (String, usize) {
+    let matcher = config.matcher();
+    let mut replaced = String::with_capacity(content.len());
+    let mut changed = 0;
+    for (i, line) in content.split_inclusive('\n').enumerate() {
+        let body = line.trim_end_matches(['\n', '\r']);
+        if !config.in_line_range(i + 1) {
+            replaced.push_str(line);
+            continue;
+        }
+        let new_body = replace_spans(config, matcher.as_ref(), body, replacement);
+        if new_body != body {
+            changed += 1;
+        }
+        replaced.push_str(&new_body);
+        replaced.push_str(&line[body.len()..]);
+    }
+    (replaced, changed)
+}
+
+/**
+ * 把内容写回文件
+ *
+ * 先写入同一目录下的临时文件，再通过 rename 替换原文件。rename 在同一个文件系统内是原子的，
+ * 即使写入过程中程序崩溃，原文件也不会只剩下一半的内容。写入失败时删除临时文件
+ */
+fn write_in_place(path: &Path, content: &str, backup: bool) -> io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    if backup {
+        fs::copy(path, path.with_file_name(format!("{file_name}.bak")))?;
+    }
+
+    let (tmp, mut file) = create_temp_file(path, &file_name)?;
+    let written = (|| {
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        // 保留原文件的权限，例如可执行脚本
+        fs::set_permissions(&tmp, fs::metadata(path)?.permissions())?;
+        fs::rename(&tmp, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    written
+}
+
+/**
+ * 在 `path` 所在的目录中新建一个临时文件，文件名中带有进程 id 和序号
+ *
+ * 使用 `create_new` 打开（即 `O_EXCL`），同名的文件或者符号链接已经存在时不会打开它，而是换一个序号重试
+ */
+fn create_temp_file(path: &Path, file_name: &str) -> io::Result<(PathBuf, fs::File)> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_file_name(format!(".{file_name}.{}.{n}.tmp", std::process::id()));
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp)
+        {
+            Ok(file) => return Ok((tmp, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// 计数模式的输出，递归搜索时每个文件一行 `路径:行数`
+fn write_count<W: Write>(
+    out: &mut W,
+    path: &Path,
+    with_path: bool,
+    count: usize,
+) -> io::Result<()> {
+    if with_path {
+        writeln!(out, "{}:{count}", path.display())
+    } else {
+        writeln!(out, "{count}")
+    }
+}
+
+/// 只判断是否存在匹配，借助惰性迭代器找到第一个匹配就停止
+fn has_match(config: &Config, content: &str) -> bool {
+    matching_lines(config, content).next().is_some()
+}
+
+/// 统计匹配行中一共出现了多少次，出现的位置与替换时一样由配置的匹配器给出
+fn count_occurrences(config: &Config, lines: &[(usize, &str)]) -> usize {
+    let matcher = config.matcher();
+    lines
+        .iter()
+        .map(|(_, line)| matcher.find_spans(line).len())
+        .sum()
+}
+
+/**
+ * 统一的搜索入口：根据 `Config` 中的各个选项选择对应的实现，返回匹配的行
+ *
+ * 调用方不需要关心应该使用 `search_right`、`search_case_insensitive_right` 还是正则等具体实现。
+ * 计数模式下只返回一个元素，即匹配的行数（反向匹配时是不匹配的行数）；`--count-matches` 时是出现的总次数
+ */
+pub fn execute_search(config: &Config, content: &str) -> Vec<String> {
+    let results: Vec<String> = if config.multiline {
+        let limit = config.max_count.unwrap_or(usize::MAX);
+        search_multiline(config, content)
+            .into_iter()
+            .take(limit)
+            .map(|(_, text)| text.to_string())
+            .collect()
+    } else if config.count_occurrences {
+        let lines = search_lines(config, content);
+        return vec![count_occurrences(config, &lines).to_string()];
+    } else {
+        search_lines(config, content)
+            .into_iter()
+            .map(|(_, line)| line.to_string())
+            .collect()
+    };
+
+    if config.count || config.count_occurrences {
+        return vec![results.len().to_string()];
+    }
+    results
+}
+
+/**
+ * 一个结构化的匹配结果，编辑器等程序化的调用方使用它，不需要解析命令行的输出
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHit {
+    pub path: PathBuf,
+    /// 行号，从 1 开始
+    pub line: usize,
+    /// 第一个匹配在行中的列号，按字符计算，从 1 开始
+    pub column: usize,
+    pub text: String,
+}
+
+impl SearchHit {
+    fn new(config: &Config, path: &Path, line: usize, text: &str) -> SearchHit {
+        SearchHit {
+            path: path.to_path_buf(),
+            line,
+            column: first_match_column(config, text),
+            text: text.to_string(),
+        }
+    }
+}
+
+/// 一个文件的结构化搜索结果
+#[derive(Debug)]
+pub struct FileHits {
+    /// 按行号排列的匹配，二进制文件没有
+    pub hits: Vec<SearchHit>,
+    /// 二进制文件中有匹配，这时只知道命中了，不会给出具体的行
+    pub binary_match: bool,
+    stats: FileStats,
+}
+
+/// 一个文件的搜索结果，`None` 表示文件按照配置被跳过
+pub type FileResult = Result<Option<FileHits>, SearchError>;
+
+/**
+ * 依次搜索每个文件，返回每个文件的结构化结果，顺序与 `paths` 相同
+ *
+ * 读取文件的规则与 [`run`] 一致：超过 `--max-filesize` 的文件和按照配置跳过的二进制文件得到 `None`，
+ * 压缩文件先解压，`--multiline` 时跨行匹配。单个文件出错不影响其它文件，错误和路径一起返回，由调用方决定如何报告。
+ * 行号和列号的计算与命令行输出（包括 `--json`）一致
+ */
+pub fn search_structured(paths: &[PathBuf], config: &Config) -> Vec<(PathBuf, FileResult)> {
+    paths
+        .iter()
+        .map(|path| (path.clone(), structured_file(config, path)))
+        .collect()
+}
+
+/// 按照 [`search_structured`] 的规则读取并搜索一个文件
+fn structured_file(config: &Config, path: &Path) -> FileResult {
+    with_read_buffer(|buffer| match load_file(config, path, buffer)? {
+        Some(empty) => file_hits(config, path, buffer, empty),
+        None => Ok(None),
+    })
+}
+
+/// 在已经读入内存（并且已经解压）的内容中搜索，返回结构化的结果，二进制文件按照 `--binary` 的策略处理
+fn file_hits(
+    config: &Config,
+    path: &Path,
+    bytes: &[u8],
+    empty: bool,
+) -> Result<Option<FileHits>, SearchError> {
+    if is_probably_binary(bytes) {
+        if config.binary == BinaryPolicy::Skip {
+            return Ok(None);
+        }
+        let content = String::from_utf8_lossy(bytes);
+        let matched = has_match(config, &content);
+        return Ok(Some(FileHits {
+            hits: Vec::new(),
+            binary_match: matched,
+            stats: FileStats {
+                matched_lines: usize::from(matched),
+                lines_scanned: content.lines().count(),
+                empty,
+            },
+        }));
+    }
+
+    let content =
+        str::from_utf8(bytes).map_err(|_| SearchError::InvalidUtf8(path.to_path_buf()))?;
+    let hits: Vec<SearchHit> = selected_lines(config, content)
+        .into_iter()
+        .map(|(line, text)| SearchHit::new(config, path, line, text))
+        .collect();
+    Ok(Some(FileHits {
+        stats: FileStats {
+            matched_lines: hits.len(),
+            lines_scanned: content.lines().count(),
+            empty,
+        },
+        hits,
+        binary_match: false,
+    }))
+}
+
+/// 一个文件的结果序列化成的 JSON 对象，有匹配的二进制文件只有一个 `{"path":…,"binary":true}`
+fn json_items(path: &Path, file: &FileHits) -> Vec<String> {
+    if file.binary_match {
+        return vec![output::json_binary_match(&path.to_string_lossy())];
+    }
+    file.hits
+        .iter()
+        .map(|hit| output::json_match(&hit.path.to_string_lossy(), hit.line, hit.column, &hit.text))
+        .collect()
+}
+
+/// 按照是否跨行匹配选出匹配的行，跨行匹配时同样按照 `max_count` 截断
+fn selected_lines<'a>(config: &'a Config, content: &'a str) -> Vec<(usize, &'a str)> {
+    if !config.multiline {
+        return search_lines(config, content);
+    }
+    let mut matches = search_multiline(config, content);
+    if let Some(max_count) = config.max_count {
+        matches.truncate(max_count);
+    }
+    matches
+}
+
+/**
+ * 逐行搜索并返回 `(行号, 行内容)`，`run` 和 `execute_search` 共用
+ *
+ * 处理顺序：先匹配（包括反向匹配），再去掉重复的行，然后按照 `nth` 选出一个，最后按照 `max_count` 截断
+ */
+fn search_lines<'a>(config: &'a Config, content: &'a str) -> Vec<(usize, &'a str)> {
+    let dedupe = config.unique_consecutive || config.unique_lines;
+    if let (Some(nth), false) = (config.nth, dedupe) {
+        // 不需要去重时直接在惰性迭代器上取第 n 个，找到之后就不再扫描后面的内容
+        return matching_lines(config, content)
+            .nth(nth - 1)
+            .into_iter()
+            .collect();
+    }
+
+    let mut results: Vec<(usize, &str)> = matching_lines(config, content).collect();
+    if config.unique_consecutive {
+        results.dedup_by(|a, b| a.1 == b.1);
+    }
+    if config.unique_lines {
+        let mut seen = HashSet::new();
+        results.retain(|(_, line)| seen.insert(*line));
+    }
+    if let Some(nth) = config.nth {
+        results = results.into_iter().nth(nth - 1).into_iter().collect();
+    }
+    if let Some(max_count) = config.max_count {
+        results.truncate(max_count);
+    }
+    results
+}
+
+/**
+ * 按照配置的锚定方式和大小写规则，惰性地返回 `(行号, 行内容)`，行号从 1 开始
+ *
+ * 配置了多个查询字符串时，任意一个命中即可；反向匹配时返回的是不匹配的行。
+ * 配置了 `--lines` 时只考虑范围内的行
+ */
+fn matching_lines<'a>(
+    config: &'a Config,
+    content: &'a str,
+) -> impl Iterator<Item = (usize, &'a str)> + 'a {
+    let matcher = config.matcher();
+    // 只扫描范围内的行，超出范围的结尾部分不再读取
+    let (skip, take) = match config.line_range {
+        Some((start, end)) => (start - 1, end - start + 1),
+        None => (0, usize::MAX),
+    };
+    content
+        .lines()
+        .enumerate()
+        .skip(skip)
+        .take(take)
+        .filter(move |(_, line)| matcher.is_match(line))
+        .map(|(i, line)| (i + 1, line))
+}
+
+/**
+ * 去掉行首行尾的空白，并把中间连续的空白（包括制表符）合并成一个空格
+ */
+pub fn normalize_whitespace(line: &str) -> String {
+    line.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// 第一个匹配的列号，范围来自配置的匹配器，没有匹配的部分（例如反向匹配）时为 1
+fn first_match_column(config: &Config, line: &str) -> usize {
+    config
+        .matcher()
+        .find_spans(line)
+        .first()
+        .map_or(1, |&(start, _)| line[..start].chars().count() + 1)
+}
+
+/// 按照配置选择普通字符串或者正则的方式高亮
+fn highlight_line(config: &Config, line: &str) -> String {
+    match &config.regex {
+        Some(regex) => highlight_regex(regex, line),
+        None => highlight_matches(line, &config.queries, config.ignore_case),
+    }
+}
+
+/**
+ * 用 ANSI 转义码包裹行中所有被正则匹配到的部分，其余内容保持不变
+ *
+ * 正则没有固定的查询字符串，只能按照 `find_iter` 给出的位置高亮。空匹配（例如 `x*`）没有可以高亮的内容，直接跳过
+ */
+pub fn highlight_regex(regex: &Regex, line: &str) -> String {
+    let mut highlighted = String::with_capacity(line.len());
+    let mut last = 0;
+    for m in regex.find_iter(line).filter(|m| !m.is_empty()) {
+        highlighted.push_str(&line[last..m.start()]);
+        highlighted.push_str(HIGHLIGHT_START);
+        highlighted.push_str(m.as_str());
+        highlighted.push_str(HIGHLIGHT_END);
+        last = m.end();
+    }
+    highlighted.push_str(&line[last..]);
+    highlighted
+}
+
+/// 高亮使用的 ANSI 转义码：加粗红色，以及恢复默认样式
+const HIGHLIGHT_START: &str = "\x1b[1;31m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+
+/**
+ * 用 ANSI 转义码包裹行中所有命中查询字符串的部分
+ *
+ * 从左到右扫描，同一位置有多个查询字符串命中时取最长的那个，命中的部分之间不会重叠
+ */
+pub fn highlight_matches(line: &str, queries: &[String], ignore_case: bool) -> String {
+    let queries: Vec<String> = queries
+        .iter()
+        .filter(|query| !query.is_empty())
+        .map(|query| {
+            if ignore_case {
+                query.to_lowercase()
+            } else {
+                query.clone()
+            }
+        })
+        .collect();
+
+    let mut highlighted = String::with_capacity(line.len());
+    let mut i = 0;
+    while let Some(c) = line[i..].chars().next() {
+        let len = queries
+            .iter()
+            .filter_map(|query| match_len_at(&line[i..], query, ignore_case))
+            .max();
+        match len {
+            Some(len) => {
+                highlighted.push_str(HIGHLIGHT_START);
+                highlighted.push_str(&line[i..i + len]);
+                highlighted.push_str(HIGHLIGHT_END);
+                i += len;
+            }
+            None => {
+                highlighted.push(c);
+                i += c.len_utf8();
+            }
+        }
+    }
+    highlighted
+}
+
+/// `rest` 以 query 开头时返回命中部分在 `rest` 中的字节长度，忽略大小写时 query 需要已经是小写
+fn match_len_at(rest: &str, query: &str, ignore_case: bool) -> Option<usize> {
+    if !ignore_case {
+        return rest.starts_with(query).then_some(query.len());
+    }
+
+    // 逐个字符转换小写后比较，原文中命中部分的字节长度可能与 query 不同
+    let mut expected = query.chars();
+    for (i, c) in rest.char_indices() {
+        for lower in c.to_lowercase() {
+            if expected.next() != Some(lower) {
+                return None;
+            }
+        }
+        if expected.as_str().is_empty() {
+            return Some(i + c.len_utf8());
+        }
+    }
+    None
+}
+
+/**
+ * 查询字符串在行中第一次出现的列号（按字符计算，从 1 开始）
+ */
+pub fn match_column(line: &str, query: &str, ignore_case: bool) -> Option<usize> {
+    if !ignore_case {
+        return line.find(query).map(|i| line[..i].chars().count() + 1);
+    }
+
+    // 小写转换可能改变字节长度，所以逐个字符位置比较，而不是在转换后的字符串中查找
+    let query = query.to_lowercase();
+    line.char_indices()
+        .position(|(i, _)| line[i..].to_lowercase().starts_with(&query))
+        .map(|position| position + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        ops::Deref,
+        path::PathBuf,
+        sync::{Mutex, PoisonError},
+        time::Duration,
+    };
+
+    #[test]
+    fn fail_result() {
+        let query = "duct";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.";
+
+        assert_eq!(vec!["safe, fast, productive."], search(query, contents));
+    }
+
+    #[test]
+    fn right_result() {
+        let query = "rust";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.";
+
+        assert_eq!(vec!["Rust:"], search_right(query, contents));
+    }
+
+    #[test]
+    fn case_fail_result() {
+        let query = "duct";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.";
+
+        assert_eq!(
+            vec!["safe, fast, productive."],
+            search_case_insensitive_fail(query, contents)
+        );
+    }
+
+    #[test]
+    fn case_right_result() {
+        let query = "rust";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.";
+
+        assert_eq!(
+            vec!["Rust:"],
+            search_case_insensitive_right(query, contents)
+        );
+    }
+
+    /// 测试用的临时目录，离开作用域时连同其中的文件一起删除
+    pub(crate) struct TempDir(PathBuf);
+
+    impl Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for TempDir {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// 在系统临时目录下为每个测试创建一个干净的目录，返回值被丢弃时目录也会被删除
+    pub(crate) fn temp_dir(name: &str) -> TempDir {
+        let dir = env::temp_dir()
+            .join("ilearn-tests")
+            .join(format!("{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    /// 在参数前面加上程序名，转换成 `Config::build` 接受的形式
+    pub(crate) fn args(args: &[&str]) -> Vec<String> {
+        ["minigrep"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect()
+    }
+
+    /// 按照 `config` 运行一次搜索，返回全部输出
+    fn run_to_string(config: &Config) -> String {
+        let mut out = Vec::new();
+        run_with_writer(config, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    // 等位名单是全局共享的，餐厅相关的测试需要依次执行
+    static RESTAURANT: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn restaurant_order_is_paid() {
+        let _guard = RESTAURANT.lock().unwrap_or_else(PoisonError::into_inner);
+        let receipt = eat_at_restaurant("alice", &[MenuItem::Soup, MenuItem::Steak], 5000).unwrap();
+        assert_eq!(vec![MenuItem::Soup, MenuItem::Steak], receipt.items);
+        assert_eq!(4400, receipt.total);
+        assert_eq!(600, receipt.change);
+        // 入座之后就不在等位名单中了，不能再次入座
+        assert_eq!(None, hosting::seat_at_table("alice"));
+    }
+
+    #[test]
+    fn restaurant_payment_fails() {
+        let _guard = RESTAURANT.lock().unwrap_or_else(PoisonError::into_inner);
+        assert_eq!(
+            Err(RestaurantError::Payment(PaymentError::Insufficient {
+                due: 1200,
+                paid: 1000
+            })),
+            eat_at_restaurant("bob", &[MenuItem::Salad], 1000)
+        );
+        assert_eq!(
+            Err(RestaurantError::Payment(PaymentError::EmptyOrder)),
+            eat_at_restaurant("carol", &[], 1000)
+        );
+    }
+
+    #[test]
+    fn restaurant_waitlist_full() {
+        let _guard = RESTAURANT.lock().unwrap_or_else(PoisonError::into_inner);
+        let waiting: Vec<_> = (0..)
+            .map(|n| format!("waiting-{n}"))
+            .take_while(|guest| hosting::add_to_waitlist(guest).is_some())
+            .collect();
+        assert!(!waiting.is_empty());
+
+        assert_eq!(
+            Err(RestaurantError::WaitlistFull),
+            eat_at_restaurant("dave", &[MenuItem::Soup], 1000)
+        );
+        // 没能排上队的客人不会被带到餐桌
+        assert_eq!(None, hosting::seat_at_table("dave"));
+
+        for guest in &waiting {
+            hosting::seat_at_table(guest).unwrap();
+        }
+        assert!(eat_at_restaurant("dave", &[MenuItem::Soup], 1000).is_ok());
+    }
+
+    #[test]
+    fn search_iter_is_lazy() {
+        let contents = "\
+one rust
+two
+three rust
+four rust";
+
+        let mut iter = search_iter("rust", contents, false);
+        let first_two: Vec<_> = iter.by_ref().take(2).collect();
+        assert_eq!(vec!["one rust", "three rust"], first_two);
+        // take(2) 只消费到第二个匹配为止，剩下的匹配仍然留在迭代器中
+        assert_eq!(Some("four rust"), iter.next());
+        assert_eq!(None, iter.next());
+
+        assert_eq!(
+            vec!["Rust:"],
+            search_iter("rust", "Rust:\nsafe", true).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn anchored_search() {
+        let contents = "\
+Rust is fast
+fast is Rust
+rust";
+
+        assert_eq!(
+            vec!["Rust is fast"],
+            search_starts_with("Rust", contents, false)
+        );
+        assert_eq!(
+            vec!["fast is Rust"],
+            search_ends_with("Rust", contents, false)
+        );
+        assert_eq!(
+            vec!["fast is Rust"],
+            search_starts_with("fast", contents, false)
+        );
+        assert_eq!(
+            vec!["Rust is fast"],
+            search_ends_with("fast", contents, false)
+        );
+        assert_eq!(
+            vec!["Rust is fast", "rust"],
+            search_starts_with("RUST", contents, true)
+        );
+        assert_eq!(vec!["rust"], search_exact("rust", contents, false));
+        assert!(search_exact("Rust", contents, false).is_empty());
+        assert_eq!(vec!["rust"], search_exact("Rust", contents, true));
+    }
+
+    #[test]
+    fn dedupe_only_adjacent_lines() {
+        assert_eq!(
+            vec!["a", "b", "a"],
+            dedupe_consecutive(vec!["a", "a", "b", "a"])
+        );
+    }
+
+    #[test]
+    fn parallel_search_matches_sequential() {
+        let content: String = (0..1000)
+            .map(|i| {
+                if i % 7 == 0 {
+                    format!("line {i} has Rust\n")
+                } else {
+                    format!("line {i}\n")
+                }
+            })
+            .collect();
+        let pool = threadpool::ThreadPool::new(4);
+
+        for ignore_case in [false, true] {
+            let expected: Vec<(usize, String)> = content
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| line_contains(line, "rust", ignore_case))
+                .map(|(i, line)| (i + 1, line.to_string()))
+                .collect();
+            assert_eq!(
+                expected,
+                search_content_parallel(&content, "rust", ignore_case, &pool)
+            );
+        }
+        assert_eq!(
+            (1, String::from("line 0 has Rust")),
+            search_content_parallel(&content, "rust", true, &pool)[0]
+        );
+        assert!(search_content_parallel("", "rust", false, &pool).is_empty());
+    }
+
+    #[test]
+    fn parallel_tree_search_matches_sequential() {
+        let root = temp_dir("parallel_tree_search_matches_sequential");
+        let mut dir = root.to_path_buf();
+        for depth in 0..6 {
+            dir = dir.join(format!("level{depth}"));
+            fs::create_dir_all(&dir).unwrap();
+            for i in 0..4 {
+                let content = format!("needle {depth}-{i}\nhaystack\nNEEDLE again\n");
+                fs::write(dir.join(format!("file{i}.txt")), content).unwrap();
+            }
+            fs::write(dir.join("none.txt"), "nothing here\n").unwrap();
+        }
+
+        // 二进制文件、超过大小限制的文件和单线程一样处理
+        fs::write(dir.join("data.bin"), b"needle\0").unwrap();
+        fs::write(dir.join("large.txt"), "needle\n".repeat(100)).unwrap();
+
+        let pool = threadpool::ThreadPool::new(4);
+        for flags in [
+            &["-i"][..],
+            &["-i", "-n", "-C", "1"],
+            &["-i", "--heading"],
+            &["-i", "--count-by-file"],
+        ] {
+            let root_arg = root.to_string_lossy();
+            let mut flags = [&["--max-filesize", "400"], flags].concat();
+            flags.extend([&*root_arg, "needle"]);
+            let config = Config::build(&args(&flags)).unwrap();
+            let mut sequential = Vec::new();
+            let expected = run_with_writer(&config, &mut sequential).unwrap();
+            assert_eq!(25, expected.files_matched);
+
+            // 上限很小时主线程会频繁地等待结果，顺序和内容仍然与顺序搜索相同
+            for max_in_flight in [1, 3, MAX_IN_FLIGHT_FILES] {
+                let mut out = Vec::new();
+                let report =
+                    search_tree_parallel(&config, &root, &pool, max_in_flight, &mut out).unwrap();
+                assert_eq!(
+                    String::from_utf8_lossy(&sequential),
+                    String::from_utf8_lossy(&out)
+                );
+                assert_eq!(expected.files_matched, report.files_matched);
+                assert_eq!(expected.files_skipped, report.files_skipped);
+            }
+        }
+
+        // --threads 让递归搜索走并行的路径
+        let config = Config::build(&args(&[
+            "--threads",
+            "3",
+            "-i",
+            &root.to_string_lossy(),
+            "needle",
+        ]))
+        .unwrap();
+        let report = run_with_writer(&config, &mut Vec::new()).unwrap();
+        assert_eq!(6 * 4 * 2 + 100 + 1, report.matched_lines);
+        assert!(matches!(
+            Config::build(&args(&["--threads", "0", "poem.txt", "to"])),
+            Err(ConfigError::InvalidValue(_, _))
+        ));
+    }
+
+    #[test]
+    fn read_buffer_is_reused_across_files() {
+        let root = temp_dir("read_buffer_is_reused_across_files");
+        // 先读到的文件更大，后面的文件如果读到了上一个文件残留的内容，结果就会多出匹配的行
+        let big: String = (0..500).map(|i| format!("needle {i}\n")).collect();
+        fs::write(root.join("a.txt"), big).unwrap();
+        fs::write(root.join("b.txt"), "haystack\nneedle b\n").unwrap();
+        // 读取失败的文件不影响后面的文件
+        fs::write(root.join("c.txt"), b"needle \xff\n").unwrap();
+        fs::write(root.join("d.txt"), "nothing here\n").unwrap();
+        fs::write(root.join("e.txt"), "needle e\n").unwrap();
+
+        let config = Config {
+            queries: vec![String::from("needle")],
+            ..Config::default()
+        };
+        // 只有一个工作线程，所有文件都读入同一块缓冲区
+        let pool = threadpool::ThreadPool::builder()
+            .min_threads(1)
+            .max_threads(1)
+            .with_worker_init(init_read_buffer)
+            .build();
+        let mut out = Vec::new();
+        let report =
+            search_tree_parallel(&config, &root, &pool, MAX_IN_FLIGHT_FILES, &mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(502, lines.len());
+        assert!(lines[499].ends_with("a.txt:needle 499"));
+        assert!(lines[500].ends_with("b.txt:needle b"));
+        assert!(lines[501].ends_with("e.txt:needle e"));
+        assert_eq!((3, 1), (report.files_matched, report.errors));
+    }
+
+    #[test]
+    fn panicking_search_still_reports_a_result() {
+        let (buffer, result) = catch_search(|buffer| {
+            buffer.extend_from_slice(b"partial");
+            panic!("search failed");
+        });
+        assert!(buffer.is_empty());
+        assert!(matches!(result, Err(SearchError::Panicked)));
+
+        let (buffer, result) = catch_search(|buffer| {
+            buffer.extend_from_slice(b"a.txt:needle\n");
+            Ok(None)
+        });
+        assert_eq!(b"a.txt:needle\n", &buffer[..]);
+        assert!(matches!(result, Ok(None)));
+    }
+
+    #[test]
+    fn walk_errors_are_counted() {
+        let root = temp_dir("walk_errors_are_counted");
+        // 目录不能作为 `.gitignore` 读取，报告错误之后仍然搜索其它文件
+        fs::create_dir_all(root.join(".gitignore")).unwrap();
+        fs::write(root.join("a.txt"), "needle\n").unwrap();
+
+        let root_arg = root.to_string_lossy();
+        for flags in [&[][..], &["--threads", "2"]] {
+            let config = Config::build(&args(&[flags, &["-h", &root_arg, "needle"]].concat()));
+            let mut out = Vec::new();
+            let report = run_with_writer(&config.unwrap(), &mut out).unwrap();
+            assert_eq!("needle\n", String::from_utf8(out).unwrap());
+            assert_eq!((1, 1), (report.files_matched, report.errors));
+        }
+    }
+
+    #[test]
+    fn read_buffer_shrinks_after_big_file() {
+        with_read_buffer(|buffer| buffer.resize(4 * READ_BUFFER_CAPACITY, b'x'));
+        let capacity = with_read_buffer(|buffer| buffer.capacity());
+        assert!(capacity <= READ_BUFFER_CAPACITY, "{capacity}");
+    }
+
+    #[test]
+    fn normalized_whitespace_matches() {
+        assert_eq!("a b c", normalize_whitespace("  a \t b   c  "));
+
+        let content = "  connection\t  reset by   peer  \nok\n";
+        let mut config = Config {
+            queries: vec![String::from("connection reset by peer")],
+            ..Config::default()
+        };
+        assert!(execute_search(&config, content).is_empty());
+
+        config.normalize_ws = true;
+        config.recompile().unwrap();
+        assert_eq!(
+            vec!["  connection\t  reset by   peer  "],
+            execute_search(&config, content)
+        );
+        config.anchor = Anchor::Exact;
+        config.recompile().unwrap();
+        assert_eq!(1, execute_search(&config, content).len());
+    }
+
+    #[test]
+    fn dedupe_all_keeps_first_occurrence() {
+        assert_eq!(
+            vec!["a", "b", "c"],
+            dedupe_all(vec!["a", "b", "a", "c", "b"])
+        );
+
+        let config = Config {
+            queries: vec![String::from("a")],
+            unique_lines: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            vec!["a", "ab", "ca"],
+            execute_search(&config, "a\nab\na\nb\nca\nab")
+        );
+    }
+
+    #[test]
+    fn quiet_run_reports_result_without_output() {
+        let dir = temp_dir("quiet_run_reports_result_without_output");
+        let file_path = dir.join("poem.txt");
+        fs::write(
+            &file_path,
+            "I'm nobody! Who are you?\nAre you nobody, too?\n",
+        )
+        .unwrap();
+
+        let mut config = Config {
+            file_path: file_path.to_string_lossy().into_owned(),
+            queries: vec![String::from("nobody")],
+            quiet: true,
+            ..Config::default()
+        };
+        let mut out = Vec::new();
+        assert!(run_with_writer(&config, &mut out).unwrap().found());
+        assert!(out.is_empty());
+
+        config.set_queries(vec![String::from("somebody")]).unwrap();
+        assert!(!run_with_writer(&config, &mut out).unwrap().found());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn json_output_per_match() {
+        let dir = temp_dir("json_output_per_match");
+        let file_path = dir.join("poem.txt");
+        fs::write(
+            &file_path,
+            "Searching for the\nI'm \"nobody\"!\tWho are you?\n",
+        )
+        .unwrap();
+
+        let config = Config {
+            file_path: file_path.to_string_lossy().into_owned(),
+            queries: vec![String::from("nobody")],
+            format: OutputFormat::Json,
+            ..Config::default()
+        };
+        let expected = format!(
+            "{{\"path\":\"{}\",\"line_number\":2,\"column\":6,\"text\":\"I'm \\\"nobody\\\"!\\tWho are you?\"}}\n",
+            output::json_escape(&file_path.to_string_lossy())
+        );
+        assert_eq!(expected, run_to_string(&config));
+    }
+
+    #[test]
+    fn json_array_is_a_single_document() {
+        let root = temp_dir("json_array_is_a_single_document");
+        fs::write(root.join("a.txt"), "needle one\nhay\n").unwrap();
+        fs::write(root.join("b.txt"), "needle \"two\"\nneedle three\n").unwrap();
+        let search = |query: &str| {
+            let config = Config {
+                file_path: root.to_string_lossy().into_owned(),
+                queries: vec![String::from(query)],
+                format: OutputFormat::JsonArray,
+                ..Config::default()
+            };
+            run_to_string(&config)
+        };
+
+        let output = search("needle");
+        // 整个输出是一行，并且是一个合法的 JSON 数组
+        assert_eq!(1, output.lines().count());
+        let items: Vec<serde_json::Value> = serde_json::from_str(&output).unwrap();
+        let hits: Vec<(String, u64, &str)> = items
+            .iter()
+            .map(|item| {
+                let path = Path::new(item["path"].as_str().unwrap());
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                (
+                    name,
+                    item["line_number"].as_u64().unwrap(),
+                    item["text"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (String::from("a.txt"), 1, "needle one"),
+                (String::from("b.txt"), 1, "needle \"two\""),
+                (String::from("b.txt"), 2, "needle three"),
+            ],
+            hits
+        );
+        assert_eq!("[]\n", search("missing"));
+
+        // 二进制文件也是数组中的一个对象，而不是混在其中的文本提示
+        fs::write(root.join("c.bin"), b"needle\0").unwrap();
+        let items: Vec<serde_json::Value> = serde_json::from_str(&search("needle")).unwrap();
+        assert_eq!(4, items.len());
+        assert_eq!(Some(true), items[3]["binary"].as_bool());
+    }
+
+    #[test]
+    fn tail_needs_a_single_file() {
+        let root = temp_dir("tail_needs_a_single_file");
+        let file = root.join("app.log");
+        fs::write(&file, "needle\n").unwrap();
+        let build =
+            |path: &Path| Config::build(&args(&["--tail", &path.to_string_lossy(), "needle"]));
+
+        assert!(build(&file).is_ok());
+        for path in [&*root, Path::new(STDIN_PATH)] {
+            assert!(matches!(
+                build(path),
+                Err(ConfigError::InvalidValue(flag, _)) if flag == "--tail"
+            ));
+        }
+    }
+
+    #[test]
+    fn json_rejects_per_file_outputs() {
+        for format in ["--json", "--json-array"] {
+            for flag in [
+                "-c",
+                "-L",
+                "--count-matches",
+                "--count-by-file",
+                "--heading",
+            ] {
+                assert!(matches!(
+                    Config::build(&args(&[format, flag, "poem.txt", "to"])),
+                    Err(ConfigError::ConflictingOptions(_, _))
+                ));
+            }
+            assert!(matches!(
+                Config::build(&args(&[format, "-C", "2", "poem.txt", "to"])),
+                Err(ConfigError::ConflictingOptions(_, "-C"))
+            ));
+        }
+    }
+
+    #[test]
+    fn patterns_from_file() {
+        let dir = temp_dir("patterns_from_file");
+        let patterns = dir.join("patterns.txt");
+        // 第二个查询字符串后面带有多余的空格
+        fs::write(&patterns, "# 注释行\nRust\nthree.  \n\n").unwrap();
+        let file_path = dir.join("poem.txt");
+        fs::write(&file_path, "Rust:\nsafe, fast, productive.\nPick three.\n").unwrap();
+
+        let config = Config::build(&args(&[
+            "-f",
+            &patterns.to_string_lossy(),
+            &file_path.to_string_lossy(),
+        ]))
+        .unwrap();
+        assert_eq!(vec!["Rust", "three."], config.queries);
+        assert_eq!("Rust:\nPick three.\n", run_to_string(&config));
+    }
+
+    #[test]
+    fn color_choice_controls_escape_codes() {
+        let dir = temp_dir("color_choice_controls_escape_codes");
+        let file_path = dir.join("poem.txt");
+        fs::write(&file_path, "Rust:\nsafe, fast, productive.\nTrust me.\n").unwrap();
+
+        let mut config = Config {
+            file_path: file_path.to_string_lossy().into_owned(),
+            queries: vec![String::from("rust")],
+            ignore_case: true,
+            color: ColorChoice::Always,
+            ..Config::default()
+        };
+        assert_eq!(
+            "\x1b[1;31mRust\x1b[0m:\nT\x1b[1;31mrust\x1b[0m me.\n",
+            run_to_string(&config)
+        );
+
+        config.color = ColorChoice::Never;
+        assert_eq!("Rust:\nTrust me.\n", run_to_string(&config));
+    }
+
+    #[test]
+    fn highlight_regex_wraps_each_span() {
+        let regex = Regex::new(r"\d+").unwrap();
+        assert_eq!(
+            "id \x1b[1;31m12\x1b[0m of \x1b[1;31m345\x1b[0m",
+            highlight_regex(&regex, "id 12 of 345")
+        );
+        // 只有空匹配时原样返回
+        let regex = Regex::new("x*").unwrap();
+        assert_eq!("café", highlight_regex(&regex, "café"));
+    }
+
+    #[test]
+    fn recursive_search_filters_extensions() {
+        let root = temp_dir("recursive_search_filters_extensions");
+        fs::write(root.join("main.rs"), "let needle = 1;\n").unwrap();
+        fs::write(root.join("logo.png"), "needle\n").unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src").join("lib.rs"), "// needle\n").unwrap();
+
+        let config = Config {
+            file_path: root.to_string_lossy().into_owned(),
+            queries: vec![String::from("needle")],
+            extensions: vec![String::from("rs")],
+            ..Config::default()
+        };
+        assert_eq!(
+            format!(
+                "{}:let needle = 1;\n{}:// needle\n",
+                root.join("main.rs").display(),
+                root.join("src").join("lib.rs").display()
+            ),
+            run_to_string(&config)
+        );
+    }
+
+    #[test]
+    fn regex_is_compiled_in_build() {
+        let build = |pattern: &str| Config::build(&args(&["-E", "/no/such/file.txt", pattern]));
+
+        // 文件并不存在，说明错误发生在读取文件之前
+        assert!(matches!(build("fn ("), Err(ConfigError::InvalidRegex(_))));
+
+        let config = build(r"fn \w+\(").unwrap();
+        let content = "fn main() {\n    let f = 1;\n}\nfn helper(x: i32) {}\n";
+        let lines: Vec<&str> = matching_lines(&config, content)
+            .map(|(_, line)| line)
+            .collect();
+        assert_eq!(vec!["fn main() {", "fn helper(x: i32) {}"], lines);
+    }
+
+    #[test]
+    fn multiline_pattern_reports_start_line() {
+        let config = Config::build(&args(&["-U", "-E", "src/lib.rs", r"fn foo\(\n\s+a"])).unwrap();
+        let content = "use std::io;\n\nfn foo(\n    a: i32,\n) {}\nfn foo(b: i32) {}\n";
+        assert_eq!(
+            vec![(3, "fn foo(\n    a: i32,")],
+            search_multiline(&config, content)
+        );
+
+        // 逐行匹配时查询中的换行符永远匹配不上
+        let config = Config {
+            multiline: false,
+            ..config
+        };
+        assert_eq!(0, matching_lines(&config, content).count());
+    }
+
+    #[test]
+    fn execute_search_dispatch() {
+        let content = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.
+rust
+rust";
+        let config = |flags: &[&str]| Config::build(&args(flags)).unwrap();
+
+        assert_eq!(
+            vec!["Trust me.", "rust", "rust"],
+            execute_search(&config(&["poem.txt", "rust"]), content)
+        );
+        assert_eq!(
+            vec!["Rust:", "safe, fast, productive.", "Pick three."],
+            execute_search(&config(&["-v", "poem.txt", "rust"]), content)
+        );
+        // 完整单词匹配时 Trust 中的 rust 不算
+        assert_eq!(
+            vec!["rust", "rust"],
+            execute_search(&config(&["-w", "poem.txt", "rust"]), content)
+        );
+        assert_eq!(
+            vec!["Trust me.", "rust"],
+            execute_search(
+                &config(&[
+                    "-E",
+                    "--unique-consecutive",
+                    "-m",
+                    "2",
+                    "poem.txt",
+                    "rust( |$)"
+                ]),
+                content
+            )
+        );
+        assert_eq!(
+            vec!["rust"],
+            execute_search(
+                &config(&["-E", "-w", "-m", "1", "poem.txt", "r[a-z]+"]),
+                content
+            )
+        );
+    }
+
+    #[test]
+    fn invert_and_count_compose() {
+        let root = temp_dir("invert_and_count_compose");
+        let path = root.join("poem.txt");
+        let content = "Rust:\nsafe, fast, productive.\nPick three.\nTrust me.\nDuct tape.";
+        fs::write(&path, content).unwrap();
+
+        let build = |flags: &[&str]| {
+            let path = path.to_string_lossy();
+            Config::build(&args(&[flags, &[&path, "rust"]].concat())).unwrap()
+        };
+
+        assert_eq!(vec!["1"], execute_search(&build(&["-c"]), content));
+        assert_eq!(vec!["4"], execute_search(&build(&["-c", "-v"]), content));
+        assert_eq!(vec!["3"], execute_search(&build(&["-ic", "-v"]), content));
+
+        let mut out = Vec::new();
+        let report = run_with_writer(&build(&["-vci"]), &mut out).unwrap();
+        assert_eq!("3\n", String::from_utf8(out).unwrap());
+        assert_eq!(3, report.matched_lines);
+    }
+
+    #[test]
+    fn files_without_match_lists_only_clean_files() {
+        let root = temp_dir("files_without_match_lists_only_clean_files");
+        fs::write(
+            root.join("licensed.rs"),
+            "// SPDX-License-Identifier: MIT\nfn main() {}\n",
+        )
+        .unwrap();
+        fs::write(root.join("missing.rs"), "fn main() {}\n").unwrap();
+
+        let config = Config::build(&args(&["-L", &root.to_string_lossy(), "SPDX"])).unwrap();
+        let mut out = Vec::new();
+        let report = run_with_writer(&config, &mut out).unwrap();
+        assert_eq!(
+            format!("{}\n", root.join("missing.rs").display()),
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!(1, report.matched_lines);
+    }
+
+    #[test]
+    fn inverted_context_surrounds_non_matching_lines() {
+        let content = "ok 1\nok 2\nfailed\nok 3\nok 4\nok 5\n";
+        let groups = search_with_context_by(content, |line| !line.contains("ok"), 0, 1);
+        assert_eq!(vec![vec![(3, "failed"), (4, "ok 3")]], groups);
+
+        let root = temp_dir("inverted_context_surrounds_non_matching_lines");
+        let path = root.join("status.txt");
+        fs::write(&path, content).unwrap();
+        let config = Config {
+            file_path: path.to_string_lossy().into_owned(),
+            queries: vec![String::from("ok")],
+            invert: true,
+            after_context: 1,
+            line_number: true,
+            ..Config::default()
+        };
+        assert_eq!("3:failed\n4-ok 3\n", run_to_string(&config));
+    }
+
+    #[test]
+    fn context_header_shows_enclosing_fn() {
+        let root = temp_dir("context_header_shows_enclosing_fn");
+        let path = root.join("main.rs");
+        let source = [
+            "use std::fs;",
+            "",
+            "fn parse(input: &str) -> u32 {",
+            "    let value = input.trim();",
+            "    value.parse().unwrap()",
+            "}",
+            "",
+            "pub fn main() {",
+            "    let n = parse(\"42\");",
+            "    println!(\"{n}\");",
+            "}",
+        ];
+        fs::write(&path, source.join("\n")).unwrap();
+
+        let output = |flags: &[&str], query: &str| {
+            let path = path.to_string_lossy();
+            let args = args(&[&["-n"], flags, &[&path, query]].concat());
+            run_to_string(&Config::build(&args).unwrap())
+        };
+
+        assert_eq!(
+            "3:fn parse(input: &str) -> u32 {\n\
+             5:    value.parse().unwrap()\n\
+             8-pub fn main() {\n\
+             9:    let n = parse(\"42\");\n",
+            output(&["--context-header"], "parse")
+        );
+        assert_eq!(
+            "8-pub fn main() {\n10:    println!(\"{n}\");\n",
+            output(&["--context-header"], "println")
+        );
+        // 自定义的标题行
+        assert_eq!(
+            "1-use std::fs;\n4:    let value = input.trim();\n",
+            output(&["--header-pattern", "^use "], "trim")
+        );
+        assert_eq!("10:    println!(\"{n}\");\n", output(&[], "println"));
+    }
+
+    #[test]
+    fn custom_group_separator() {
+        let root = temp_dir("custom_group_separator");
+        let path = root.join("poem.txt");
+        fs::write(&path, "a match\nb\nc\nd\ne match\n").unwrap();
+
+        let output = |separator: &[&str]| {
+            let path = path.to_string_lossy();
+            let args = args(&[&["-A", "1"], separator, &[&path, "match"]].concat());
+            run_to_string(&Config::build(&args).unwrap())
+        };
+
+        assert_eq!("a match\nb\n--\ne match\n", output(&[]));
+        assert_eq!(
+            "a match\nb\n====\ne match\n",
+            output(&["--group-separator", "===="])
+        );
+        assert_eq!("a match\nb\ne match\n", output(&["--group-separator="]));
+        assert_eq!("a match\nb\ne match\n", output(&["--no-group-separator"]));
+        // 有行号时上下文行的行号后面是 `-`
+        assert_eq!("1:a match\n2-b\n--\n5:e match\n", output(&["-n"]));
+    }
+
+    #[test]
+    fn context_respects_match_limits() {
+        let root = temp_dir("context_respects_match_limits");
+        let path = root.join("log.txt");
+        fs::write(&path, "err 1\nok\nerr 1\nok\nerr 2\nok\n").unwrap();
+
+        let output = |flags: &[&str]| {
+            let path = path.to_string_lossy();
+            let args = args(&[&["-n", "-A", "1"], flags, &[&path, "err"]].concat());
+            run_to_string(&Config::build(&args).unwrap())
+        };
+
+        assert_eq!("1:err 1\n2-ok\n", output(&["-m", "1"]));
+        assert_eq!("5:err 2\n6-ok\n", output(&["--nth", "3"]));
+        assert_eq!(
+            "1:err 1\n2-ok\n--\n5:err 2\n6-ok\n",
+            output(&["--unique-lines"])
+        );
+    }
+
+    #[test]
+    fn in_place_replace_keeps_backup() {
+        let dir = temp_dir("in_place_replace_keeps_backup");
+        let path = dir.join("notes.txt");
+        let original = "foo bar\r\nbaz FOO\nnone\nfoo";
+        fs::write(&path, original).unwrap();
+
+        let config = Config::build(&args(&[
+            "-i",
+            "--replace",
+            "qux",
+            "--in-place",
+            "--backup",
+            &path.to_string_lossy(),
+            "foo",
+        ]))
+        .unwrap();
+        let mut out = Vec::new();
+        let report = run_with_writer(&config, &mut out).unwrap();
+
+        assert_eq!(3, report.matched_lines);
+        assert!(out.is_empty());
+        assert_eq!(
+            "qux bar\r\nbaz qux\nnone\nqux",
+            fs::read_to_string(&path).unwrap()
+        );
+        assert_eq!(
+            original,
+            fs::read_to_string(dir.join("notes.txt.bak")).unwrap()
+        );
+        // 临时文件已经 rename 成了原文件，目录中只剩原文件和备份
+        assert_eq!(2, fs::read_dir(&dir).unwrap().count());
+
+        assert!(matches!(
+            Config::build(&args(&["--in-place", "notes.txt", "foo"])),
+            Err(ConfigError::MissingOption("--in-place", "--replace"))
+        ));
+    }
+
+    #[test]
+    fn replacement_follows_matcher_options() {
+        let replace = |flags: &[&str], query: &str, line: &str| {
+            let args = args(&[&["--replace", "dog"], flags, &["r.txt", query]].concat());
+            replace_matches(&Config::build(&args).unwrap(), line, "dog")
+        };
+
+        assert_eq!("dog condogenate", replace(&[], "cat", "cat concatenate"));
+        assert_eq!(
+            "dog concatenate",
+            replace(&["-w"], "cat", "cat concatenate")
+        );
+        assert_eq!("dog cat", replace(&["--start"], "cat", "cat cat"));
+        assert_eq!("cat dog", replace(&["--end"], "cat", "cat cat"));
+        assert_eq!("cat cat", replace(&["--exact"], "cat", "cat cat"));
+        assert_eq!("cat", replace(&["-v"], "cat", "cat"));
+        assert_eq!("dog dog", replace(&["-i"], "cat", "Cat CAT"));
+        assert_eq!(
+            "say dog!",
+            replace(&["--normalize-ws"], "hello world", "say hello \t world!")
+        );
+
+        let config =
+            Config::build(&args(&["--replace", "x", "--lines", "2:2", "r.txt", "a"])).unwrap();
+        let (content, changed) = replace_content(&config, "a\na\na\n", "x");
+        assert_eq!(("a\nx\na\n", 1), (content.as_str(), changed));
+    }
+
+    #[test]
+    fn in_place_refuses_compressed_and_symlinked_files() {
+        let dir = temp_dir("in_place_refuses_compressed_and_symlinked_files");
+        let compressed = dir.join("log.gz");
+        fs::write(&compressed, [GZIP_MAGIC.as_slice(), b"rest"].concat()).unwrap();
+
+        let run = |path: &Path| {
+            let args = args(&[
+                "--replace",
+                "x",
+                "--in-place",
+                &path.to_string_lossy(),
+                "rest",
+            ]);
+            search_contents(&Config::build(&args).unwrap(), path, false, &mut Vec::new())
+        };
+        assert!(matches!(
+            run(&compressed),
+            Err(SearchError::NotEditable(..))
+        ));
+
+        #[cfg(unix)]
+        {
+            let target = dir.join("target.txt");
+            fs::write(&target, "rest\n").unwrap();
+            let link = dir.join("link.txt");
+            std::os::unix::fs::symlink(&target, &link).unwrap();
+            assert!(matches!(run(&link), Err(SearchError::NotEditable(..))));
+            assert!(fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink());
+            assert_eq!("rest\n", fs::read_to_string(&target).unwrap());
+        }
+    }
+
+    #[test]
+    fn replacement_uses_capture_groups() {
+        let build = |replacement: &str| {
+            Config::build(&args(&[
+                "-E",
+                "--replace",
+                replacement,
+                "names.txt",
+                r"(?P<last>\w+), (\w+)",
+            ]))
+        };
+
+        let config = build("$2 $1").unwrap();
+        let (content, changed) = replace_content(&config, "Lovelace, Ada\nTuring, Alan\n", "$2 $1");
+        assert_eq!("Ada Lovelace\nAlan Turing\n", content);
+        assert_eq!(2, changed);
+
+        let config = build("${2}_$last").unwrap();
+        assert_eq!(
+            "Ada_Lovelace!",
+            replace_matches(&config, "Lovelace, Ada!", "${2}_$last")
+        );
+
+        assert!(build("$$1 costs $$").is_ok());
+        for (replacement, group) in [("$3 $1", "3"), ("${first}", "first"), ("$2_x", "2_x")] {
+            assert!(matches!(
+                build(replacement),
+                Err(ConfigError::UnknownCaptureGroup(g)) if g == group
+            ));
+        }
+    }
+
+    #[test]
+    fn large_files_are_skipped() {
+        let root = temp_dir("large_files_are_skipped");
+        fs::write(root.join("small.txt"), "needle\n").unwrap();
+        fs::write(
+            root.join("large.txt"),
+            format!("needle\n{}", "x".repeat(2048)),
+        )
+        .unwrap();
+
+        let config = Config::build(&args(&[
+            "--max-filesize",
+            "1K",
+            &root.to_string_lossy(),
+            "needle",
+        ]))
+        .unwrap();
+        assert_eq!(Some(1024), config.max_filesize);
+
+        let mut out = Vec::new();
+        let report = run_with_writer(&config, &mut out).unwrap();
+        assert_eq!(
+            format!("{}:needle\n", root.join("small.txt").display()),
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!((1, 1), (report.files_searched, report.files_skipped));
+
+        assert!(matches!(
+            parse_size("12X"),
+            Err(ConfigError::InvalidValue(_, _))
+        ));
+        assert_eq!(2 << 20, parse_size("2M").unwrap());
+    }
+
+    #[test]
+    fn count_by_file_sorts_by_matches() {
+        let root = temp_dir("count_by_file_sorts_by_matches");
+        fs::write(root.join("a.txt"), "todo\n").unwrap();
+        fs::write(root.join("b.txt"), "todo\ntodo\ntodo\n").unwrap();
+        fs::write(root.join("c.txt"), "done\n").unwrap();
+        fs::write(root.join("d.txt"), "todo\nx\ntodo\n").unwrap();
+        fs::write(root.join("e.txt"), "todo\n").unwrap();
+
+        let config = Config {
+            file_path: root.to_string_lossy().into_owned(),
+            queries: vec![String::from("todo")],
+            count_by_file: true,
+            ..Config::default()
+        };
+        let mut out = Vec::new();
+        let report = run_with_writer(&config, &mut out).unwrap();
+
+        let expected: String = [("b.txt", 3), ("d.txt", 2), ("a.txt", 1), ("e.txt", 1)]
+            .iter()
+            .map(|(name, count)| format!("{}:{count}\n", root.join(name).display()))
+            .collect();
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+        assert_eq!(7, report.matched_lines);
+    }
+
+    #[test]
+    fn heading_groups_matches_by_file() {
+        let root = temp_dir("heading_groups_matches_by_file");
+        fs::write(root.join("a.txt"), "todo one\nskip\ntodo two\n").unwrap();
+        fs::write(root.join("b.txt"), "nothing\n").unwrap();
+        fs::write(root.join("c.txt"), "last todo\n").unwrap();
+
+        let mut config = Config {
+            file_path: root.to_string_lossy().into_owned(),
+            queries: vec![String::from("todo")],
+            line_number: true,
+            heading: true,
+            ..Config::default()
+        };
+        let expected = format!(
+            "{}\n  1:todo one\n  3:todo two\n\n{}\n  1:last todo\n",
+            root.join("a.txt").display(),
+            root.join("c.txt").display()
+        );
+        assert_eq!(expected, run_to_string(&config));
+
+        // 单个文件没有标题
+        config.file_path = root.join("a.txt").to_string_lossy().into_owned();
+        assert_eq!("1:todo one\n3:todo two\n", run_to_string(&config));
+    }
+
+    #[test]
+    fn filename_prefix_can_be_forced_either_way() {
+        let root = temp_dir("filename_prefix_can_be_forced_either_way");
+        fs::write(root.join("a.txt"), "todo one\n").unwrap();
+        fs::write(root.join("b.txt"), "todo two\n").unwrap();
+        let search = |flags: &[&str]| run_to_string(&Config::build(&args(flags)).unwrap());
+        let dir = root.to_string_lossy().into_owned();
+        let file = root.join("a.txt").to_string_lossy().into_owned();
+
+        assert_eq!(
+            format!(
+                "{}:todo one\n{}:todo two\n",
+                root.join("a.txt").display(),
+                root.join("b.txt").display()
+            ),
+            search(&[&dir, "todo"])
+        );
+        assert_eq!("todo one\ntodo two\n", search(&["-h", &dir, "todo"]));
+        assert_eq!(
+            "todo one\ntodo two\n",
+            search(&["-H", "--no-filename", &dir, "todo"])
+        );
+
+        assert_eq!("1:todo one\n", search(&["-n", &file, "todo"]));
+        assert_eq!(
+            format!("{file}:1:todo one\n"),
+            search(&["-nH", &file, "todo"])
+        );
+        assert_eq!(
+            format!("{file}:todo one\n"),
+            search(&["--with-filename", &file, "todo"])
+        );
+    }
+
+    #[test]
+    fn match_path_reports_named_files() {
+        let root = temp_dir("match_path_reports_named_files");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src").join("needle.rs"), "fn main() {}\n").unwrap();
+        fs::write(root.join("src").join("lib.rs"), "// needle\n").unwrap();
+        fs::write(root.join("other.rs"), "nothing\n").unwrap();
+
+        let mut config = Config {
+            file_path: root.to_string_lossy().into_owned(),
+            queries: vec![String::from("needle")],
+            match_path: true,
+            ..Config::default()
+        };
+        let mut out = Vec::new();
+        let report = run_with_writer(&config, &mut out).unwrap();
+        let expected = format!(
+            "{lib}:// needle\n{named}\n",
+            lib = root.join("src").join("lib.rs").display(),
+            named = root.join("src").join("needle.rs").display()
+        );
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+        assert_eq!(2, report.matched_lines);
+
+        // 关闭之后只匹配内容
+        config.match_path = false;
+        let mut out = Vec::new();
+        assert_eq!(1, run_with_writer(&config, &mut out).unwrap().matched_lines);
+    }
+
+    #[test]
+    fn channel_output_applies_backpressure() {
+        let dir = temp_dir("channel_output_applies_backpressure");
+        let path = dir.join("app.log");
+        let content: String = (1..=40)
+            .map(|i| format!("{} {i}\n", if i % 2 == 0 { "error" } else { "info" }))
+            .collect();
+        fs::write(&path, content).unwrap();
+
+        let config = Config {
+            file_path: path.to_string_lossy().into_owned(),
+            queries: vec![String::from("error")],
+            ..Config::default()
+        };
+        let (tx, rx) = mpsc::sync_channel(2);
+        let producer = thread::spawn(move || run_to_channel(&config, tx).unwrap());
+
+        let mut received = Vec::new();
+        for m in rx {
+            // 消费者比生产者慢，生产者会阻塞在 send 上
+            thread::sleep(Duration::from_millis(2));
+            received.push(m.line_number);
+        }
+        let report = producer.join().unwrap();
+
+        assert_eq!((2..=40).step_by(2).collect::<Vec<_>>(), received);
+        assert_eq!(20, report.matched_lines);
+    }
+
+    #[test]
+    fn channel_output_follows_file_rules() {
+        let root = temp_dir("channel_output_follows_file_rules");
+        fs::write(root.join("a.txt"), "one\nneedle\nhay\n").unwrap();
+        fs::write(root.join("b.bin"), b"needle\0").unwrap();
+        fs::write(root.join("c.txt"), "needle\n".repeat(10)).unwrap();
+
+        let config = Config {
+            file_path: root.to_string_lossy().into_owned(),
+            queries: vec![String::from("needle\nhay")],
+            multiline: true,
+            max_filesize: Some(32),
+            ..Config::default()
+        };
+        let (tx, rx) = mpsc::sync_channel(4);
+        let producer = thread::spawn(move || run_to_channel(&config, tx).unwrap());
+        let received: Vec<_> = rx.iter().collect();
+        let report = producer.join().unwrap();
+
+        // 跨行匹配按照开始的行号发送；二进制文件不会被当作文本发送；超过大小限制的文件被跳过
+        assert_eq!(
+            vec![search_async::Match {
+                path: root.join("a.txt"),
+                line_number: 2,
+                line: String::from("needle\nhay"),
+            }],
+            received
+        );
+        assert_eq!((2, 1), (report.files_searched, report.files_skipped));
+    }
+
+    #[test]
+    fn dry_run_lists_candidates_only() {
+        let root = temp_dir("dry_run_lists_candidates_only");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src").join("main.rs"), "needle").unwrap();
+        fs::write(root.join("notes.txt"), "needle").unwrap();
+        fs::write(root.join("lib.rs"), "haystack").unwrap();
+
+        let config = Config {
+            file_path: root.to_string_lossy().into_owned(),
+            queries: vec![String::from("needle")],
+            extensions: vec![String::from("rs")],
+            dry_run: true,
+            ..Config::default()
+        };
+        let mut out = Vec::new();
+        let report = run_with_writer(&config, &mut out).unwrap();
+
+        // 不匹配的 lib.rs 也会列出来，扩展名不符的 notes.txt 不会
+        let expected = format!(
+            "{}\n{}\n",
+            root.join("lib.rs").display(),
+            root.join("src").join("main.rs").display()
+        );
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+        assert_eq!(RunReport::default(), report);
+    }
+
+    #[test]
+    fn long_lines_are_truncated() {
+        let dir = temp_dir("long_lines_are_truncated");
+        let path = dir.join("bundle.min.js");
+        let long = format!("var a=1;{}needle", "x".repeat(200));
+        fs::write(&path, format!("{long}\nshort needle\n")).unwrap();
+
+        let config = Config {
+            file_path: path.to_string_lossy().into_owned(),
+            queries: vec![String::from("needle")],
+            max_line_width: Some(12),
+            line_number: true,
+            ..Config::default()
+        };
+        let mut out = Vec::new();
+        let report = run_with_writer(&config, &mut out).unwrap();
+
+        // 查询字符串在被截掉的部分中，仍然算作匹配
+        assert_eq!(2, report.matched_lines);
+        assert_eq!(
+            "1:var a=1;xxx…\n2:short needle\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        for width in ["0", "wide"] {
+            assert!(matches!(
+                Config::build(&args(&["--max-line-width", width, "f", "q"])),
+                Err(ConfigError::InvalidValue(flag, value)) if flag == "--max-line-width" && value == width
+            ));
+        }
+    }
+
+    #[test]
+    fn structured_hits_carry_positions() {
+        let root = temp_dir("structured_hits_carry_positions");
+        let first = root.join("a.rs");
+        let second = root.join("b.rs");
+        fs::write(&first, "fn main() {\n    let café = todo();\n}\n").unwrap();
+        fs::write(&second, "// TODO: later\n").unwrap();
+
+        let config = Config {
+            queries: vec![String::from("todo")],
+            ignore_case: true,
+            ..Config::default()
+        };
+        let hits: Vec<SearchHit> = search_structured(&[first.clone(), second.clone()], &config)
+            .into_iter()
+            .flat_map(|(_, result)| result.unwrap().unwrap().hits)
+            .collect();
+        assert_eq!(
+            vec![
+                SearchHit {
+                    path: first,
+                    line: 2,
+                    // 列号按字符计算，é 虽然占两个字节，但只算一列
+                    column: 16,
+                    text: String::from("    let café = todo();"),
+                },
+                SearchHit {
+                    path: second,
+                    line: 1,
+                    column: 4,
+                    text: String::from("// TODO: later"),
+                },
+            ],
+            hits
+        );
+    }
+
+    #[test]
+    fn structured_search_reads_files_like_run() {
+        let root = temp_dir("structured_search_reads_files_like_run");
+        let text = root.join("text.txt");
+        let binary = root.join("data.bin");
+        let large = root.join("large.txt");
+        let missing = root.join("missing.txt");
+        fs::write(&text, "one\nneedle\nhay\n").unwrap();
+        fs::write(&binary, b"needle\0junk").unwrap();
+        fs::write(&large, "needle ".repeat(10)).unwrap();
+
+        let config = Config {
+            queries: vec![String::from("needle")],
+            max_filesize: Some(32),
+            ..Config::default()
+        };
+        let paths = [text.clone(), binary.clone(), large.clone(), missing.clone()];
+        let results = search_structured(&paths, &config);
+        assert_eq!(
+            paths.to_vec(),
+            results
+                .iter()
+                .map(|(path, _)| path.clone())
+                .collect::<Vec<_>>()
+        );
+        let text_hits = results[0].1.as_ref().unwrap().as_ref().unwrap();
+        assert_eq!(2, text_hits.hits[0].line);
+        assert!(!text_hits.binary_match);
+        let binary_hits = results[1].1.as_ref().unwrap().as_ref().unwrap();
+        assert!(binary_hits.binary_match && binary_hits.hits.is_empty());
+        // 超过大小限制的文件不读取，缺失的文件只影响它自己
+        assert!(results[2].1.as_ref().unwrap().is_none());
+        assert!(matches!(results[3].1, Err(SearchError::Io(_))));
+
+        let config = Config {
+            queries: vec![String::from("needle\nhay")],
+            multiline: true,
+            ..Config::default()
+        };
+        let hits = &search_structured(&[text], &config)[0].1;
+        let hits = &hits.as_ref().unwrap().as_ref().unwrap().hits;
+        assert_eq!(
+            vec![(2, "needle\nhay")],
+            hits.iter()
+                .map(|hit| (hit.line, hit.text.as_str()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn line_range_limits_searched_lines() {
+        let dir = temp_dir("line_range_limits_searched_lines");
+        let path = dir.join("ten.txt");
+        let content: String = (1..=10).map(|i| format!("match {i}\n")).collect();
+        fs::write(&path, content).unwrap();
+        let config = Config::build(&args(&[
+            "-n",
+            "--lines",
+            "3:5",
+            &path.to_string_lossy(),
+            "match",
+        ]))
+        .unwrap();
+        let mut out = Vec::new();
+        let report = run_with_writer(&config, &mut out).unwrap();
+        // 行号仍然是在整个文件中的行号
+        assert_eq!(
+            "3:match 3\n4:match 4\n5:match 5\n",
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!(3, report.matched_lines);
+
+        for range in ["0:5", "5:3", "3", "a:b"] {
+            assert!(matches!(
+                Config::build(&args(&["--lines", range, "f", "q"])),
+                Err(ConfigError::InvalidValue(_, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn nth_prints_a_single_match() {
+        let dir = temp_dir("nth_prints_a_single_match");
+        let path = dir.join("log.txt");
+        fs::write(&path, "error one\nok\nerror two\nerror three\n").unwrap();
+        let search = |n: &str| {
+            let args = args(&["-n", "--nth", n, &path.to_string_lossy(), "error"]);
+            run_to_string(&Config::build(&args).unwrap())
+        };
+
+        assert_eq!("3:error two\n", search("2"));
+        assert_eq!("", search("4"));
+
+        // 搜索多个文件时跨文件计数，找到之后不再搜索后面的文件
+        let root = temp_dir("nth_counts_across_files");
+        fs::write(root.join("a.txt"), "error a1\n").unwrap();
+        fs::write(root.join("b.txt"), "ok\nerror b1\nerror b2\n").unwrap();
+        fs::write(root.join("c.txt"), "error c1\n").unwrap();
+        let search_dir = |n: &str| {
+            let args = args(&["-h", "-n", "--nth", n, &root.to_string_lossy(), "error"]);
+            let config = Config::build(&args).unwrap();
+            let mut out = Vec::new();
+            let report = run_with_writer(&config, &mut out).unwrap();
+            (String::from_utf8(out).unwrap(), report)
+        };
+        let (out, report) = search_dir("3");
+        assert_eq!("3:error b2\n", out);
+        assert_eq!((2, 1), (report.files_searched, report.matched_lines));
+        assert_eq!("1:error c1\n", search_dir("4").0);
+        let (out, report) = search_dir("5");
+        assert_eq!("", out);
+        assert!(!report.found());
+        let config = Config {
+            queries: vec![String::from("a")],
+            nth: Some(2),
+            unique_lines: true,
+            ..Config::default()
+        };
+        // 去重之后再选第 n 个
+        assert_eq!(vec!["ab"], execute_search(&config, "a\na\nab\n"));
+        assert!(matches!(
+            Config::build(&args(&["--nth", "0", "f", "q"])),
+            Err(ConfigError::InvalidValue(_, _))
+        ));
+    }
+
+    #[test]
+    fn empty_files_are_counted() {
+        let root = temp_dir("empty_files_are_counted");
+        fs::write(root.join("empty.txt"), "").unwrap();
+        fs::write(root.join("full.txt"), "haystack\n").unwrap();
+
+        let config = Config {
+            file_path: root.to_string_lossy().into_owned(),
+            queries: vec![String::from("needle")],
+            warn_empty: true,
+            ..Config::default()
+        };
+        let mut out = Vec::new();
+        let report = run_with_writer(&config, &mut out).unwrap();
+
+        // 提示只输出到标准错误，搜索结果中没有任何内容
+        assert!(out.is_empty());
+        assert_eq!(1, report.empty_files);
+        assert_eq!(2, report.files_searched);
+        assert_eq!(1, report.lines_scanned);
+    }
+
+    #[test]
+    fn search_errors_are_typed() {
+        let config = Config {
+            file_path: String::from("/no/such/file.txt"),
+            queries: vec![String::from("rust")],
+            ..Config::default()
+        };
+        let err = run_with_writer(&config, &mut Vec::new()).unwrap_err();
+        assert!(matches!(&err, SearchError::Io(e) if e.kind() == io::ErrorKind::NotFound));
+
+        let err = run_with_writer(&Config::default(), &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, SearchError::NoInput));
+
+        let root = temp_dir("search_errors_are_typed");
+        let path = root.join("latin1.txt");
+        fs::write(&path, b"caf\xe9").unwrap();
+        let config = Config {
+            file_path: path.to_string_lossy().into_owned(),
+            ..config
+        };
+        let err = run_with_writer(&config, &mut Vec::new()).unwrap_err();
+        assert!(matches!(&err, SearchError::InvalidUtf8(p) if *p == path));
+        assert_eq!(
+            format!("{} is not valid UTF-8", path.display()),
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn exit_code_follows_grep() {
+        assert_eq!(0, exit_code(true, false));
+        assert_eq!(1, exit_code(false, false));
+        assert_eq!(2, exit_code(false, true));
+        assert_eq!(2, exit_code(true, true));
+    }
+
+    #[test]
+    fn escaped_whitespace_in_query() {
+        assert_eq!("a\tb\nc\r\\d\\x\\", interpret_escapes(r"a\tb\nc\r\\d\x\"));
+
+        let content = "name\tage\nname age\nC:\\Users\nC:Users";
+        let build = |query: &str| Config::build(&args(&["--escape", "poem.txt", query])).unwrap();
+
+        let config = build(r"name\tage");
+        let lines: Vec<&str> = matching_lines(&config, content)
+            .map(|(_, line)| line)
+            .collect();
+        assert_eq!(vec!["name\tage"], lines);
+
+        let config = build(r"C:\\Users");
+        let lines: Vec<&str> = matching_lines(&config, content)
+            .map(|(_, line)| line)
+            .collect();
+        assert_eq!(vec!["C:\\Users"], lines);
+    }
+
+    #[test]
+    fn config_accessors() {
+        let config = Config::build(&args(&["poem.txt", "body"])).unwrap();
+
+        assert_eq!("body", config.query());
+        assert_eq!(["body"], config.queries());
+        assert_eq!("poem.txt", config.file_path());
+        assert_eq!(env::var("IGNORE_CASE").is_ok(), config.ignore_case());
+    }
+
+    #[test]
+    fn query_from_environment() {
+        // 不修改进程的环境变量，否则会与并行运行的其它测试互相影响
+        let var = |name: &str| (name == "MINIGREP_QUERY").then(|| String::from("from env"));
+        let from_env = Config::parse(&args(&["poem.txt"]), &[], var).map(|config| config.queries);
+        let from_argv =
+            Config::parse(&args(&["poem.txt", "from argv"]), &[], var).map(|config| config.queries);
+
+        assert_eq!(vec!["from env"], from_env.unwrap());
+        assert_eq!(vec!["from argv"], from_argv.unwrap());
+        assert!(matches!(
+            Config::parse(&args(&["poem.txt"]), &[], |_| None),
+            Err(ConfigError::NotEnoughArguments)
+        ));
+    }
+
+    #[test]
+    fn empty_query_requires_opt_in() {
+        assert!(matches!(
+            Config::build(&args(&["poem.txt", ""])),
+            Err(ConfigError::EmptyQuery)
+        ));
+        assert!(matches!(
+            Config::build(&args(&["-e", "body", "-e", "", "poem.txt"])),
+            Err(ConfigError::EmptyQuery)
+        ));
+
+        let config = Config::build(&args(&["--allow-empty", "poem.txt", ""])).unwrap();
+        assert_eq!([""], config.queries());
+        assert_eq!(2, execute_search(&config, "a\nb").len());
+    }
+
+    #[test]
+    fn combined_short_flags() {
+        let build = |flags: &str| Config::build(&args(&[flags, "poem.txt", "body"]));
+
+        for flags in ["-in", "-ni"] {
+            let config = build(flags).unwrap();
+            assert!(config.ignore_case());
+            assert!(config.line_number);
+            assert_eq!("body", config.query());
+        }
+
+        assert!(matches!(
+            build("-ix"),
+            Err(ConfigError::InvalidFlag(flag)) if flag == "-x"
+        ));
+        assert!(matches!(
+            build("-mi"),
+            Err(ConfigError::MissingValue(flag)) if flag == "-m"
+        ));
+        // 有值但不是数字时报告无效的值，而不是缺少值
+        assert!(matches!(
+            Config::build(&args(&["-m", "abc", "poem.txt", "body"])),
+            Err(ConfigError::InvalidValue(flag, value)) if flag == "-m" && value == "abc"
+        ));
+        assert!(matches!(
+            Config::build(&args(&["--max-depth", "deep", "poem.txt", "body"])),
+            Err(ConfigError::InvalidValue(flag, value)) if flag == "--max-depth" && value == "deep"
+        ));
+        assert!(matches!(
+            Config::build(&args(&["poem.txt", "body", "-m"])),
+            Err(ConfigError::MissingValue(flag)) if flag == "-m"
+        ));
+
+        // 选项的值即使看起来像组合短选项也原样保留
+        let build =
+            |flags: &[&str]| Config::build(&args(&[flags, &["poem.txt"]].concat())).unwrap();
+        for value in ["-in", "-ab"] {
+            let config = build(&["-e", value]);
+            assert_eq!(vec![value], config.queries);
+            assert!(!config.ignore_case() && !config.line_number);
+            let config = build(&["--group-separator", value, "-e", "x"]);
+            assert_eq!(Some(value), config.group_separator.as_deref());
+            let config = build(&["--replace", value, "-e", "x"]);
+            assert_eq!(Some(value), config.replace.as_deref());
+            let config = build(&["--header-pattern", value, "-e", "x"]);
+            assert_eq!(
+                Some(value),
+                config.context_header.as_ref().map(Regex::as_str)
+            );
+        }
+        let config = build(&["-in", "-e", "-in"]);
+        assert!(config.ignore_case() && config.line_number);
+        assert_eq!(vec!["-in"], config.queries);
+    }
+
+    #[test]
+    fn shell_words_honor_quotes() {
+        let config = Config::from_shell_words(r#"minigrep -n poem.txt "to be or""#).unwrap();
+        assert_eq!(vec!["to be or"], config.queries);
+        assert_eq!("poem.txt", config.file_path);
+        assert!(config.line_number);
+
+        let config = Config::from_shell_words(r#"minigrep 'my notes.txt' 'say "hi"'"#).unwrap();
+        assert_eq!("my notes.txt", config.file_path);
+        assert_eq!(vec![r#"say "hi""#], config.queries);
+
+        let config = Config::from_shell_words(r#"minigrep a.txt "back\\slash \"q\"" "#).unwrap();
+        assert_eq!(vec![r#"back\slash "q""#], config.queries);
+
+        assert!(matches!(
+            Config::from_shell_words("minigrep a.txt 'open"),
+            Err(ConfigError::UnterminatedQuote(_))
+        ));
+    }
+
+    #[test]
+    fn quoted_dash_pattern_is_a_query() {
+        for input in [
+            "minigrep notes.txt '-v'",
+            r#"minigrep notes.txt "-v""#,
+            r"minigrep notes.txt \-v",
+        ] {
+            let config = Config::from_shell_words(input).unwrap();
+            assert_eq!(vec!["-v"], config.queries);
+            assert!(!config.invert);
+        }
+
+        // 没有引号时仍然是选项
+        let config = Config::from_shell_words("minigrep -v notes.txt todo").unwrap();
+        assert!(config.invert);
+        assert_eq!(vec!["todo"], config.queries);
+
+        // 组合短选项不会展开带引号的单词；选项的值本来就可以以 - 开头
+        let config = Config::from_shell_words("minigrep -in notes.txt '-in'").unwrap();
+        assert!(config.ignore_case() && config.line_number);
+        assert_eq!(vec!["-in"], config.queries);
+        let config = Config::from_shell_words("minigrep -e '--help' notes.txt").unwrap();
+        assert_eq!(vec!["--help"], config.queries);
+        assert!(matches!(
+            Config::from_shell_words("minigrep notes.txt --nope"),
+            Err(ConfigError::InvalidFlag(flag)) if flag == "--nope"
+        ));
+    }
+
+    #[test]
+    fn pipeline_chains_transforms() {
+        use compute::{pipeline, AddN, Transform};
+
+        let steps: Vec<Box<dyn Transform>> = vec![Box::new(AddN(1)), Box::new(AddN(2))];
+        assert_eq!(8, pipeline(5, &steps));
+        assert_eq!(5, pipeline(5, &[]));
+
+        let steps: Vec<Box<dyn Transform>> = vec![Box::new(AddN(1)), Box::new(|x| x * 2)];
+        assert_eq!(compute::add_one(5) * 2, pipeline(5, &steps));
+    }
+
+    #[test]
+    fn run_report_counts_files() {
+        let root = temp_dir("run_report_counts_files");
+        fs::write(root.join("a.txt"), "needle\nhay\nneedle again\n").unwrap();
+        fs::write(root.join("b.txt"), "more needle\n").unwrap();
+        fs::write(root.join("c.bin"), b"needle\0").unwrap();
+        // 不是合法的 UTF-8，也不包含 NUL，读取为文本时会出错
+        fs::write(root.join("d.txt"), b"needle \xff\xfe").unwrap();
+
+        let config = Config {
+            file_path: root.to_string_lossy().into_owned(),
+            queries: vec![String::from("needle")],
+            binary: BinaryPolicy::Skip,
+            ..Config::default()
+        };
+        let report = run_with_writer(&config, &mut Vec::new()).unwrap();
+        assert_eq!(
+            RunReport {
+                matched_lines: 3,
+                files_searched: 2,
+                files_matched: 2,
+                files_skipped: 1,
+                errors: 1,
+                lines_scanned: 4,
+                empty_files: 0,
+            },
+            report
+        );
+        assert_eq!(
+            "3 matches in 2 files (1 skipped, 1 error)",
+            report.to_string()
+        );
+    }
+
+    #[test]
+    fn summary_counts_matched_searched_and_skipped_files() {
+        let root = temp_dir("summary_counts_matched_searched_and_skipped_files");
+        fs::create_dir_all(root.join("nested")).unwrap();
+        fs::write(root.join("a.txt"), "needle\n").unwrap();
+        fs::write(root.join("nested").join("b.txt"), "hay\nneedle\n").unwrap();
+        fs::write(root.join("c.txt"), "hay\n").unwrap();
+        fs::write(root.join("binary.bin"), b"needle\0\x01").unwrap();
+        fs::write(root.join("big.txt"), "needle ".repeat(300)).unwrap();
+
+        let config = Config::build(&args(&[
+            "--summary",
+            "--skip-binary",
+            "--max-filesize",
+            "1K",
+            &root.to_string_lossy(),
+            "needle",
+        ]))
+        .unwrap();
+        assert!(config.summary());
+        let report = run_with_writer(&config, &mut Vec::new()).unwrap();
+        assert_eq!(
+            (2, 3, 2),
+            (
+                report.files_matched,
+                report.files_searched,
+                report.files_skipped
+            )
+        );
+        assert_eq!("matched 2 of 3 files (2 skipped)", report.summary());
+    }
+
+    #[test]
+    fn overlapping_and_non_overlapping_counts() {
+        assert_eq!(2, count_matches("aa", "aaaa", false));
+        assert_eq!(3, count_overlapping("aa", "aaaa", false));
+
+        assert_eq!(1, count_matches("aba", "ababa", false));
+        assert_eq!(2, count_overlapping("aba", "ababa", false));
+
+        assert_eq!(0, count_overlapping("AA", "aaaa", false));
+        assert_eq!(3, count_overlapping("AA", "aaaa", true));
+        assert_eq!(2, count_overlapping("哈哈", "哈哈哈", false));
+        assert_eq!(0, count_overlapping("", "aaaa", false));
+    }
+
+    #[test]
+    fn occurrences_per_line() {
+        let content = "\
+Rust:
+rust and Rust again
+Pick three.";
+        assert_eq!(
+            vec![(2, "rust and Rust again", 1)],
+            search_count_per_line("rust", content, false)
+        );
+        assert_eq!(
+            vec![(1, "Rust:", 1), (2, "rust and Rust again", 2)],
+            search_count_per_line("rust", content, true)
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_file_is_searched_like_plain_text() {
+        use flate2::{write::GzEncoder, Compression};
+
+        let content = "Rust:\nsafe, fast, productive.\nPick three.\nTrust me.\n";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert!(compressed.starts_with(&GZIP_MAGIC));
+
+        let root = temp_dir("gzip_file_is_searched_like_plain_text");
+        fs::write(root.join("poem.txt"), content).unwrap();
+        fs::write(root.join("poem.txt.gz"), compressed).unwrap();
+
+        let search = |name: &str| {
+            let config = Config {
+                file_path: root.join(name).to_string_lossy().into_owned(),
+                queries: vec![String::from("ust")],
+                ..Config::default()
+            };
+            let mut out = Vec::new();
+            run_with_writer(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!("Rust:\nTrust me.\n", search("poem.txt"));
+        assert_eq!(search("poem.txt"), search("poem.txt.gz"));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_stdin_is_detected() {
+        use flate2::{write::GzEncoder, Compression};
+
+        let content = "Rust:\nsafe, fast, productive.\nPick three.\nTrust me.\n";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let config = Config {
+            file_path: String::from(STDIN_PATH),
+            queries: vec![String::from("ust")],
+            line_number: true,
+            ..Config::default()
+        };
+        let search = |input: &[u8]| {
+            let mut out = Vec::new();
+            let report = search_reader(&config, input, &mut out).unwrap();
+            (String::from_utf8(out).unwrap(), report.matched_lines)
+        };
+        assert_eq!(
+            (String::from("1:Rust:\n4:Trust me.\n"), 2),
+            search(&compressed)
+        );
+        // 不是 gzip 的数据原样搜索，窥探过的开头两个字节也不会丢失
+        assert_eq!(search(content.as_bytes()), search(&compressed));
+        assert_eq!((String::from("1:ust\n"), 1), search(b"ust"));
+    }
+
+    #[test]
+    fn lines_scanned_counts_blank_lines() {
+        let root = temp_dir("lines_scanned_counts_blank_lines");
+        let path = root.join("poem.txt");
+        fs::write(&path, "Rust:\n\nsafe, fast, productive.\n\nPick three.\n").unwrap();
+
+        let config = Config {
+            file_path: path.to_string_lossy().into_owned(),
+            queries: vec![String::from("nothing")],
+            ..Config::default()
+        };
+        let report = run_with_writer(&config, &mut Vec::new()).unwrap();
+        assert_eq!(5, report.lines_scanned);
+        assert!(!report.found());
+    }
+
+    #[test]
+    fn owned_results_outlive_content() {
+        let results = {
+            let content = String::from("Rust:\nsafe, fast, productive.\nTrust me.");
+            search_owned("rust", &content, true)
+            // content 在这里被释放
+        };
+        assert_eq!(vec!["Rust:", "Trust me."], results);
+    }
+
+    #[test]
+    fn crlf_line_endings_are_stripped() {
+        let content = "Rust:\r\nsafe, fast, productive.\r\nPick three.\r\n";
+        let build = |flags: &[&str]| Config::build(&args(flags)).unwrap();
+
+        let config = build(&["--end", "poem.txt", "three."]);
+        let lines: Vec<&str> = matching_lines(&config, content)
+            .map(|(_, line)| line)
+            .collect();
+        assert_eq!(vec!["Pick three."], lines);
+
+        let config = build(&["-U", "-E", "poem.txt", r"productive\.$"]);
+        assert_eq!(
+            vec![(2, "safe, fast, productive.")],
+            search_multiline(&config, content)
+        );
+
+        assert_eq!(
+            vec![&b"Pick three."[..]],
+            search_bytes(b"three", content.as_bytes())
+        );
+    }
+
+    #[cfg(feature = "glob")]
+    #[test]
+    fn glob_expands_to_matching_files() {
+        let root = temp_dir("glob_expands_to_matching_files");
+        for name in ["a.txt", "b.txt", "c.log"] {
+            fs::write(root.join(name), "needle\n").unwrap();
+        }
+        let build = |pattern: &str| {
+            Config::build(&args(&[&root.join(pattern).to_string_lossy(), "needle"]))
+        };
+
+        let config = build("*.txt").unwrap();
+        assert_eq!(vec![root.join("a.txt"), root.join("b.txt")], config.files);
+        assert_eq!(
+            2,
+            run_with_writer(&config, &mut Vec::new())
+                .unwrap()
+                .files_searched
+        );
+
+        assert!(matches!(
+            build("*.md"),
+            Err(ConfigError::NoMatchingFiles(_))
+        ));
+
+        // 已经存在的路径即使带有通配符中的字符也原样使用
+        fs::write(root.join("report[1].txt"), "needle\n").unwrap();
+        let config = build("report[1].txt").unwrap();
+        assert!(config.files.is_empty());
+        assert_eq!(
+            root.join("report[1].txt").to_string_lossy(),
+            config.file_path
+        );
+    }
+
+    #[test]
+    fn prefilter_matches_unfiltered_regex() {
+        let content: String = (0..200)
+            .map(|i| match i % 7 {
+                0 => format!("error {i}: disk full\n"),
+                1 => format!("Error {i}: disk almost full\n"),
+                _ => format!("ok {i}\n"),
+            })
+            .collect();
+        for pattern in [
+            r"[Ee]rror \d+: disk (almost )?full",
+            r"rror \d+: \w+ full$",
+            "k [0-9]+",
+        ] {
+            let args = args(&["-n", "-E", "log.txt", pattern]);
+            let config = Config::build(&args).unwrap();
+            assert!(config.prefilter.is_some(), "{pattern}");
+            let mut unfiltered = Config::build(&args).unwrap();
+            unfiltered.prefilter = None;
+            // build 中已经构建好了带预过滤的匹配器，去掉缓存之后按照修改过的字段重新构建
+            unfiltered.matcher = OnceLock::new();
+            assert_eq!(
+                execute_search(&unfiltered, &content),
+                execute_search(&config, &content)
+            );
+        }
+
+        let build = |extra: &[&str]| Config::build(&args(&[&["log.txt", "err.r"], extra].concat()));
+        let config = build(&["-E", "--literal-prefilter", "err"]).unwrap();
+        assert_eq!(Some("err"), config.prefilter.as_deref());
+        assert!(matches!(
+            build(&["--literal-prefilter", "err"]),
+            Err(ConfigError::MissingOption("--literal-prefilter", "--regex"))
+        ));
+    }
+
+    #[test]
+    fn count_lines_versus_count_matches() {
+        let root = temp_dir("count_lines_versus_count_matches");
+        let input = root.join("input.txt");
+        fs::write(&input, "rust and rust\nno match\n").unwrap();
+
+        let count = |flag: &str| {
+            let config = Config::build(&args(&[flag, &input.to_string_lossy(), "rust"])).unwrap();
+            (
+                run_to_string(&config),
+                execute_search(&config, "rust and rust\nno match\n"),
+            )
+        };
+
+        assert_eq!(
+            (String::from("1\n"), vec![String::from("1")]),
+            count("--count")
+        );
+        assert_eq!(
+            (String::from("2\n"), vec![String::from("2")]),
+            count("--count-matches")
+        );
+
+        let occurrences = |flags: &[&str], queries: &[&str]| {
+            let args = args(&[&["--count-matches"], flags, &["input.txt"], queries].concat());
+            execute_search(&Config::build(&args).unwrap(), "cat concatenate cat\n")
+        };
+        assert_eq!(vec!["3"], occurrences(&[], &["cat"]));
+        assert_eq!(vec!["2"], occurrences(&["-w"], &["cat"]));
+        assert_eq!(vec!["1"], occurrences(&["--start"], &["cat"]));
+        // 重叠的查询字符串只计一次
+        assert_eq!(vec!["3"], occurrences(&["-e", "cat", "-e", "ca"], &[]));
+        assert_eq!(
+            vec!["3"],
+            occurrences(&["--and", "-e", "cat", "-e", "ca"], &[])
+        );
+    }
+
+    #[test]
+    fn fixed_strings_from_pattern_file() {
+        let root = temp_dir("fixed_strings_from_pattern_file");
+        let patterns = root.join("patterns.txt");
+        // 正则的元字符在固定字符串模式中都是普通字符
+        fs::write(&patterns, "a.b\n(x)\nneedle\n[0-9]+\n").unwrap();
+        let input = root.join("input.txt");
+        fs::write(
+            &input,
+            "axb\nfound a.b here\nf(x)\nhay\nNEEDLE\nneedle\nid [0-9]+\n123\n",
+        )
+        .unwrap();
+
+        let search = |flags: &[&str]| {
+            let paths = [&*patterns.to_string_lossy(), &input.to_string_lossy()];
+            let config = Config::build(&args(&[flags, &paths].concat())).unwrap();
+            let mut out = Vec::new();
+            let report = run_with_writer(&config, &mut out).unwrap();
+            (String::from_utf8(out).unwrap(), report.matched_lines)
+        };
+
+        let expected = "2:found a.b here\n3:f(x)\n6:needle\n7:id [0-9]+\n";
+        assert_eq!((String::from(expected), 4), search(&["-nFf"]));
+        // -F 在 -E 之后出现，以 -F 为准
+        assert_eq!(
+            (String::from(expected), 4),
+            search(&["-n", "-E", "-F", "-f"])
+        );
+    }
+
+    #[test]
+    fn all_queries_must_match() {
+        let content = "\
+error: disk full
+warning: disk almost full
+error: network down";
+        let queries = vec![String::from("disk"), String::from("error")];
+        assert_eq!(
+            vec!["error: disk full"],
+            search_all(&queries, content, false)
+        );
+        let reversed: Vec<String> = queries.iter().rev().cloned().collect();
+        assert_eq!(
+            search_all(&queries, content, false),
+            search_all(&reversed, content, false)
+        );
+
+        let build = |extra: &[&str]| {
+            let flags = ["--and", "-e", "DISK", "-e", "error", "-i"];
+            Config::build(&args(&[&flags, extra].concat()))
+        };
+        let config = build(&["log.txt"]).unwrap();
+        assert_eq!(vec!["error: disk full"], execute_search(&config, content));
+        assert!(matches!(
+            build(&["-E", "log.txt"]),
+            Err(ConfigError::ConflictingOptions("--and", "--regex"))
+        ));
+    }
+
+    #[test]
+    fn line_contains_respects_case() {
+        assert!(line_contains("Trust me.", "rust", false));
+        assert!(!line_contains("Rust:", "rust", false));
+        assert!(line_contains("Rust:", "rust", true));
+        assert!(line_contains("rust:", "RUST", true));
+        assert!(!line_contains("Pick three.", "rust", true));
+        assert!(matches!(lowercase_query("rust", true), Cow::Borrowed(_)));
+        assert_eq!("rust", lowercase_query("RuSt", true));
+        assert_eq!("RuSt", lowercase_query("RuSt", false));
+    }
+
+    #[test]
+    fn distinct_matches_in_first_seen_order() {
+        let content = "order 42 shipped\norder 7 pending\nretry order 42\n7 items, 1000 total";
+        let regex = Regex::new(r"\d+").unwrap();
+        assert_eq!(
+            vec!["42", "7", "1000"],
+            unique_regex_matches(&regex, content)
+        );
+
+        let content = "Rust:\ntrust RUST\nrust";
+        assert_eq!(vec!["rust"], unique_matches("rust", content, false));
+        assert_eq!(
+            vec!["Rust", "rust", "RUST"],
+            unique_matches("rust", content, true)
+        );
+    }
+
+    #[test]
+    fn search_bytes_with_invalid_utf8() {
+        let contents: &[u8] = b"ok line\nbad \xFF error\nerror again\n";
+
+        assert_eq!(
+            vec![&b"bad \xFF error"[..], &b"error again"[..]],
+            search_bytes(b"error", contents)
+        );
+        assert_eq!(vec![&b"bad \xFF error"[..]], search_bytes(b"\xFF", contents));
+        assert!(search_bytes(b"missing", contents).is_empty());
+    }
+
+    #[test]
+    fn binary_buffer_is_detected() {
+        let bytes = b"PNG\x00\x01\x02 some header";
+        assert!(is_probably_binary(bytes));
+    }
+
+    #[test]
+    fn text_buffer_is_not_binary() {
+        let bytes = "Rust:\nsafe, fast, productive.\n你好".as_bytes();
+        assert!(!is_probably_binary(bytes));
+    }
+}
+
+/// 增加生命周期提示，让编译器知道在函数调用期间这些引用变量是不会出现问题的
+pub fn search<'a>(query: &'a str, content: &'a str) -> Vec<&'a str> {
+    vec![]
+}
+
+pub fn search_right<'a>(query: &'a str, content: &'a str) -> Vec<&'a str> {
+    search_iter(query, content, false).collect()
+}
+
+/**
+ * 失败用例
+ */
+pub fn search_case_insensitive_fail<'a>(query: &'a str, content: &'a str) -> Vec<&'a str> {
+    vec![]
+}
+
+/**
+ * 成功的用例
+ */
+pub fn search_case_insensitive_right<'a>(query: &'a str, content: &'a str) -> Vec<&'a str> {
+    search_iter(query, content, true).collect()
+}
+
+/**
+ * 惰性搜索：返回迭代器而不是 Vec，只有在消费时才会继续向后查找
+ *
+ * 配合 `.take(n)` 可以在找到足够的结果后立即停止，不需要为全部结果分配内存
+ */
+pub fn search_iter<'a>(
+    query: &'a str,
+    content: &'a str,
+    ignore_case: bool,
+) -> impl Iterator<Item = &'a str> {
+    let query = lowercase_query(query, ignore_case);
+    content
+        .lines()
+        .filter(move |line| line_contains(line, &query, ignore_case))
+}
+
+/**
+ * 在线程池中并行搜索一个很大的文件内容，返回 `(行号, 行内容)`，行号从 1 开始
+ *
+ * 按行切分成与线程数相同的若干块，每块作为一个任务提交，并带上这一块第一行的下标，
+ * 这样每个任务算出的行号在整个文件中都是正确的。各块完成的先后顺序不确定，最后按块的序号合并
+ */
+pub fn search_content_parallel(
+    content: &str,
+    query: &str,
+    ignore_case: bool,
+    pool: &threadpool::ThreadPool,
+) -> Vec<(usize, String)> {
+    let lines: Vec<&str> = content.lines().collect();
+    let chunk_size = lines.len().div_ceil(pool.thread_count().max(1)).max(1);
+    // 任务需要 'static，查询字符串和每一块的内容都要转换成拥有所有权的数据
+    let query: Arc<str> = Arc::from(lowercase_query(query, ignore_case).as_ref());
+
+    let (tx, rx) = mpsc::channel();
+    let mut chunks = 0;
+    for (index, chunk) in lines.chunks(chunk_size).enumerate() {
+        let start = index * chunk_size;
+        let chunk: Vec<String> = chunk.iter().map(|line| line.to_string()).collect();
+        let query = Arc::clone(&query);
+        let tx = tx.clone();
+        pool.execute(move || {
+            let found: Vec<(usize, String)> = chunk
+                .into_iter()
+                .enumerate()
+                .filter(|(_, line)| line_contains(line, &query, ignore_case))
+                .map(|(i, line)| (start + i + 1, line))
+                .collect();
+            let _ = tx.send((index, found));
+        });
+        chunks += 1;
+    }
+    drop(tx);
+
+    threadpool::ordered_collect(rx, chunks)
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// [`init_read_buffer`] 为每个工作线程预先分配的读取缓冲区大小
+pub const READ_BUFFER_CAPACITY: usize = 64 * 1024;
+
+thread_local! {
+    /// 每个线程复用的读取缓冲区，搜索文件时（包括 [`search_tree_parallel`] 的任务）读取每个文件之前先清空它
+    static READ_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/**
+ * 为当前线程预先分配读取缓冲区，作为线程池的 `with_worker_init` 回调使用
+ *
+ * 每个工作线程只分配一次，之后这个线程搜索的所有文件都复用这块内存，
+ * 只有遇到比缓冲区更大的文件时才会重新分配。没有调用它的线程第一次读取文件时按需分配
+ */
+pub fn init_read_buffer(_worker: usize) {
+    READ_BUFFER.with(|buffer| buffer.borrow_mut().reserve(READ_BUFFER_CAPACITY));
+}
+
+/**
+ * 把当前线程的缓冲区交给 `f`，由 `f` 把文件读入其中，缓冲区中可能还留着上一个文件的内容
+ *
+ * 读过特别大的文件之后缓冲区会收缩回 [`READ_BUFFER_CAPACITY`]，一个大文件不会让线程池中的线程一直占着那么多内存
+ */
+fn with_read_buffer<T>(f: impl FnOnce(&mut Vec<u8>) -> T) -> T {
+    READ_BUFFER.with(|buffer| {
+        let mut buffer = buffer.borrow_mut();
+        let result = f(&mut buffer);
+        if buffer.capacity() > READ_BUFFER_CAPACITY {
+            buffer.clear();
+            buffer.shrink_to(READ_BUFFER_CAPACITY);
+        }
+        result
+    })
+}
+
+/// [`search_tree_parallel`] 默认最多同时读取的文件数量
+pub const MAX_IN_FLIGHT_FILES: usize = 64;
+
+/**
+ * 在线程池中并行地递归搜索目录，输出与逐个搜索每个文件时完全相同，对应命令行的 `--threads`
+ *
+ * 主线程一边遍历目录一边把发现的文件作为任务提交，每个任务按照与单线程相同的规则（二进制、压缩、大小限制、
+ * 跨行匹配、上下文等）搜索一个文件，把输出写入自己的缓冲区后通过通道发回来，主线程按照遍历的顺序依次写出。
+ * 同时在处理中的文件最多 `max_in_flight` 个，达到上限时主线程先等待一个结果再继续遍历，
+ * 避免目录很大时一次打开太多文件。单个文件出错时记录下来继续搜索其它文件。
+ *
+ * 文件读入工作线程的缓冲区中搜索，不会为每个文件分配新的内存，线程池通过
+ * `with_worker_init(init_read_buffer)` 构建时每个工作线程启动时就分配好缓冲区
+ */
+pub fn search_tree_parallel<W: Write>(
+    config: &Config,
+    root: &Path,
+    pool: &threadpool::ThreadPool,
+    max_in_flight: usize,
+    out: &mut W,
+) -> Result<RunReport, SearchError> {
+    // 任务需要 'static 的配置，克隆一份共享给所有任务，匹配器仍然是同一个
+    let shared = Arc::new(config.clone());
+    let (tx, rx) = mpsc::channel();
+    let mut results = FileResults::new(config);
+    // 已经完成、但前面还有文件没有完成的结果，按照提交的顺序暂存
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+    let mut collect = |(index, path, buffer, result): (usize, PathBuf, Vec<u8>, _)| {
+        pending.insert(index, (path, buffer, result));
+        while let Some((path, buffer, result)) = pending.remove(&next) {
+            results.finish(&path, &buffer, result, out);
+            next += 1;
+        }
+    };
+
+    let mut submitted = 0;
+    let mut in_flight = 0;
+    let walk_errors = walk::walk_files(root, config, |path| {
+        if in_flight >= max_in_flight.max(1) {
+            // 每个任务都会发送一个结果，主线程持有 tx，这里不会因为通道断开而返回错误
+            if let Ok(done) = rx.recv() {
+                collect(done);
+                in_flight -= 1;
+            }
+        }
+        let config = Arc::clone(&shared);
+        let tx = tx.clone();
+        let index = submitted;
+        pool.execute(move || {
+            let (buffer, result) =
+                catch_search(|buffer| search_listed_file(&config, &path, buffer));
+            let _ = tx.send((index, path, buffer, result));
+        });
+        submitted += 1;
+        in_flight += 1;
+    });
+    drop(tx);
+    rx.iter().for_each(&mut collect);
+
+    let mut report = results.into_report(out);
+    report.errors += walk_errors;
+    Ok(report)
+}
+
+/**
+ * 在任务中搜索一个文件，返回写入的输出和搜索结果
+ *
+ * 搜索时 panic 也返回一个 [`SearchError::Panicked`]，而不是让任务直接结束：
+ * 主线程按照提交的顺序输出，少了一个文件的结果时后面所有文件的输出都会被一直暂存。
+ * 已经写入的部分输出不完整，直接丢弃
+ */
+fn catch_search(
+    search: impl FnOnce(&mut Vec<u8>) -> Result<Option<FileStats>, SearchError>,
+) -> (Vec<u8>, Result<Option<FileStats>, SearchError>) {
+    let mut buffer = Vec::new();
+    match panic::catch_unwind(AssertUnwindSafe(|| search(&mut buffer))) {
+        Ok(result) => (buffer, result),
+        Err(_) => (Vec::new(), Err(SearchError::Panicked)),
+    }
+}
+
+/**
+ * 判断一行是否包含查询字符串，所有按子串搜索的函数都通过它判断，保证大小写的处理方式一致
+ *
+ * 忽略大小写时行和查询字符串都按小写比较。在循环中调用时可以先用 [`lowercase_query`] 把查询字符串转换一次，
+ * 这里发现它已经是小写就不会再分配
+ */
+pub fn line_contains(line: &str, query: &str, ignore_case: bool) -> bool {
+    if !ignore_case {
+        return line.contains(query);
+    }
+    let query = lowercase_query(query, true);
+    line.to_lowercase().contains(query.as_ref())
+}
+
+/// 忽略大小写时返回小写的查询字符串，已经是小写或者不需要忽略大小写时直接借用
+pub fn lowercase_query(query: &str, ignore_case: bool) -> Cow<'_, str> {
+    if ignore_case && !query.chars().all(|c| c.to_lowercase().eq([c])) {
+        Cow::Owned(query.to_lowercase())
+    } else {
+        Cow::Borrowed(query)
+    }
+}
+
+/**
+ * 返回拥有所有权的结果，不再借用 `content`
+ *
+ * 在辅助函数中读取文件并返回匹配结果时，文件内容在函数结束时就会被释放，借用版本的结果无法返回出去
+ */
+pub fn search_owned(query: &str, content: &str, ignore_case: bool) -> Vec<String> {
+    search_iter(query, content, ignore_case)
+        .map(str::to_owned)
+        .collect()
+}
+
+/**
+ * 搜索同时包含所有查询字符串的行，与查询字符串的顺序无关
+ *
+ * 与配置了多个查询字符串时默认的“任意一个”相反，对应命令行的 `--and`
+ */
+pub fn search_all<'a>(queries: &[String], content: &'a str, ignore_case: bool) -> Vec<&'a str> {
+    let matchers = queries
+        .iter()
+        .map(|query| {
+            Box::new(SubstringMatcher::new(
+                std::slice::from_ref(query),
+                ignore_case,
+            )) as Box<dyn Matcher>
+        })
+        .collect();
+    lines_of(matcher::search_with(&AllMatcher(matchers), content))
+}
+
+/**
+ * 搜索以查询字符串开头的行
+ */
+pub fn search_starts_with<'a>(query: &str, content: &'a str, ignore_case: bool) -> Vec<&'a str> {
+    search_anchored(query, content, ignore_case, Anchor::Start)
+}
+
+/**
+ * 搜索以查询字符串结尾的行
+ */
+pub fn search_ends_with<'a>(query: &str, content: &'a str, ignore_case: bool) -> Vec<&'a str> {
+    search_anchored(query, content, ignore_case, Anchor::End)
+}
+
+/**
+ * 搜索与查询字符串完全相同的行
+ */
+pub fn search_exact<'a>(query: &str, content: &'a str, ignore_case: bool) -> Vec<&'a str> {
+    search_anchored(query, content, ignore_case, Anchor::Exact)
+}
+
+/// 锚定搜索的公共部分，交给 [`AnchoredMatcher`] 判断
+fn search_anchored<'a>(
+    query: &str,
+    content: &'a str,
+    ignore_case: bool,
+    anchor: Anchor,
+) -> Vec<&'a str> {
+    let queries = [query.to_string()];
+    let matcher = AnchoredMatcher::new(&queries, ignore_case, anchor, false);
+    lines_of(matcher::search_with(&matcher, content))
+}
+
+/// 去掉 [`matcher::search_with`] 结果中的行号
+fn lines_of(results: Vec<(usize, &str)>) -> Vec<&str> {
+    results.into_iter().map(|(_, line)| line).collect()
+}
+
+/**
+ * 与 `uniq` 一致：只合并连续重复的行，不相邻的重复行会保留
+ */
+pub fn dedupe_consecutive(mut lines: Vec<&str>) -> Vec<&str> {
+    lines.dedup();
+    lines
+}
+
+/**
+ * 去掉所有重复的行，不要求相邻，保留每一行第一次出现的位置
+ */
+pub fn dedupe_all(lines: Vec<&str>) -> Vec<&str> {
+    let mut seen = HashSet::new();
+    lines
+        .into_iter()
+        .filter(|line| seen.insert(*line))
+        .collect()
+}
+
+/**
+ * 统计查询字符串出现的次数，匹配之间不重叠：`aaaa` 中的 `aa` 计为 2 次
+ *
+ * 空的查询字符串返回 0
+ */
+pub fn count_matches(query: &str, content: &str, ignore_case: bool) -> usize {
+    if query.is_empty() {
+        return 0;
+    }
+    if ignore_case {
+        content
+            .to_lowercase()
+            .matches(&query.to_lowercase())
+            .count()
+    } else {
+        content.matches(query).count()
+    }
+}
+
+/**
+ * 统计查询字符串出现的次数，允许匹配之间重叠：`aaaa` 中的 `aa` 计为 3 次
+ *
+ * 每次匹配后只向后移动一个字符，而不是整个匹配的长度。空的查询字符串返回 0
+ */
+pub fn count_overlapping(query: &str, content: &str, ignore_case: bool) -> usize {
+    if query.is_empty() {
+        return 0;
+    }
+    let (query, content) = if ignore_case {
+        (query.to_lowercase(), content.to_lowercase())
+    } else {
+        (query.to_string(), content.to_string())
+    };
+
+    let mut count = 0;
+    let mut start = 0;
+    while let Some(i) = content[start..].find(&query) {
+        count += 1;
+        // 按字符前进，保证下一次切片落在字符边界上
+        let first = content[start + i..]
+            .chars()
+            .next()
+            .map_or(1, char::len_utf8);
+        start += i + first;
+    }
+    count
+}
+
+/**
+ * 按第一次出现的顺序返回所有不同的匹配内容，每个只返回一次
+ *
+ * 普通查询字符串区分大小写时结果最多只有一个；忽略大小写时返回原文中出现过的各种大小写形式，例如 `Rust` 和 `rust`
+ */
+pub fn unique_matches(query: &str, content: &str, ignore_case: bool) -> Vec<String> {
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let query = lowercase_query(query, ignore_case);
+    let mut found = Vec::new();
+    let mut i = 0;
+    while let Some(c) = content[i..].chars().next() {
+        match match_len_at(&content[i..], &query, ignore_case) {
+            Some(len) => {
+                found.push(&content[i..i + len]);
+                i += len;
+            }
+            None => i += c.len_utf8(),
+        }
+    }
+    dedupe_all(found).into_iter().map(String::from).collect()
+}
+
+/**
+ * 与 [`unique_matches`] 相同，但使用正则表达式匹配，返回所有不同的匹配内容，例如 `\d+` 匹配到的所有数字
+ */
+pub fn unique_regex_matches(regex: &Regex, content: &str) -> Vec<String> {
+    dedupe_all(regex.find_iter(content).map(|m| m.as_str()).collect())
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+/**
+ * 统计每个匹配行中查询字符串出现的次数，返回 `(行号, 行内容, 出现次数)`
+ *
+ * 行号从 1 开始，没有出现查询字符串的行不会返回。同一行中的多次出现按不重叠的方式计数，与 [`count_matches`] 一致
+ */
+pub fn search_count_per_line<'a>(
+    query: &str,
+    content: &'a str,
+    ignore_case: bool,
+) -> Vec<(usize, &'a str, usize)> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line, count_matches(query, line, ignore_case)))
+        .filter(|&(_, _, occurrences)| occurrences > 0)
+        .collect()
+}
+
+/**
+ * 带上下文的搜索：每个匹配行返回一组 `(行号, 行内容)`，包含它前后的若干行
+ *
+ * 行号从 1 开始，相邻或重叠的组交给 [`output::format_context_output`] 合并
+ */
+pub fn search_with_context<'a>(
+    query: &str,
+    content: &'a str,
+    ignore_case: bool,
+    before: usize,
+    after: usize,
+) -> Vec<Vec<(usize, &'a str)>> {
+    let query = lowercase_query(query, ignore_case);
+    search_with_context_by(
+        content,
+        |line| line_contains(line, &query, ignore_case),
+        before,
+        after,
+    )
+}
+
+/**
+ * 与 [`search_with_context`] 相同，但由 `is_match` 决定哪些行是分组的中心
+ *
+ * 上下文只围绕 `is_match` 返回 true 的行收集，不假设这些行一定“包含查询字符串”。
+ * 例如反向匹配时传入 `|line| !line.contains(query)`，得到的就是不匹配的行及其上下文，与 `grep -v -A` 一致
+ */
+pub fn search_with_context_by(
+    content: &str,
+    is_match: impl Fn(&str) -> bool,
+    before: usize,
+    after: usize,
+) -> Vec<Vec<(usize, &str)>> {
+    let lines: Vec<&str> = content.lines().collect();
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| is_match(line))
+        .map(|(i, _)| context_group(&lines, i, before, after))
+        .collect()
+}
+
+/// 第 `index` 行（从 0 开始）及其前后的上下文
+fn context_group<'a>(
+    lines: &[&'a str],
+    index: usize,
+    before: usize,
+    after: usize,
+) -> Vec<(usize, &'a str)> {
+    let start = index.saturating_sub(before);
+    let end = (index + after).min(lines.len() - 1);
+    (start..=end).map(|j| (j + 1, lines[j])).collect()
+}
+
+/**
+ * 按字节搜索，内容不要求是合法的 UTF-8
+ *
+ * 日志中偶尔会混入非法的 UTF-8 字节，`read_to_string` 会直接报错，这里按 `\n` 切分后逐行做字节匹配，避免有损转换
+ */
+pub fn search_bytes<'a>(query: &[u8], content: &'a [u8]) -> Vec<&'a [u8]> {
+    let mut lines: Vec<&[u8]> = content.split(|&b| b == b'\n').collect();
+    // 与 `str::lines` 保持一致，末尾的换行符不会产生额外的空行
+    if content.ends_with(b"\n") {
+        lines.pop();
+    }
+    lines
+        .into_iter()
+        // Windows 换行符 `\r\n` 中的 `\r` 不属于行的内容
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| find_bytes(line, query).is_some())
+        .collect()
+}
+
+/// 在 haystack 中查找 needle 第一次出现的位置
+pub fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let Some((&first, rest)) = needle.split_first() else {
+        return Some(0);
+    };
+    if haystack.len() < needle.len() {
+        return None;
+    }
+
+    // 先快速定位首字节，再比较剩余的字节
+    (0..=haystack.len() - needle.len())
+        .filter(|&i| haystack[i] == first)
+        .find(|&i| &haystack[i + 1..i + needle.len()] == rest)
+}
+
+/// gzip 文件开头固定的两个字节，没有开启 `gzip` 特性时也用它拒绝原地修改压缩文件
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/**
+ * 开启 `gzip` 特性时，根据文件开头的魔数识别 gzip 文件并解压，其它内容原样返回
+ *
+ * 轮转后的日志经常被压缩成 `.gz`，解压之后的匹配逻辑和普通文件完全一样
+ */
+#[cfg(feature = "gzip")]
+fn decompress(bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+    if !bytes.starts_with(&GZIP_MAGIC) {
+        return Ok(bytes);
+    }
+    let mut decoded = Vec::new();
+    flate2::read::MultiGzDecoder::new(&bytes[..]).read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+
+/// 未开启 `gzip` 特性时不做任何处理，压缩文件会被当作二进制文件
+#[cfg(not(feature = "gzip"))]
+fn decompress(bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+    Ok(bytes)
+}
+
+/**
+ * 标准输入这样的流只能读取一次，不能像文件那样先整个读入再检查魔数
+ *
+ * 先读出开头的两个字节，再把它们接回流的前面，相当于窥探而不消费。是 gzip 数据时包装一个解码器边读边解压
+ */
+#[cfg(feature = "gzip")]
+fn maybe_gzip<'a, R: Read + 'a>(mut reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    let mut head = Vec::with_capacity(GZIP_MAGIC.len());
+    Read::by_ref(&mut reader)
+        .take(GZIP_MAGIC.len() as u64)
+        .read_to_end(&mut head)?;
+    let gzip = head == GZIP_MAGIC;
+    let reader = io::Cursor::new(head).chain(reader);
+    if gzip {
+        Ok(Box::new(flate2::read::MultiGzDecoder::new(reader)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// 未开启 `gzip` 特性时原样读取
+#[cfg(not(feature = "gzip"))]
+fn maybe_gzip<'a, R: Read + 'a>(reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(Box::new(reader))
+}
+
+/// 判断二进制文件时只检查开头的这部分字节
+const BINARY_SNIFF_LEN: usize = 8 * 1024;
+
+/// 与 grep 的做法类似：开头一段数据中出现 NUL 字节就认为是二进制文件
+pub fn is_probably_binary(bytes: &[u8]) -> bool {
+    let len = bytes.len().min(BINARY_SNIFF_LEN);
+    bytes[..len].contains(&0)
+}
+
+pub mod threadpool;
+
+pub mod walk;
+
+pub mod output;
+
+pub mod search_async;
+
+pub mod webserver;
+
+pub mod counter;
+
+pub mod connection;
+
+pub mod redis;
+
+pub mod follow;
+
+pub mod remote;
+
+pub mod matcher;
+
+pub mod repl;
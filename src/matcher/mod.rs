@@ -0,0 +1,667 @@
+use regex::Regex;
+
+use crate::Anchor;
+
+/**
+ * 判断一行是否匹配的策略
+ *
+ * 每种搜索方式实现这个特征，`InvertMatcher` 这样的包装器可以和任意一种组合，
+ * 新增一种搜索方式只需要新增一个实现，不需要再写一个 `search_*` 函数
+ */
+pub trait Matcher: Send + Sync {
+    fn is_match(&self, line: &str) -> bool;
+
+    /**
+     * 行中每个匹配的字节范围 `(开始, 结束)`，从左到右排列、互不重叠，不匹配的行返回空
+     *
+     * 替换和统计出现次数都基于它，与 `is_match` 遵循同样的单词边界、锚定等规则
+     */
+    fn find_spans(&self, line: &str) -> Vec<(usize, usize)>;
+}
+
+/// 忽略大小写时查询字符串只转换一次小写
+fn prepare_queries(queries: &[String], ignore_case: bool) -> Vec<String> {
+    queries
+        .iter()
+        .map(|query| {
+            if ignore_case {
+                query.to_lowercase()
+            } else {
+                query.clone()
+            }
+        })
+        .collect()
+}
+
+/// 忽略大小写时把行转换成小写后再交给 `f`，查询字符串需要已经是小写
+fn with_case<R>(line: &str, ignore_case: bool, f: impl FnOnce(&str) -> R) -> R {
+    if ignore_case {
+        f(&line.to_lowercase())
+    } else {
+        f(line)
+    }
+}
+
+/**
+ * 从左到右扫描原始的行，在每个位置取满足 `accept(开始, 结束)` 的最长查询字符串，返回命中的字节范围
+ *
+ * 查询字符串需要已经按照 `ignore_case` 处理过。忽略大小写时在原始的行上逐个字符比较，
+ * 转换小写之后字节长度会变化的字符也能得到原始行中正确的范围
+ */
+fn literal_spans(
+    line: &str,
+    queries: &[String],
+    ignore_case: bool,
+    accept: impl Fn(usize, usize) -> bool,
+) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while let Some(c) = line[i..].chars().next() {
+        let len = queries
+            .iter()
+            .filter(|query| !query.is_empty())
+            .filter_map(|query| crate::match_len_at(&line[i..], query, ignore_case))
+            .filter(|&len| accept(i, i + len))
+            .max();
+        match len {
+            Some(len) => {
+                spans.push((i, i + len));
+                i += len;
+            }
+            None => i += c.len_utf8(),
+        }
+    }
+    spans
+}
+
+/// 行中包含任意一个查询字符串即可
+pub struct SubstringMatcher {
+    queries: Vec<String>,
+    ignore_case: bool,
+}
+
+impl SubstringMatcher {
+    pub fn new(queries: &[String], ignore_case: bool) -> Self {
+        SubstringMatcher {
+            queries: prepare_queries(queries, ignore_case),
+            ignore_case,
+        }
+    }
+}
+
+impl Matcher for SubstringMatcher {
+    fn is_match(&self, line: &str) -> bool {
+        with_case(line, self.ignore_case, |line| {
+            self.queries
+                .iter()
+                .any(|query| line.contains(query.as_str()))
+        })
+    }
+
+    fn find_spans(&self, line: &str) -> Vec<(usize, usize)> {
+        literal_spans(line, &self.queries, self.ignore_case, |_, _| true)
+    }
+}
+
+/**
+ * 与 [`SubstringMatcher`] 的结果相同，但把所有查询字符串构建成一个 Aho-Corasick 自动机，
+ * 每一行只需要扫描一遍，不会随着查询字符串的数量线性变慢
+ */
+#[cfg(feature = "aho-corasick")]
+pub struct AhoCorasickMatcher {
+    automaton: aho_corasick::AhoCorasick,
+    /// 只用于计算匹配的范围，与 `SubstringMatcher` 的规则保持一致
+    queries: Vec<String>,
+    ignore_case: bool,
+}
+
+#[cfg(feature = "aho-corasick")]
+impl AhoCorasickMatcher {
+    /// 查询字符串太多或太长时自动机可能超出大小限制而构建失败，调用方可以退回到 [`SubstringMatcher`]
+    pub fn new(queries: &[String], ignore_case: bool) -> Result<Self, aho_corasick::BuildError> {
+        // 与 SubstringMatcher 一样先把查询字符串和行都转换成小写，而不是只处理 ASCII 的大小写
+        let queries = prepare_queries(queries, ignore_case);
+        let automaton = aho_corasick::AhoCorasick::new(&queries)?;
+        Ok(AhoCorasickMatcher {
+            automaton,
+            queries,
+            ignore_case,
+        })
+    }
+}
+
+#[cfg(feature = "aho-corasick")]
+impl Matcher for AhoCorasickMatcher {
+    fn is_match(&self, line: &str) -> bool {
+        with_case(line, self.ignore_case, |line| self.automaton.is_match(line))
+    }
+
+    fn find_spans(&self, line: &str) -> Vec<(usize, usize)> {
+        if !self.is_match(line) {
+            return Vec::new();
+        }
+        literal_spans(line, &self.queries, self.ignore_case, |_, _| true)
+    }
+}
+
+/// 查询字符串必须作为完整的单词出现，两侧不能紧挨着字母、数字或下划线
+pub struct WordMatcher {
+    queries: Vec<String>,
+    ignore_case: bool,
+}
+
+impl WordMatcher {
+    pub fn new(queries: &[String], ignore_case: bool) -> Self {
+        WordMatcher {
+            queries: prepare_queries(queries, ignore_case),
+            ignore_case,
+        }
+    }
+}
+
+impl Matcher for WordMatcher {
+    fn is_match(&self, line: &str) -> bool {
+        with_case(line, self.ignore_case, |line| {
+            self.queries.iter().any(|query| contains_word(line, query))
+        })
+    }
+
+    fn find_spans(&self, line: &str) -> Vec<(usize, usize)> {
+        literal_spans(line, &self.queries, self.ignore_case, |start, end| {
+            word_bounded(line, start, end)
+        })
+    }
+}
+
+/// 锚定在行首、行尾或者整行的匹配，`word` 为 true 时锚定的另一侧也必须是单词边界
+pub struct AnchoredMatcher {
+    queries: Vec<String>,
+    ignore_case: bool,
+    anchor: Anchor,
+    word: bool,
+}
+
+impl AnchoredMatcher {
+    pub fn new(queries: &[String], ignore_case: bool, anchor: Anchor, word: bool) -> Self {
+        AnchoredMatcher {
+            queries: prepare_queries(queries, ignore_case),
+            ignore_case,
+            anchor,
+            word,
+        }
+    }
+}
+
+impl Matcher for AnchoredMatcher {
+    fn is_match(&self, line: &str) -> bool {
+        with_case(line, self.ignore_case, |line| {
+            self.queries.iter().any(|query| match self.anchor {
+                Anchor::None if self.word => contains_word(line, query),
+                Anchor::None => line.contains(query.as_str()),
+                Anchor::Start => {
+                    line.starts_with(query.as_str())
+                        && (!self.word || !starts_with_word_char(&line[query.len()..]))
+                }
+                Anchor::End => {
+                    line.ends_with(query.as_str())
+                        && (!self.word || !ends_with_word_char(&line[..line.len() - query.len()]))
+                }
+                Anchor::Exact => line == query,
+            })
+        })
+    }
+
+    fn find_spans(&self, line: &str) -> Vec<(usize, usize)> {
+        literal_spans(line, &self.queries, self.ignore_case, |start, end| {
+            let anchored = match self.anchor {
+                Anchor::None => true,
+                Anchor::Start => start == 0,
+                Anchor::End => end == line.len(),
+                Anchor::Exact => start == 0 && end == line.len(),
+            };
+            anchored && (!self.word || word_bounded(line, start, end))
+        })
+    }
+}
+
+/// 正则匹配，锚定、单词边界和大小写都已经在编译正则时处理好了
+pub struct RegexMatcher(Regex);
+
+impl RegexMatcher {
+    pub fn new(regex: Regex) -> Self {
+        RegexMatcher(regex)
+    }
+}
+
+impl Matcher for RegexMatcher {
+    fn is_match(&self, line: &str) -> bool {
+        self.0.is_match(line)
+    }
+
+    fn find_spans(&self, line: &str) -> Vec<(usize, usize)> {
+        self.0
+            .find_iter(line)
+            .map(|m| (m.start(), m.end()))
+            .collect()
+    }
+}
+
+/**
+ * 先做一次普通的子串查找，行中不包含 `literal` 时直接判定不匹配，包含时才交给被包装的匹配器
+ *
+ * 正则引擎逐行匹配的开销比 `contains` 大得多，大部分行都不匹配时可以省掉绝大多数正则匹配。
+ * `literal` 必须是每个匹配都会包含的字面量，否则会漏掉结果，通常由 [`required_literal`] 得到
+ */
+pub struct PrefilterMatcher {
+    literal: String,
+    ignore_case: bool,
+    inner: Box<dyn Matcher>,
+}
+
+impl PrefilterMatcher {
+    pub fn new(literal: &str, ignore_case: bool, inner: Box<dyn Matcher>) -> Self {
+        PrefilterMatcher {
+            literal: prepare_queries(&[literal.to_string()], ignore_case).remove(0),
+            ignore_case,
+            inner,
+        }
+    }
+}
+
+impl Matcher for PrefilterMatcher {
+    fn is_match(&self, line: &str) -> bool {
+        with_case(line, self.ignore_case, |line| line.contains(&self.literal))
+            && self.inner.is_match(line)
+    }
+
+    fn find_spans(&self, line: &str) -> Vec<(usize, usize)> {
+        if !with_case(line, self.ignore_case, |line| line.contains(&self.literal)) {
+            return Vec::new();
+        }
+        self.inner.find_spans(line)
+    }
+}
+
+/**
+ * 从正则表达式中找出每个匹配都必须包含的一段字面量，有多段时返回最长的一段，找不到时返回 `None`
+ *
+ * 只做简单的分析，不确定时宁可放弃：
+ * - 顶层出现 `|`、内联标志（例如 `(?i)`）或者 `\x`、`\p` 这类转义时直接返回 `None`
+ * - 分组、字符类、`.`、`\d` 这样的转义会打断字面量
+ * - 后面跟着 `?`、`*`、`{` 的字符可能不出现，不算在字面量中
+ */
+pub fn required_literal(pattern: &str) -> Option<String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut runs = Vec::new();
+    let mut current = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        // 当前位置的字面字符（不是字面字符时为 None），以及下一个元素的位置
+        let (literal, next) = match chars[i] {
+            '\\' => match chars.get(i + 1) {
+                Some(&c) if c.is_ascii_punctuation() => (Some(c), i + 2),
+                Some('x' | 'u' | 'U' | 'p' | 'P') | None => return None,
+                Some(_) => (None, i + 2),
+            },
+            '(' => {
+                if chars.get(i + 1) == Some(&'?') && chars.get(i + 2) != Some(&':') {
+                    return None;
+                }
+                (None, skip_group(&chars, i)?)
+            }
+            '[' => (None, skip_class(&chars, i)?),
+            '{' => (None, i + chars[i..].iter().position(|&c| c == '}')? + 1),
+            '|' => return None,
+            '.' | '^' | '$' | '*' | '+' | '?' | ')' | ']' | '}' => (None, i + 1),
+            c => (Some(c), i + 1),
+        };
+        let optional = matches!(chars.get(next), Some('?' | '*' | '{'));
+        match literal {
+            Some(c) if !optional => current.push(c),
+            _ => runs.push(std::mem::take(&mut current)),
+        }
+        i = next;
+    }
+    runs.push(current);
+    runs.into_iter()
+        .max_by_key(|run| run.chars().count())
+        .filter(|run| !run.is_empty())
+}
+
+/// `chars[start]` 是 `(`，返回与它配对的 `)` 之后的位置
+fn skip_group(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '[' => {
+                i = skip_class(chars, i)?;
+                continue;
+            }
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// `chars[start]` 是 `[`，返回字符类结束之后的位置，支持 `[[:alpha:]]` 这样的嵌套
+fn skip_class(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    // 紧跟在开头（或者 `^` 之后）的 `]` 是普通字符
+    if chars.get(i) == Some(&'^') {
+        i += 1;
+    }
+    if chars.get(i) == Some(&']') {
+        i += 1;
+    }
+    let mut depth = 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// 每个被包装的匹配器都匹配时才算匹配，与查询字符串的顺序无关
+pub struct AllMatcher(pub Vec<Box<dyn Matcher>>);
+
+impl Matcher for AllMatcher {
+    fn is_match(&self, line: &str) -> bool {
+        self.0.iter().all(|matcher| matcher.is_match(line))
+    }
+
+    /// 所有匹配器的范围合并在一起，同一段内容被多个查询字符串命中时只算一次
+    fn find_spans(&self, line: &str) -> Vec<(usize, usize)> {
+        if !self.is_match(line) {
+            return Vec::new();
+        }
+        let mut spans: Vec<(usize, usize)> = self
+            .0
+            .iter()
+            .flat_map(|matcher| matcher.find_spans(line))
+            .collect();
+        // 同一位置开始的范围先取最长的，与其它匹配器的扫描规则一致
+        spans.sort_by_key(|&(start, end)| (start, std::cmp::Reverse(end)));
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(spans.len());
+        for span in spans {
+            if merged.last().is_none_or(|last| span.0 >= last.1) {
+                merged.push(span);
+            }
+        }
+        merged
+    }
+}
+
+/// 反向匹配：被包装的匹配器不匹配的行才算匹配
+pub struct InvertMatcher(pub Box<dyn Matcher>);
+
+impl Matcher for InvertMatcher {
+    fn is_match(&self, line: &str) -> bool {
+        !self.0.is_match(line)
+    }
+
+    /// 反向匹配选出的行中没有被匹配的内容
+    fn find_spans(&self, _line: &str) -> Vec<(usize, usize)> {
+        Vec::new()
+    }
+}
+
+/// 先用 [`crate::normalize_whitespace`] 规范化行中的空白，再交给被包装的匹配器
+pub struct NormalizedMatcher(pub Box<dyn Matcher>);
+
+impl Matcher for NormalizedMatcher {
+    fn is_match(&self, line: &str) -> bool {
+        self.0.is_match(&crate::normalize_whitespace(line))
+    }
+
+    /// 在规范化之后的行中匹配，再把范围映射回原始的行
+    fn find_spans(&self, line: &str) -> Vec<(usize, usize)> {
+        let (normalized, origins) = normalize_with_origins(line);
+        let original = |start: usize, end: usize| {
+            if start == end {
+                let at = origins.get(start).map_or(line.len(), |origin| origin.0);
+                (at, at)
+            } else {
+                (origins[start].0, origins[end - 1].1)
+            }
+        };
+        self.0
+            .find_spans(&normalized)
+            .into_iter()
+            .map(|(start, end)| original(start, end))
+            .collect()
+    }
+}
+
+/**
+ * 与 [`crate::normalize_whitespace`] 的结果相同，同时返回规范化之后每个字节对应的原始字符的范围
+ *
+ * 单词中的字节对应自己所在的字符；合并出来的空格对应原始空白中的第一个字符
+ */
+fn normalize_with_origins(line: &str) -> (String, Vec<(usize, usize)>) {
+    let mut normalized = String::with_capacity(line.len());
+    let mut origins = Vec::with_capacity(line.len());
+    // 上一个单词之后的第一个空白字符的范围，遇到下一个单词时才输出成一个空格
+    let mut gap = None;
+    for (i, c) in line.char_indices() {
+        let end = i + c.len_utf8();
+        if c.is_whitespace() {
+            if !normalized.is_empty() && gap.is_none() {
+                gap = Some((i, end));
+            }
+            continue;
+        }
+        if let Some(space) = gap.take() {
+            normalized.push(' ');
+            origins.push(space);
+        }
+        normalized.push(c);
+        origins.extend((i..end).map(|_| (i, end)));
+    }
+    (normalized, origins)
+}
+
+/**
+ * 使用指定的匹配器逐行搜索，返回 `(行号, 行内容)`，行号从 1 开始
+ */
+pub fn search_with<'a>(matcher: &dyn Matcher, content: &'a str) -> Vec<(usize, &'a str)> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| matcher.is_match(line))
+        .map(|(i, line)| (i + 1, line))
+        .collect()
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn starts_with_word_char(s: &str) -> bool {
+    s.chars().next().is_some_and(is_word_char)
+}
+
+fn ends_with_word_char(s: &str) -> bool {
+    s.chars().next_back().is_some_and(is_word_char)
+}
+
+/// `line[start..end]` 两侧都不是字母、数字或下划线
+fn word_bounded(line: &str, start: usize, end: usize) -> bool {
+    !ends_with_word_char(&line[..start]) && !starts_with_word_char(&line[end..])
+}
+
+/// 行中是否包含作为完整单词出现的查询字符串
+fn contains_word(line: &str, query: &str) -> bool {
+    line.match_indices(query).any(|(i, m)| {
+        !ends_with_word_char(&line[..i]) && !starts_with_word_char(&line[i + m.len()..])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &str = "Rust is fast\ntrust me\nrusty nails\nrust";
+
+    fn queries(queries: &[&str]) -> Vec<String> {
+        queries.iter().map(|query| query.to_string()).collect()
+    }
+
+    #[test]
+    fn substring_matcher() {
+        let matcher = SubstringMatcher::new(&queries(&["rust"]), false);
+        assert_eq!(
+            vec![(2, "trust me"), (3, "rusty nails"), (4, "rust")],
+            search_with(&matcher, CONTENT)
+        );
+        let matcher = SubstringMatcher::new(&queries(&["RUST", "fast"]), true);
+        assert_eq!(4, search_with(&matcher, CONTENT).len());
+    }
+
+    #[cfg(feature = "aho-corasick")]
+    #[test]
+    fn aho_corasick_matches_substring_matcher() {
+        for ignore_case in [false, true] {
+            let queries = queries(&["RUST", "fast", "nails", "a.b"]);
+            let automaton = AhoCorasickMatcher::new(&queries, ignore_case).unwrap();
+            let substring = SubstringMatcher::new(&queries, ignore_case);
+            assert_eq!(
+                search_with(&substring, CONTENT),
+                search_with(&automaton, CONTENT)
+            );
+        }
+    }
+
+    #[test]
+    fn spans_follow_matching_rules() {
+        let line = "cat concatenate Cat";
+        let matcher = SubstringMatcher::new(&queries(&["cat"]), true);
+        assert_eq!(vec![(0, 3), (7, 10), (16, 19)], matcher.find_spans(line));
+        let matcher = WordMatcher::new(&queries(&["cat"]), true);
+        assert_eq!(vec![(0, 3), (16, 19)], matcher.find_spans(line));
+        let matcher = AnchoredMatcher::new(&queries(&["cat"]), true, Anchor::End, false);
+        assert_eq!(vec![(16, 19)], matcher.find_spans(line));
+        assert!(InvertMatcher(Box::new(matcher)).find_spans(line).is_empty());
+
+        // 多个查询字符串命中重叠的内容时只算一次
+        let all = AllMatcher(vec![
+            Box::new(SubstringMatcher::new(&queries(&["conca"]), false)),
+            Box::new(SubstringMatcher::new(&queries(&["cat"]), false)),
+        ]);
+        assert_eq!(vec![(0, 3), (4, 9)], all.find_spans(line));
+
+        let matcher = NormalizedMatcher(Box::new(SubstringMatcher::new(&queries(&["a b"]), false)));
+        assert_eq!(vec![(2, 7)], matcher.find_spans("  a \t b  "));
+    }
+
+    #[test]
+    fn word_matcher() {
+        let matcher = WordMatcher::new(&queries(&["rust"]), true);
+        assert_eq!(
+            vec![(1, "Rust is fast"), (4, "rust")],
+            search_with(&matcher, CONTENT)
+        );
+    }
+
+    #[test]
+    fn anchored_matcher() {
+        let matcher = AnchoredMatcher::new(&queries(&["rust"]), true, Anchor::Start, false);
+        assert_eq!(
+            vec![(1, "Rust is fast"), (3, "rusty nails"), (4, "rust")],
+            search_with(&matcher, CONTENT)
+        );
+        let matcher = AnchoredMatcher::new(&queries(&["rust"]), true, Anchor::Start, true);
+        assert_eq!(2, search_with(&matcher, CONTENT).len());
+        let matcher = AnchoredMatcher::new(&queries(&["rust"]), false, Anchor::Exact, false);
+        assert_eq!(vec![(4, "rust")], search_with(&matcher, CONTENT));
+    }
+
+    #[test]
+    fn regex_matcher() {
+        let matcher = RegexMatcher::new(Regex::new(r"^\w+ (is|me)").unwrap());
+        assert_eq!(
+            vec![(1, "Rust is fast"), (2, "trust me")],
+            search_with(&matcher, CONTENT)
+        );
+    }
+
+    #[test]
+    fn required_literals() {
+        let literal = |pattern: &str| required_literal(pattern);
+        assert_eq!(
+            Some("eedle in line "),
+            literal(r"[Nn]eedle in line \d+").as_deref()
+        );
+        assert_eq!(
+            Some("error: "),
+            literal(r"error: (disk|net) full").as_deref()
+        );
+        assert_eq!(Some("colo"), literal("colou?r").as_deref());
+        assert_eq!(Some("bcd"), literal("a{2}bcd").as_deref());
+        assert_eq!(Some(".rs"), literal(r"\.rs$").as_deref());
+        assert_eq!(Some("ab"), literal("ab+c").as_deref());
+        assert_eq!(Some("x"), literal("[]a]x").as_deref());
+        assert_eq!(None, literal("foo|bar"));
+        assert_eq!(None, literal("(?i)abc"));
+        assert_eq!(None, literal(r"\x41BC"));
+        assert_eq!(None, literal(r"\d+.*"));
+    }
+
+    #[test]
+    fn prefilter_does_not_change_results() {
+        let regex = Regex::new(r"[Rr]ust( is)?").unwrap();
+        let plain = RegexMatcher::new(regex.clone());
+        let filtered = PrefilterMatcher::new("ust", false, Box::new(RegexMatcher::new(regex)));
+        assert_eq!(
+            search_with(&plain, CONTENT),
+            search_with(&filtered, CONTENT)
+        );
+
+        // 忽略大小写时字面量和行都按小写比较
+        let regex = Regex::new(r"(?i)rust\b").unwrap();
+        let plain = RegexMatcher::new(regex.clone());
+        let filtered = PrefilterMatcher::new("RUST", true, Box::new(RegexMatcher::new(regex)));
+        assert_eq!(3, search_with(&filtered, CONTENT).len());
+        assert_eq!(
+            search_with(&plain, CONTENT),
+            search_with(&filtered, CONTENT)
+        );
+    }
+
+    #[test]
+    fn wrappers_compose() {
+        let inner = Box::new(SubstringMatcher::new(&queries(&["rust"]), false));
+        let matcher = InvertMatcher(inner);
+        assert_eq!(vec![(1, "Rust is fast")], search_with(&matcher, CONTENT));
+
+        let all = AllMatcher(vec![
+            Box::new(SubstringMatcher::new(&queries(&["rust"]), true)),
+            Box::new(WordMatcher::new(&queries(&["is"]), false)),
+        ]);
+        assert_eq!(vec![(1, "Rust is fast")], search_with(&all, CONTENT));
+
+        let inner = Box::new(SubstringMatcher::new(&queries(&["a b"]), false));
+        let matcher = NormalizedMatcher(inner);
+        assert!(matcher.is_match("  a \t b  "));
+        assert!(!InvertMatcher(Box::new(matcher)).is_match("a    b"));
+    }
+}
@@ -0,0 +1,260 @@
+/// 不相邻的上下文分组之间的分隔行，与 grep 保持一致
+pub const GROUP_SEPARATOR: &str = "--";
+
+/**
+ * 格式化带上下文的搜索结果
+ *
+ * 每个分组是一个匹配行及其前后的上下文 `(行号, 行内容)`：
+ * - 重叠或者首尾相接的分组会合并成一个分组，重复的行只输出一次
+ * - 中间有间隔的分组之间输出一行 `--`
+ *
+ * 不区分匹配行和上下文行，每一行都输出为 `行号:内容`
+ */
+pub fn format_context_output(groups: Vec<Vec<(usize, &str)>>) -> String {
+    let mut matches: Vec<usize> = groups.iter().flatten().map(|&(number, _)| number).collect();
+    matches.sort_unstable();
+    format_context_output_with(groups, &matches, &ContextFormat::default())
+}
+
+/// [`format_context_output_with`] 的输出格式
+#[derive(Debug, Clone, Copy)]
+pub struct ContextFormat<'a> {
+    /// 不相邻的分组之间的分隔行，空字符串表示不输出分隔行
+    pub separator: &'a str,
+    /// 是否在每行前面输出行号
+    pub line_number: bool,
+    /// 在每行前面输出的文件路径，分隔行除外
+    pub path: Option<&'a str>,
+}
+
+impl Default for ContextFormat<'_> {
+    fn default() -> Self {
+        ContextFormat {
+            separator: GROUP_SEPARATOR,
+            line_number: true,
+            path: None,
+        }
+    }
+}
+
+/**
+ * 与 [`format_context_output`] 相同，但区分匹配行和上下文行
+ *
+ * `matches` 是按升序排列的匹配行的行号。与 grep 一致，匹配行的路径和行号后面跟 `:`，
+ * 上下文行跟 `-`，例如 `3:match` 和 `4-context`
+ */
+pub fn format_context_output_with(
+    mut groups: Vec<Vec<(usize, &str)>>,
+    matches: &[usize],
+    format: &ContextFormat<'_>,
+) -> String {
+    let separator = format.separator;
+    groups.retain(|group| !group.is_empty());
+    groups.sort_by_key(|group| group[0].0);
+
+    let mut output = String::new();
+    // 上一个已经输出的行号
+    let mut last: Option<usize> = None;
+    for group in groups {
+        if let Some(last) = last {
+            if group[0].0 > last + 1 && !separator.is_empty() {
+                output.push_str(separator);
+                output.push('\n');
+            }
+        }
+
+        for (number, line) in group {
+            if last.is_some_and(|last| number <= last) {
+                continue;
+            }
+            let mark = if matches.binary_search(&number).is_ok() {
+                ':'
+            } else {
+                '-'
+            };
+            if let Some(path) = format.path {
+                output.push_str(&format!("{path}{mark}"));
+            }
+            if format.line_number {
+                output.push_str(&format!("{number}{mark}"));
+            }
+            output.push_str(line);
+            output.push('\n');
+            last = Some(number);
+        }
+    }
+    output
+}
+
+/// 行被截断时末尾追加的标记，它本身也算一个字符
+pub const ELLIPSIS: char = '…';
+
+/**
+ * 把一行截断为最多 `width` 个可见字符，超出时最后一个字符换成 [`ELLIPSIS`]
+ *
+ * `\x1b[...m` 形式的 ANSI 转义码不计入宽度，也不会被从中间截断。截断的位置可能在高亮的内容中间，
+ * 所以原来包含转义码时在省略号之前补上一个恢复默认样式的转义码，避免颜色延续到后面的输出中
+ */
+pub fn truncate_line(line: &str, width: usize) -> String {
+    let mut visible = 0;
+    let mut end = None;
+    let mut has_escape = false;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\x1b' {
+            has_escape = true;
+            // 跳过整个转义序列，直到结尾的字母
+            for (_, c) in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
+        visible += 1;
+        if visible == width {
+            end = Some(i);
+        }
+        if visible > width {
+            break;
+        }
+    }
+    if visible <= width {
+        return line.to_string();
+    }
+
+    // 给省略号留出一个字符的位置
+    let mut truncated = String::from(&line[..end.unwrap_or(0)]);
+    if has_escape {
+        truncated.push_str("\x1b[0m");
+    }
+    truncated.push(ELLIPSIS);
+    truncated
+}
+
+/**
+ * 转义 JSON 字符串中的特殊字符，返回值不包含两侧的引号
+ */
+pub fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            // 其它控制字符使用 \uXXXX 的形式
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/**
+ * 将一个匹配序列化为一行 JSON 对象
+ */
+pub fn json_match(path: &str, line_number: usize, column: usize, text: &str) -> String {
+    format!(
+        "{{\"path\":\"{}\",\"line_number\":{line_number},\"column\":{column},\"text\":\"{}\"}}",
+        json_escape(path),
+        json_escape(text)
+    )
+}
+
+/**
+ * 有匹配的二进制文件序列化为一行 JSON 对象，只有路径，没有具体的行
+ */
+pub fn json_binary_match(path: &str) -> String {
+    format!("{{\"path\":\"{}\",\"binary\":true}}", json_escape(path))
+}
+
+/**
+ * 把已经序列化好的 JSON 值拼成一个数组，例如 `json_match` 返回的对象
+ */
+pub fn json_array<'a>(items: impl IntoIterator<Item = &'a str>) -> String {
+    let items: Vec<&str> = items.into_iter().collect();
+    format!("[{}]", items.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlapping_groups_are_merged() {
+        let groups = vec![
+            vec![(1, "a"), (2, "b"), (3, "c")],
+            vec![(2, "b"), (3, "c")],
+        ];
+        assert_eq!("1:a\n2:b\n3:c\n", format_context_output(groups));
+    }
+
+    #[test]
+    fn touching_groups_are_merged() {
+        let groups = vec![vec![(1, "a"), (2, "b")], vec![(3, "c"), (4, "d")]];
+        assert_eq!("1:a\n2:b\n3:c\n4:d\n", format_context_output(groups));
+    }
+
+    #[test]
+    fn far_apart_groups_are_separated() {
+        let groups = vec![vec![(5, "e"), (6, "f")], vec![(1, "a"), (2, "b")]];
+        assert_eq!("1:a\n2:b\n--\n5:e\n6:f\n", format_context_output(groups));
+    }
+
+    #[test]
+    fn custom_and_empty_separators() {
+        let groups = || vec![vec![(1, "a")], vec![(5, "e")]];
+        let format = |separator| ContextFormat {
+            separator,
+            ..ContextFormat::default()
+        };
+        assert_eq!(
+            "1:a\n##\n5:e\n",
+            format_context_output_with(groups(), &[1, 5], &format("##"))
+        );
+        assert_eq!(
+            "1:a\n5:e\n",
+            format_context_output_with(groups(), &[1, 5], &format(""))
+        );
+    }
+
+    #[test]
+    fn context_lines_are_marked() {
+        let groups = || vec![vec![(1, "a"), (2, "b")], vec![(4, "d"), (5, "e")]];
+        assert_eq!(
+            "1:a\n2-b\n--\n4-d\n5:e\n",
+            format_context_output_with(groups(), &[1, 5], &ContextFormat::default())
+        );
+        let format = ContextFormat {
+            line_number: false,
+            path: Some("poem.txt"),
+            ..ContextFormat::default()
+        };
+        assert_eq!(
+            "poem.txt:a\npoem.txt-b\n--\npoem.txt-d\npoem.txt:e\n",
+            format_context_output_with(groups(), &[1, 5], &format)
+        );
+    }
+
+    #[test]
+    fn truncate_skips_escape_sequences() {
+        assert_eq!("short", truncate_line("short", 5));
+        assert_eq!("abcd…", truncate_line("abcdefgh", 5));
+        assert_eq!("日本…", truncate_line("日本語のテキスト", 3));
+
+        // 转义码不计入宽度，截断在高亮内容中间时补上恢复样式的转义码
+        let highlighted = "ab\x1b[1;31mcdefgh\x1b[0mij";
+        assert_eq!("ab\x1b[1;31mcd\x1b[0m…", truncate_line(highlighted, 5));
+        assert_eq!(highlighted, truncate_line(highlighted, 10));
+    }
+
+    #[test]
+    fn json_match_escapes_text() {
+        assert_eq!(
+            r#"{"path":"src/a.rs","line_number":3,"column":5,"text":"say \"hi\"\t\\ \u0001"}"#,
+            json_match("src/a.rs", 3, 5, "say \"hi\"\t\\ \u{1}")
+        );
+    }
+}
@@ -0,0 +1,997 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    io,
+    ops::Bound,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use mini_redis::Frame;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+
+use crate::connection::{describe, Connection};
+
+/**
+ * 数据库中保存的值
+ *
+ * 执行过 `INCR` 之类命令的键直接保存为整数，连续自增时不需要每次都重新解析字符串
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Str(Bytes),
+    Int(i64),
+}
+
+impl Value {
+    /// `GET` 时整数同样以字符串的形式返回，与 redis 一致
+    fn to_bytes(&self) -> Bytes {
+        match self {
+            Value::Str(bytes) => bytes.clone(),
+            Value::Int(n) => Bytes::from(n.to_string()),
+        }
+    }
+
+    fn to_int(&self) -> Result<i64, DbError> {
+        match self {
+            Value::Str(bytes) => parse_int(bytes).ok_or(DbError::NotAnInteger),
+            Value::Int(n) => Ok(*n),
+        }
+    }
+}
+
+/// 数据库中的一项，`expires_at` 为 `None` 表示永不过期
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    value: Value,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+/// `SET` 的 `NX` / `XX` 选项
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SetCondition {
+    #[default]
+    Always,
+    /// NX：只有键不存在时才设置，常用来实现简单的锁
+    IfAbsent,
+    /// XX：只有键已经存在时才设置
+    IfPresent,
+}
+
+/// 命令执行中可能出现的错误，错误信息与 redis 保持一致
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbError {
+    #[error("ERR value is not an integer or out of range")]
+    NotAnInteger,
+    #[error("ERR increment or decrement would overflow")]
+    Overflow,
+    /// 过期时间太大，加上当前时间之后超出了 `Instant` 能表示的范围
+    #[error("ERR invalid expire time in '{0}' command")]
+    InvalidExpireTime(&'static str),
+}
+
+/**
+ * 多个连接共享的键值数据库
+ *
+ * 内部是 `Arc<Mutex<..>>`，克隆之后指向同一份数据，每个连接的任务持有一份即可。
+ * 过期的键采用惰性删除：不启动后台任务扫描，而是在访问到它时才删除。
+ * 键按字典序保存，`SCAN` 可以从上一批的最后一个键继续向后遍历
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Db {
+    entries: Arc<Mutex<BTreeMap<String, Entry>>>,
+}
+
+impl Db {
+    pub fn new() -> Db {
+        Db::default()
+    }
+
+    /**
+     * 执行一条命令并返回响应帧
+     *
+     * 命令是由多个 bulk 字符串组成的数组帧，例如 `*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n`，
+     * 命令名不区分大小写。格式错误或者不支持的命令返回 `Frame::Error`，不会让连接断开
+     *
+     * 注意：与 redis 不同，`INCR`、`DECRBY`、`TTL` 等命令的结果为负数时回复的是简单字符串 `+-4`，
+     * 而不是整数 `:-4`。mini-redis 的 `Frame::Integer` 只能保存无符号整数，它的解析器也不接受负数，
+     * 客户端需要把这种回复当作字符串解析
+     */
+    pub fn execute(&self, frame: Frame) -> Frame {
+        let args = match parse_args(frame) {
+            Some(args) if !args.is_empty() => args,
+            _ => return Frame::Error("ERR protocol error".to_string()),
+        };
+        let name = String::from_utf8_lossy(&args[0]).to_lowercase();
+        let key = args
+            .get(1)
+            .map(|key| String::from_utf8_lossy(key).into_owned());
+
+        match (name.as_str(), key, args.len()) {
+            // 很多客户端连接后先发送 PING 检查服务是否可用
+            ("ping", _, 1) => Frame::Simple("PONG".to_string()),
+            ("ping" | "echo", _, 2) => Frame::Bulk(args[1].clone()),
+            ("get", Some(key), 2) => match self.get(&key) {
+                Some(value) => Frame::Bulk(value),
+                None => Frame::Null,
+            },
+            // MSET k1 v1 k2 v2 ...，键和值必须成对出现
+            ("mset", Some(_), n) if n >= 3 && !n.is_multiple_of(2) => {
+                let pairs = args[1..]
+                    .chunks(2)
+                    .map(|pair| {
+                        (
+                            String::from_utf8_lossy(&pair[0]).into_owned(),
+                            pair[1].clone(),
+                        )
+                    })
+                    .collect();
+                self.mset(pairs);
+                Frame::Simple("OK".to_string())
+            }
+            // 按照请求的顺序回复每个键的值，不存在的键对应 Null
+            ("mget", Some(_), _) => {
+                let keys: Vec<String> = args[1..]
+                    .iter()
+                    .map(|key| String::from_utf8_lossy(key).into_owned())
+                    .collect();
+                Frame::Array(
+                    self.mget(&keys)
+                        .into_iter()
+                        .map(|value| value.map_or(Frame::Null, Frame::Bulk))
+                        .collect(),
+                )
+            }
+            // 设置成功返回 OK，条件不满足时返回 Null
+            ("set", Some(key), 3 | 4) => {
+                let condition = match args.get(3).map(|option| option.to_ascii_lowercase()) {
+                    None => SetCondition::Always,
+                    Some(option) if option == b"nx" => SetCondition::IfAbsent,
+                    Some(option) if option == b"xx" => SetCondition::IfPresent,
+                    Some(_) => return Frame::Error("ERR syntax error".to_string()),
+                };
+                if self.set_if(key, args[2].clone(), condition) {
+                    Frame::Simple("OK".to_string())
+                } else {
+                    Frame::Null
+                }
+            }
+            // 设置值和过期时间在同一次加锁中完成，不会出现值已经写入但还没有过期时间的窗口
+            ("setex", Some(key), 4) => match parse_int(&args[2]) {
+                Some(seconds) if seconds > 0 => {
+                    match self.set_ex(key, args[3].clone(), Duration::from_secs(seconds as u64)) {
+                        Ok(()) => Frame::Simple("OK".to_string()),
+                        Err(e) => Frame::Error(e.to_string()),
+                    }
+                }
+                Some(_) => Frame::Error("ERR invalid expire time in 'setex' command".to_string()),
+                None => Frame::Error(DbError::NotAnInteger.to_string()),
+            },
+            ("getdel", Some(key), 2) => match self.get_del(&key) {
+                Some(value) => Frame::Bulk(value),
+                None => Frame::Null,
+            },
+            // 键不存在时回复 0；过期时间不是正数时与 redis 一样直接删除这个键
+            ("expire", Some(key), 3) => match parse_int(&args[2]) {
+                Some(seconds) if seconds > 0 => {
+                    match self.expire(&key, Duration::from_secs(seconds as u64)) {
+                        Ok(found) => Frame::Integer(u64::from(found)),
+                        Err(e) => Frame::Error(e.to_string()),
+                    }
+                }
+                Some(_) => Frame::Integer(u64::from(self.get_del(&key).is_some())),
+                None => Frame::Error(DbError::NotAnInteger.to_string()),
+            },
+            // 键不存在回复 -2，没有过期时间回复 -1
+            ("ttl", Some(key), 2) => reply(Ok(match self.expiry(&key) {
+                None => -2,
+                Some(None) => -1,
+                Some(Some(ttl)) => ttl.as_secs_f64().round() as i64,
+            })),
+            ("persist", Some(key), 2) => Frame::Integer(u64::from(self.persist(&key))),
+            ("keys", Some(pattern), 2) => keys_frame(self.keys(&pattern)),
+            // SCAN cursor [MATCH pattern] [COUNT count]，回复下一次的游标和这一批的键
+            ("scan", Some(_), 2 | 4 | 6) => self.scan_command(&args[1..]),
+            ("strlen", Some(key), 2) => Frame::Integer(self.strlen(&key) as u64),
+            ("getrange", Some(key), 4) => match (parse_int(&args[2]), parse_int(&args[3])) {
+                (Some(start), Some(end)) => Frame::Bulk(self.get_range(&key, start, end)),
+                _ => Frame::Error(DbError::NotAnInteger.to_string()),
+            },
+            ("incr", Some(key), 2) => reply(self.incr_by(&key, 1)),
+            ("decr", Some(key), 2) => reply(self.incr_by(&key, -1)),
+            ("incrby", Some(key), 3) => match parse_int(&args[2]) {
+                Some(delta) => reply(self.incr_by(&key, delta)),
+                None => Frame::Error(DbError::NotAnInteger.to_string()),
+            },
+            // -i64::MIN 会溢出，需要用 checked_neg
+            ("decrby", Some(key), 3) => match parse_int(&args[2]).map(i64::checked_neg) {
+                Some(Some(delta)) => reply(self.incr_by(&key, delta)),
+                Some(None) => Frame::Error(DbError::Overflow.to_string()),
+                None => Frame::Error(DbError::NotAnInteger.to_string()),
+            },
+            (
+                "ping" | "echo" | "get" | "mset" | "mget" | "set" | "setex" | "getdel" | "expire"
+                | "ttl" | "persist" | "keys" | "scan" | "strlen" | "getrange" | "incr" | "decr"
+                | "incrby" | "decrby",
+                _,
+                _,
+            ) => Frame::Error(format!(
+                "ERR wrong number of arguments for '{name}' command"
+            )),
+            _ => Frame::Error(format!("ERR unknown command '{name}'")),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Bytes> {
+        let mut entries = self.entries.lock().unwrap();
+        live_entry(&mut entries, key).map(|entry| entry.value.to_bytes())
+    }
+
+    /// 返回键的值并删除它，两步在同一次加锁中完成，两个连接同时 `GETDEL` 时只有一个能拿到值
+    pub fn get_del(&self, key: &str) -> Option<Bytes> {
+        let mut entries = self.entries.lock().unwrap();
+        live_entry(&mut entries, key)?;
+        entries.remove(key).map(|entry| entry.value.to_bytes())
+    }
+
+    pub fn set(&self, key: String, value: Bytes) {
+        self.set_if(key, value, SetCondition::Always);
+    }
+
+    /// 在同一次加锁中设置所有的键，其它连接不会看到只设置了一部分的状态，已有的过期时间被清除
+    pub fn mset(&self, pairs: Vec<(String, Bytes)>) {
+        let mut entries = self.entries.lock().unwrap();
+        for (key, value) in pairs {
+            let entry = Entry {
+                value: Value::Str(value),
+                expires_at: None,
+            };
+            entries.insert(key, entry);
+        }
+    }
+
+    /// 按照 `keys` 的顺序返回每个键的值，不存在或者已经过期的键为 `None`
+    pub fn mget(&self, keys: &[String]) -> Vec<Option<Bytes>> {
+        let mut entries = self.entries.lock().unwrap();
+        keys.iter()
+            .map(|key| live_entry(&mut entries, key).map(|entry| entry.value.to_bytes()))
+            .collect()
+    }
+
+    /// 所有没有过期的键中匹配 `pattern` 的键，按字典序排列，需要遍历所有的键
+    pub fn keys(&self, pattern: &str) -> Vec<String> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, entry| !entry.is_expired(now));
+        entries
+            .keys()
+            .filter(|key| key_matches(pattern, key))
+            .cloned()
+            .collect()
+    }
+
+    /**
+     * 从 `after` 之后（不包含它）按字典序最多检查 `count` 个键，返回其中没有过期并且匹配 `pattern` 的键，
+     * 以及下一批的起点，已经没有更多的键时为 `None`
+     *
+     * 每次只持有锁检查一小批键，键很多时也不会长时间阻塞其它连接。与 redis 一样，一批返回的键可能少于 `count` 个，
+     * 甚至为空；遍历期间一直存在的键一定会被返回，遍历期间新增的键可能返回也可能不返回
+     */
+    pub fn scan(
+        &self,
+        after: Option<&str>,
+        pattern: &str,
+        count: usize,
+    ) -> (Vec<String>, Option<String>) {
+        let entries = self.entries.lock().unwrap();
+        let start = match after {
+            Some(after) => Bound::Excluded(after),
+            None => Bound::Unbounded,
+        };
+        let now = Instant::now();
+        let mut batch = entries.range::<str, _>((start, Bound::Unbounded));
+        let mut keys = Vec::new();
+        let mut last = None;
+        for (key, entry) in batch.by_ref().take(count.max(1)) {
+            if !entry.is_expired(now) && key_matches(pattern, key) {
+                keys.push(key.clone());
+            }
+            last = Some(key);
+        }
+        let next = batch.next().and(last).cloned();
+        (keys, next)
+    }
+
+    fn scan_command(&self, args: &[Bytes]) -> Frame {
+        let Some(after) = decode_cursor(&args[0]) else {
+            return Frame::Error("ERR invalid cursor".to_string());
+        };
+        let mut pattern = String::from("*");
+        let mut count = SCAN_COUNT;
+        for option in args[1..].chunks(2) {
+            match option[0].to_ascii_lowercase().as_slice() {
+                b"match" => pattern = String::from_utf8_lossy(&option[1]).into_owned(),
+                b"count" => match parse_int(&option[1]) {
+                    Some(n) if n > 0 => count = n as usize,
+                    _ => return Frame::Error("ERR syntax error".to_string()),
+                },
+                _ => return Frame::Error("ERR syntax error".to_string()),
+            }
+        }
+
+        let (keys, next) = self.scan(after.as_deref(), &pattern, count);
+        let cursor = next.map_or_else(|| String::from("0"), |key| encode_cursor(&key));
+        Frame::Array(vec![Frame::Bulk(Bytes::from(cursor)), keys_frame(keys)])
+    }
+
+    /// 值的字节长度，键不存在时为 0
+    pub fn strlen(&self, key: &str) -> usize {
+        self.get(key).map_or(0, |value| value.len())
+    }
+
+    /**
+     * 返回值中 `[start, end]` 范围内的字节，两端都包含
+     *
+     * 与 redis 一致：负数表示从末尾开始计算，-1 是最后一个字节；超出范围的下标被截断到有效范围内，
+     * 范围为空时返回空字符串而不是错误。`Bytes::slice` 只增加引用计数，不会复制数据
+     */
+    pub fn get_range(&self, key: &str, start: i64, end: i64) -> Bytes {
+        let Some(value) = self.get(key) else {
+            return Bytes::new();
+        };
+        let len = value.len() as i64;
+        let resolve = |index: i64| {
+            if index < 0 {
+                (len + index).max(0)
+            } else {
+                index
+            }
+        };
+        let (start, end) = (resolve(start), resolve(end).min(len - 1));
+        if start > end {
+            return Bytes::new();
+        }
+        value.slice(start as usize..=end as usize)
+    }
+
+    /// 设置值，并在 `ttl` 之后过期；`ttl` 太大导致过期时刻溢出时返回错误，不写入
+    pub fn set_ex(&self, key: String, value: Bytes, ttl: Duration) -> Result<(), DbError> {
+        let expires_at = Instant::now()
+            .checked_add(ttl)
+            .ok_or(DbError::InvalidExpireTime("setex"))?;
+        let entry = Entry {
+            value: Value::Str(value),
+            expires_at: Some(expires_at),
+        };
+        self.entries.lock().unwrap().insert(key, entry);
+        Ok(())
+    }
+
+    /// 键剩余的存活时间，键不存在或者没有设置过期时间时返回 `None`
+    pub fn ttl(&self, key: &str) -> Option<Duration> {
+        self.expiry(key).flatten()
+    }
+
+    /// 与 [`Db::ttl`] 相同，但区分键不存在（外层 `None`）和没有过期时间（内层 `None`）
+    pub fn expiry(&self, key: &str) -> Option<Option<Duration>> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = live_entry(&mut entries, key)?;
+        Some(
+            entry
+                .expires_at
+                .map(|at| at.saturating_duration_since(Instant::now())),
+        )
+    }
+
+    /// 设置或者替换键的过期时间，键不存在时返回 false
+    ///
+    /// 过期时刻在加锁之前算好，`ttl` 太大导致溢出时返回错误，不会在持有锁时 panic
+    pub fn expire(&self, key: &str, ttl: Duration) -> Result<bool, DbError> {
+        let expires_at = Instant::now()
+            .checked_add(ttl)
+            .ok_or(DbError::InvalidExpireTime("expire"))?;
+        let mut entries = self.entries.lock().unwrap();
+        Ok(match live_entry(&mut entries, key) {
+            Some(entry) => {
+                entry.expires_at = Some(expires_at);
+                true
+            }
+            None => false,
+        })
+    }
+
+    /// 清除键的过期时间，只有键存在并且原来设置了过期时间时才返回 true
+    pub fn persist(&self, key: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        live_entry(&mut entries, key).is_some_and(|entry| entry.expires_at.take().is_some())
+    }
+
+    /**
+     * 按照条件设置键的值，返回是否真正设置了
+     *
+     * 检查键是否存在和写入在同一次加锁中完成，两个连接同时 `SET lock 1 NX` 时只有一个会成功。
+     * 与 redis 一致，`SET` 会清除原来的过期时间
+     */
+    pub fn set_if(&self, key: String, value: Bytes, condition: SetCondition) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let exists = live_entry(&mut entries, &key).is_some();
+        let allowed = match condition {
+            SetCondition::Always => true,
+            SetCondition::IfAbsent => !exists,
+            SetCondition::IfPresent => exists,
+        };
+        if allowed {
+            let entry = Entry {
+                value: Value::Str(value),
+                expires_at: None,
+            };
+            entries.insert(key, entry);
+        }
+        allowed
+    }
+
+    /**
+     * 将键的值加上 `delta` 并返回新的值，键不存在时从 0 开始
+     *
+     * 读取、计算、写入在同一次加锁中完成，多个连接同时自增也不会丢失更新。自增不会改变过期时间
+     */
+    pub fn incr_by(&self, key: &str, delta: i64) -> Result<i64, DbError> {
+        let mut entries = self.entries.lock().unwrap();
+        let (current, expires_at) = match live_entry(&mut entries, key) {
+            None => (0, None),
+            Some(entry) => (entry.value.to_int()?, entry.expires_at),
+        };
+        let next = current.checked_add(delta).ok_or(DbError::Overflow)?;
+        let entry = Entry {
+            value: Value::Int(next),
+            expires_at,
+        };
+        entries.insert(key.to_string(), entry);
+        Ok(next)
+    }
+}
+
+/**
+ * 处理一个连接：依次读取命令、执行并写回响应，直到客户端关闭连接
+ *
+ * 每条命令的响应都立即 flush，客户端发送下一条命令之前就能收到上一条的结果
+ */
+pub async fn process<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    db: Db,
+) -> mini_redis::Result<()> {
+    let mut connection = Connection::new(stream);
+    while let Some(frame) = connection.read_frame().await? {
+        println!("GOT: {}", describe(&frame));
+        let response = db.execute(frame);
+        connection.write_frame(&response).await?;
+        connection.flush().await?;
+    }
+    Ok(())
+}
+
+/**
+ * 接收连接，每个连接交给一个单独的任务处理，所有连接共享同一个 `db`
+ *
+ * `shutdown` 完成时停止接收新的连接并返回，监听的端口随之关闭，已经建立的连接不受影响
+ */
+pub async fn serve(
+    listener: TcpListener,
+    db: Db,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                let db = db.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = process(stream, db).await {
+                        eprintln!("connection error: {e}");
+                    }
+                });
+            }
+            _ = &mut shutdown => return Ok(()),
+        }
+    }
+}
+
+/// 查找没有过期的键，已经过期的键在这里被删除
+fn live_entry<'a>(entries: &'a mut BTreeMap<String, Entry>, key: &str) -> Option<&'a mut Entry> {
+    if entries
+        .get(key)
+        .is_some_and(|entry| entry.is_expired(Instant::now()))
+    {
+        entries.remove(key);
+    }
+    entries.get_mut(key)
+}
+
+/// `SCAN` 没有指定 `COUNT` 时每批检查的键的数量，与 redis 相同
+const SCAN_COUNT: usize = 10;
+
+/**
+ * `KEYS` 和 `SCAN` 使用的通配符匹配：`*` 匹配任意个字符，`?` 匹配一个字符
+ *
+ * 与 [`crate::walk::glob_match`] 不同，`/` 在键中只是普通的字符
+ */
+fn key_matches(pattern: &str, key: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = key.chars().collect();
+
+    // 记录最近一个 `*` 的位置，后面匹配失败时让它多吃一个字符再试
+    let (mut p, mut k) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while k < key.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == key[k]) {
+            p += 1;
+            k += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, k));
+            p += 1;
+        } else if let Some((star_p, star_k)) = star {
+            p = star_p + 1;
+            k = star_k + 1;
+            star = Some((star_p, star_k + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/**
+ * `SCAN` 的游标是上一批最后一个键的十六进制编码，`0` 表示从头开始，回复 `0` 表示遍历结束
+ *
+ * 十六进制编码的长度总是偶数，不会和 `0` 混淆，即使键本身就是 `0`
+ */
+fn encode_cursor(key: &str) -> String {
+    key.bytes().map(|b| format!("{b:02x}")).collect()
+}
+
+/// 解析游标，`Some(None)` 表示从头开始，格式错误时返回 `None`
+fn decode_cursor(cursor: &[u8]) -> Option<Option<String>> {
+    if cursor == b"0" {
+        return Some(None);
+    }
+    if !cursor.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = cursor
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok().map(Some)
+}
+
+fn keys_frame(keys: Vec<String>) -> Frame {
+    Frame::Array(
+        keys.into_iter()
+            .map(|key| Frame::Bulk(Bytes::from(key)))
+            .collect(),
+    )
+}
+
+/// 命令数组中的每一项都应该是 bulk 字符串，也兼容简单字符串
+fn parse_args(frame: Frame) -> Option<Vec<Bytes>> {
+    let Frame::Array(parts) = frame else {
+        return None;
+    };
+    parts
+        .into_iter()
+        .map(|part| match part {
+            Frame::Bulk(bytes) => Some(bytes),
+            Frame::Simple(s) => Some(Bytes::from(s)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse_int(bytes: &[u8]) -> Option<i64> {
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+/**
+ * 自增命令的响应
+ *
+ * mini-redis 的 `Frame::Integer` 是无符号整数，负数无法用它表示，这里以简单字符串的形式返回
+ */
+fn reply(result: Result<i64, DbError>) -> Frame {
+    match result {
+        Ok(n) => match u64::try_from(n) {
+            Ok(n) => Frame::Integer(n),
+            Err(_) => Frame::Simple(n.to_string()),
+        },
+        Err(e) => Frame::Error(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use tokio::{net::TcpStream, sync::oneshot, task::JoinHandle};
+
+    fn cmd(args: &[&str]) -> Frame {
+        Frame::Array(
+            args.iter()
+                .map(|arg| Frame::Bulk(Bytes::from(arg.to_string())))
+                .collect(),
+        )
+    }
+
+    /// 停止测试服务器，调用 `shutdown` 之后服务器不再接收新的连接
+    struct ShutdownHandle {
+        tx: oneshot::Sender<()>,
+        task: JoinHandle<io::Result<()>>,
+    }
+
+    impl ShutdownHandle {
+        async fn shutdown(self) {
+            let _ = self.tx.send(());
+            self.task.await.unwrap().unwrap();
+        }
+    }
+
+    /// 在随机端口上启动一个完整的服务器，返回实际监听的地址，需要在 tokio 运行时中调用
+    fn spawn_test_server() -> (SocketAddr, ShutdownHandle) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let listener = TcpListener::from_std(listener).unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        let task = tokio::spawn(serve(listener, Db::new(), async {
+            let _ = rx.await;
+        }));
+        (addr, ShutdownHandle { tx, task })
+    }
+
+    async fn request(client: &mut Connection, args: &[&str]) -> String {
+        client.write_frame(&cmd(args)).await.unwrap();
+        client.flush().await.unwrap();
+        client.read_frame().await.unwrap().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn set_and_get_round_trip() {
+        let (addr, server) = spawn_test_server();
+
+        let mut client = Connection::new(TcpStream::connect(addr).await.unwrap());
+        assert_eq!("OK", request(&mut client, &["SET", "hello", "world"]).await);
+        assert_eq!("world", request(&mut client, &["GET", "hello"]).await);
+        assert_eq!("(nil)", request(&mut client, &["GET", "missing"]).await);
+
+        // 不同的连接共享同一份数据
+        let mut other = Connection::new(TcpStream::connect(addr).await.unwrap());
+        assert_eq!("1", request(&mut other, &["INCR", "hello2"]).await);
+        assert_eq!("1", request(&mut client, &["GET", "hello2"]).await);
+
+        server.shutdown().await;
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[test]
+    fn ping_and_echo() {
+        let db = Db::new();
+        assert!(matches!(db.execute(cmd(&["PING"])), Frame::Simple(s) if s == "PONG"));
+        assert!(matches!(db.execute(cmd(&["ping", "hello"])), Frame::Bulk(b) if b == "hello"));
+        assert!(
+            matches!(db.execute(cmd(&["ECHO", "hi there"])), Frame::Bulk(b) if b == "hi there")
+        );
+        assert_eq!(
+            "error: ERR wrong number of arguments for 'echo' command",
+            db.execute(cmd(&["ECHO"])).to_string()
+        );
+    }
+
+    #[test]
+    fn incr_and_decr_by() {
+        let db = Db::new();
+        assert_eq!("5", db.execute(cmd(&["INCRBY", "n", "5"])).to_string());
+        assert_eq!("6", db.execute(cmd(&["incr", "n"])).to_string());
+        // 负数以简单字符串的形式回复
+        assert!(matches!(db.execute(cmd(&["DECRBY", "n", "10"])), Frame::Simple(s) if s == "-4"));
+        assert_eq!(Some(Bytes::from("-4")), db.get("n"));
+
+        // 已经保存为字符串的数字也可以自增
+        db.set("s".to_string(), Bytes::from("41"));
+        assert_eq!(Ok(42), db.incr_by("s", 1));
+        assert_eq!(
+            Some(Value::Int(42)),
+            db.entries
+                .lock()
+                .unwrap()
+                .get("s")
+                .map(|entry| entry.value.clone())
+        );
+    }
+
+    #[test]
+    fn set_nx_and_xx() {
+        let db = Db::new();
+        assert_eq!(
+            "OK",
+            db.execute(cmd(&["SET", "lock", "a", "NX"])).to_string()
+        );
+        assert_eq!(
+            "(nil)",
+            db.execute(cmd(&["SET", "lock", "b", "nx"])).to_string()
+        );
+        assert_eq!(Some(Bytes::from("a")), db.get("lock"));
+
+        assert_eq!(
+            "(nil)",
+            db.execute(cmd(&["SET", "missing", "a", "XX"])).to_string()
+        );
+        assert_eq!(None, db.get("missing"));
+        assert_eq!(
+            "OK",
+            db.execute(cmd(&["SET", "lock", "c", "XX"])).to_string()
+        );
+        assert_eq!(Some(Bytes::from("c")), db.get("lock"));
+
+        assert_eq!(
+            "error: ERR syntax error",
+            db.execute(cmd(&["SET", "lock", "d", "EX"])).to_string()
+        );
+    }
+
+    #[test]
+    fn setex_and_getdel() {
+        let db = Db::new();
+        assert_eq!(
+            "OK",
+            db.execute(cmd(&["SETEX", "session", "10", "abc"]))
+                .to_string()
+        );
+        let ttl = db.ttl("session").unwrap();
+        assert!(ttl > Duration::from_secs(9) && ttl <= Duration::from_secs(10));
+        assert_eq!(Some(Bytes::from("abc")), db.get("session"));
+
+        assert!(matches!(db.execute(cmd(&["GETDEL", "session"])), Frame::Bulk(b) if b == "abc"));
+        assert_eq!(None, db.get("session"));
+        assert_eq!("(nil)", db.execute(cmd(&["GETDEL", "session"])).to_string());
+
+        assert_eq!(
+            "error: ERR invalid expire time in 'setex' command",
+            db.execute(cmd(&["SETEX", "k", "0", "v"])).to_string()
+        );
+        assert_eq!(
+            "error: ERR invalid expire time in 'setex' command",
+            db.execute(cmd(&["SETEX", "k", &i64::MAX.to_string(), "v"]))
+                .to_string()
+        );
+        assert_eq!(None, db.get("k"));
+        // 普通的 SET 没有过期时间，并且会清除原来的过期时间
+        db.set_ex("k".to_string(), Bytes::from("v"), Duration::from_secs(10))
+            .unwrap();
+        db.set("k".to_string(), Bytes::from("w"));
+        assert_eq!(None, db.ttl("k"));
+    }
+
+    #[test]
+    fn expire_ttl_and_persist() {
+        let db = Db::new();
+        // 键不存在
+        assert_eq!("0", db.execute(cmd(&["EXPIRE", "k", "10"])).to_string());
+        assert_eq!("-2", db.execute(cmd(&["TTL", "k"])).to_string());
+        assert_eq!("0", db.execute(cmd(&["PERSIST", "k"])).to_string());
+
+        db.set("k".to_string(), Bytes::from("v"));
+        assert_eq!("-1", db.execute(cmd(&["TTL", "k"])).to_string());
+        assert_eq!("0", db.execute(cmd(&["PERSIST", "k"])).to_string());
+
+        assert_eq!("1", db.execute(cmd(&["EXPIRE", "k", "10"])).to_string());
+        assert_eq!("10", db.execute(cmd(&["TTL", "k"])).to_string());
+        // 再次 EXPIRE 会替换原来的过期时间
+        assert_eq!("1", db.execute(cmd(&["expire", "k", "100"])).to_string());
+        assert_eq!("100", db.execute(cmd(&["ttl", "k"])).to_string());
+
+        assert_eq!("1", db.execute(cmd(&["PERSIST", "k"])).to_string());
+        assert_eq!("-1", db.execute(cmd(&["TTL", "k"])).to_string());
+        assert_eq!(Some(Bytes::from("v")), db.get("k"));
+
+        // 过期时间不是正数时直接删除
+        assert_eq!("1", db.execute(cmd(&["EXPIRE", "k", "0"])).to_string());
+        assert_eq!("-2", db.execute(cmd(&["TTL", "k"])).to_string());
+        assert_eq!(
+            "error: ERR value is not an integer or out of range",
+            db.execute(cmd(&["EXPIRE", "k", "soon"])).to_string()
+        );
+
+        // 过期时间溢出时回复错误，数据库仍然可以继续使用
+        db.set("k".to_string(), Bytes::from("v"));
+        assert_eq!(
+            "error: ERR invalid expire time in 'expire' command",
+            db.execute(cmd(&["EXPIRE", "k", &i64::MAX.to_string()]))
+                .to_string()
+        );
+        assert_eq!("-1", db.execute(cmd(&["TTL", "k"])).to_string());
+    }
+
+    #[test]
+    fn ttl_decreases_until_expired() {
+        let db = Db::new();
+        db.set("k".to_string(), Bytes::from("v"));
+        assert_eq!(Ok(true), db.expire("k", Duration::from_millis(60)));
+        let first = db.ttl("k").unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let second = db.ttl("k").unwrap();
+        assert!(second < first);
+
+        // 过期之后惰性删除，TTL 和 PERSIST 都当作键不存在
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!db.persist("k"));
+        assert_eq!(None, db.expiry("k"));
+        assert_eq!(Ok(false), db.expire("k", Duration::from_secs(1)));
+        assert_eq!("-2", db.execute(cmd(&["TTL", "k"])).to_string());
+    }
+
+    #[test]
+    fn mset_and_mget_preserve_order() {
+        let db = Db::new();
+        db.set_ex("b".to_string(), Bytes::from("old"), Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(
+            "OK",
+            db.execute(cmd(&["MSET", "a", "1", "b", "2", "c", "3"]))
+                .to_string()
+        );
+        // MSET 覆盖已有的值时也清除了过期时间
+        assert_eq!(Some(None), db.expiry("b"));
+        assert_eq!(
+            "1 (nil) 3 2",
+            db.execute(cmd(&["MGET", "a", "missing", "c", "b"]))
+                .to_string()
+        );
+        assert_eq!(
+            vec![None, Some(Bytes::from("1"))],
+            db.mget(&["x".to_string(), "a".to_string()])
+        );
+
+        for args in [&["MSET", "a"][..], &["MSET", "a", "1", "b"], &["MGET"]] {
+            assert!(db
+                .execute(cmd(args))
+                .to_string()
+                .contains("wrong number of arguments"));
+        }
+        assert_eq!(Some(Bytes::from("1")), db.get("a"));
+    }
+
+    #[test]
+    fn keys_match_glob_patterns() {
+        let db = Db::new();
+        for key in ["user:1", "user:2", "user:10", "session:1", "user/x"] {
+            db.set(key.to_string(), Bytes::from("v"));
+        }
+        db.set_ex("user:old".to_string(), Bytes::from("v"), Duration::ZERO)
+            .unwrap();
+
+        assert_eq!(vec!["user:1", "user:10", "user:2"], db.keys("user:*"));
+        assert_eq!(vec!["user:1", "user:2"], db.keys("user:?"));
+        assert_eq!(5, db.keys("*").len());
+        assert_eq!(vec!["user/x"], db.keys("user/*"));
+        assert!(db.keys("nothing*").is_empty());
+        assert_eq!("session:1", db.execute(cmd(&["KEYS", "s*"])).to_string());
+
+        assert!(key_matches("*", ""));
+        assert!(key_matches("a*b*c", "aXXbYYc"));
+        assert!(!key_matches("a*b", "aXXc"));
+    }
+
+    #[test]
+    fn scan_visits_every_key_in_batches() {
+        let db = Db::new();
+        for i in 0..25 {
+            db.set(format!("key:{i}"), Bytes::from("v"));
+        }
+        // 键本身是 0 也不会和表示结束的游标混淆
+        db.set("0".to_string(), Bytes::from("v"));
+        db.set_ex("expired".to_string(), Bytes::from("v"), Duration::ZERO)
+            .unwrap();
+
+        let mut seen = Vec::new();
+        let mut cursor = String::from("0");
+        let mut batches = 0;
+        loop {
+            let Frame::Array(reply) = db.execute(cmd(&["SCAN", &cursor, "COUNT", "4"])) else {
+                panic!("SCAN should reply with an array");
+            };
+            let [Frame::Bulk(next), Frame::Array(keys)] = reply.as_slice() else {
+                panic!("unexpected SCAN reply");
+            };
+            assert!(keys.len() <= 4);
+            seen.extend(keys.iter().map(Frame::to_string));
+            batches += 1;
+            cursor = String::from_utf8(next.to_vec()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert!(batches > 1);
+        seen.sort();
+        let mut expected: Vec<String> = (0..25).map(|i| format!("key:{i}")).collect();
+        expected.push(String::from("0"));
+        expected.sort();
+        assert_eq!(expected, seen);
+
+        let (keys, next) = db.scan(None, "key:1*", 100);
+        assert_eq!(11, keys.len());
+        assert_eq!(None, next);
+        assert_eq!(
+            "error: ERR invalid cursor",
+            db.execute(cmd(&["SCAN", "abc"])).to_string()
+        );
+    }
+
+    #[test]
+    fn expired_keys_are_gone() {
+        let db = Db::new();
+        db.set_ex("k".to_string(), Bytes::from("v"), Duration::from_millis(20))
+            .unwrap();
+        assert_eq!(Some(Bytes::from("v")), db.get("k"));
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(None, db.get("k"));
+        assert_eq!(None, db.get_del("k"));
+        // 过期的键被删除了，NX 可以重新设置
+        assert!(db.set_if("k".to_string(), Bytes::from("w"), SetCondition::IfAbsent));
+    }
+
+    #[test]
+    fn strlen_and_getrange() {
+        let db = Db::new();
+        db.set("greeting".to_string(), Bytes::from("Hello, world"));
+        assert_eq!("12", db.execute(cmd(&["STRLEN", "greeting"])).to_string());
+        assert_eq!("0", db.execute(cmd(&["strlen", "missing"])).to_string());
+
+        assert_eq!(Bytes::from("Hello"), db.get_range("greeting", 0, 4));
+        assert_eq!(Bytes::from("world"), db.get_range("greeting", -5, -1));
+        assert_eq!(Bytes::from("Hello, world"), db.get_range("greeting", 0, -1));
+        // 超出范围的下标被截断
+        assert_eq!(Bytes::from("Hel"), db.get_range("greeting", -100, 2));
+        assert_eq!(Bytes::from("world"), db.get_range("greeting", 7, 100));
+        assert_eq!(Bytes::new(), db.get_range("greeting", 20, 30));
+        assert_eq!(Bytes::new(), db.get_range("greeting", 5, 2));
+        assert_eq!(Bytes::new(), db.get_range("missing", 0, -1));
+
+        assert!(matches!(
+            db.execute(cmd(&["GETRANGE", "greeting", "-5", "-1"])),
+            Frame::Bulk(b) if b == "world"
+        ));
+        // 整数同样按照字符串处理
+        db.incr_by("n", 12345).unwrap();
+        assert_eq!(Bytes::from("234"), db.get_range("n", 1, 3));
+    }
+
+    #[test]
+    fn incr_overflow_and_non_integer() {
+        let db = Db::new();
+        db.set("max".to_string(), Bytes::from(i64::MAX.to_string()));
+        assert_eq!(Err(DbError::Overflow), db.incr_by("max", 1));
+        assert_eq!(
+            "error: ERR increment or decrement would overflow",
+            db.execute(cmd(&["INCR", "max"])).to_string()
+        );
+        // 溢出时原来的值保持不变
+        assert_eq!(Some(Bytes::from(i64::MAX.to_string())), db.get("max"));
+
+        db.set("name".to_string(), Bytes::from("redis"));
+        assert_eq!(Err(DbError::NotAnInteger), db.incr_by("name", 1));
+        assert_eq!(
+            "error: ERR value is not an integer or out of range",
+            db.execute(cmd(&["INCRBY", "n", "ten"])).to_string()
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{execute_search, threadpool::ThreadPool, Config};
+
+/// 单个客户端最多可以发送的字节数，超过时返回错误而不是无限制地缓存
+pub const MAX_INPUT: u64 = 1 << 20;
+
+/// 客户端两次发送之间最多等待这么久，超时后断开，不发送数据也不关闭的连接不会一直占用工作线程
+pub const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 同时处理的连接数量，更多的连接在线程池中排队
+pub const WORKERS: usize = 4;
+
+/**
+ * 把 minigrep 变成一个简单的网络服务
+ *
+ * 每个连接交给线程池处理：读取客户端发送的全部文本，直到客户端关闭写入端，
+ * 搜索之后把结果按行写回去再关闭连接。单个连接的错误以及接受连接时的错误只打印出来，不影响其它连接
+ */
+pub fn serve(listener: TcpListener, config: &Config) -> io::Result<()> {
+    let pool = ThreadPool::new(WORKERS);
+    // 任务需要 'static 的配置，克隆一份给所有连接共享
+    let config = Arc::new(config.clone());
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept error: {e}");
+                continue;
+            }
+        };
+        let config = Arc::clone(&config);
+        pool.execute(move || {
+            if let Err(e) = handle_stream(stream, &config) {
+                eprintln!("connection error: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_stream(mut stream: TcpStream, config: &Config) -> io::Result<usize> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let matched = handle_client(&mut stream, config, MAX_INPUT)?;
+    stream.shutdown(Shutdown::Write)?;
+    Ok(matched)
+}
+
+/**
+ * 处理一个客户端，返回写回的结果行数
+ *
+ * 最多读取 `limit` 个字节，多读一个字节用来判断是否超出限制；超出时告诉客户端原因并返回 `InvalidData` 错误
+ */
+pub fn handle_client<S: Read + Write>(
+    mut stream: S,
+    config: &Config,
+    limit: u64,
+) -> io::Result<usize> {
+    let mut input = Vec::new();
+    Read::by_ref(&mut stream)
+        .take(limit + 1)
+        .read_to_end(&mut input)?;
+    if input.len() as u64 > limit {
+        let message = format!("input exceeds {limit} bytes");
+        writeln!(stream, "error: {message}")?;
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+
+    let content = String::from_utf8_lossy(&input);
+    let results = execute_search(config, &content);
+    for line in &results {
+        writeln!(stream, "{line}")?;
+    }
+    stream.flush()?;
+    Ok(results.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Cursor, thread};
+
+    fn search_remotely(addr: std::net::SocketAddr, content: &str) -> String {
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(content.as_bytes()).unwrap();
+        // 关闭写入端，服务端读取到 EOF 后开始搜索
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn client_receives_matches() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config {
+            queries: vec![String::from("rust")],
+            ignore_case: true,
+            ..Config::default()
+        };
+        // 服务端一直运行到测试进程结束
+        thread::spawn(move || serve(listener, &config));
+
+        assert_eq!(
+            "Rust:\nTrust me.\n",
+            search_remotely(addr, "Rust:\nsafe, fast, productive.\nTrust me.")
+        );
+        assert_eq!("", search_remotely(addr, "nothing here\n"));
+
+        // 一个连接之后什么也不发送，不影响其它客户端
+        let _idle = TcpStream::connect(addr).unwrap();
+        assert_eq!("rust\n", search_remotely(addr, "rust\n"));
+    }
+
+    #[test]
+    fn oversized_input_is_rejected() {
+        struct Duplex {
+            input: Cursor<Vec<u8>>,
+            output: Vec<u8>,
+        }
+        impl Read for Duplex {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.input.read(buf)
+            }
+        }
+        impl Write for Duplex {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.output.write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let config = Config {
+            queries: vec![String::from("a")],
+            ..Config::default()
+        };
+        let mut stream = Duplex {
+            input: Cursor::new(b"a\n".repeat(10)),
+            output: Vec::new(),
+        };
+        let err = handle_client(&mut stream, &config, 8).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(b"error: input exceeds 8 bytes\n".to_vec(), stream.output);
+
+        let mut stream = Duplex {
+            input: Cursor::new(b"a\nb\n".to_vec()),
+            output: Vec::new(),
+        };
+        assert_eq!(1, handle_client(&mut stream, &config, 8).unwrap());
+        assert_eq!(b"a\n".to_vec(), stream.output);
+    }
+}
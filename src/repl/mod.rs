@@ -0,0 +1,161 @@
+use std::{
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+use crate::{execute_search, Config};
+
+/// 每次读取查询之前输出的提示符
+pub const PROMPT: &str = "> ";
+
+/**
+ * 交互式搜索：内容只读取一次，之后反复从 `input` 读取一行查询并输出匹配的行，直到 EOF 或者 `:quit`
+ *
+ * 以 `:` 开头的行是修改选项的命令，例如 `:ignore-case on`，支持的命令见 [`apply_command`]。
+ * 查询使用 [`execute_search`]，与命令行的其它选项组合的方式相同。有多个输入时每一行结果前面带上路径
+ */
+pub fn run<R: BufRead, W: Write>(
+    config: &mut Config,
+    inputs: &[(PathBuf, String)],
+    input: R,
+    out: &mut W,
+) -> io::Result<()> {
+    let with_path = inputs.len() > 1;
+    let mut lines = input.lines();
+    loop {
+        write!(out, "{PROMPT}")?;
+        // 提示符没有换行，需要手动刷新才能显示出来
+        out.flush()?;
+        let Some(line) = lines.next().transpose()? else {
+            break;
+        };
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(command) = line.strip_prefix(':') {
+            match apply_command(config, command) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(message) => writeln!(out, "error: {message}")?,
+            }
+            continue;
+        }
+
+        if let Err(e) = config.set_queries(vec![line.to_string()]) {
+            writeln!(out, "error: invalid regex: {e}")?;
+            continue;
+        }
+        for (path, content) in inputs {
+            for result in execute_search(config, content) {
+                if with_path {
+                    writeln!(out, "{}:{result}", path.display())?;
+                } else {
+                    writeln!(out, "{result}")?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/**
+ * 执行一条命令（不包括开头的 `:`），返回是否继续读取查询
+ *
+ * - `quit`、`q`：结束
+ * - `ignore-case`、`invert`、`word`、`count` 后面跟 `on` 或者 `off`：打开或者关闭对应的选项
+ */
+fn apply_command(config: &mut Config, command: &str) -> Result<bool, String> {
+    let mut parts = command.split_whitespace();
+    let name = parts.next().unwrap_or_default();
+    if matches!(name, "quit" | "q") {
+        return Ok(false);
+    }
+
+    let option = match name {
+        "ignore-case" => &mut config.ignore_case,
+        "invert" => &mut config.invert,
+        "word" => &mut config.word,
+        "count" => &mut config.count,
+        _ => return Err(format!("unknown command :{name}")),
+    };
+    *option = match parts.next() {
+        Some("on") => true,
+        Some("off") => false,
+        _ => return Err(format!("usage: :{name} on|off")),
+    };
+    // 正则在编译时已经处理了大小写和单词边界
+    config.recompile().map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::args, ConfigError};
+    use std::io::Cursor;
+
+    #[test]
+    fn queries_and_commands_are_applied_in_order() {
+        let mut config = Config::build(&args(&["--interactive", "poem.txt"])).unwrap();
+        let inputs = vec![(
+            PathBuf::from("poem.txt"),
+            String::from("Rust:\nsafe, fast, productive.\nTrust me.\n"),
+        )];
+        let input = Cursor::new("rust\n:ignore-case on\nrust\n:color on\n:quit\nfast\n");
+        let mut out = Vec::new();
+        run(&mut config, &inputs, input, &mut out).unwrap();
+
+        assert_eq!(
+            "> Trust me.\n> > Rust:\nTrust me.\n> error: unknown command :color\n> ",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn regex_is_recompiled_per_query() {
+        let mut config = Config::build(&args(&["--interactive", "-E", "log.txt"])).unwrap();
+        let inputs = vec![(
+            PathBuf::from("log.txt"),
+            String::from("error 1\nwarning 2\nError 3\n"),
+        )];
+        let input = Cursor::new("^[eE]rror \\d\n(unclosed\n:count on\nwarn\n");
+        let mut out = Vec::new();
+        run(&mut config, &inputs, input, &mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("> error 1\nError 3\n> error: invalid regex"));
+        assert!(out.ends_with("> > 1\n> "));
+    }
+
+    #[test]
+    fn literal_prefilter_survives_new_queries() {
+        let mut config = Config::build(&args(&[
+            "--interactive",
+            "-E",
+            "--literal-prefilter",
+            "err",
+            "log.txt",
+        ]))
+        .unwrap();
+        let inputs = vec![(
+            PathBuf::from("log.txt"),
+            String::from("error 1\nwarning 2\nerror 3\n"),
+        )];
+        // 指定的字面量仍然生效，不包含 err 的行即使匹配正则也会被跳过
+        let input = Cursor::new("r\n");
+        let mut out = Vec::new();
+        run(&mut config, &inputs, input, &mut out).unwrap();
+        assert_eq!("> error 1\nerror 3\n> ", String::from_utf8(out).unwrap());
+        assert_eq!(Some("err"), config.prefilter.as_deref());
+    }
+
+    #[test]
+    fn stdin_cannot_be_searched_interactively() {
+        assert!(matches!(
+            Config::build(&args(&["--interactive", "-"])),
+            Err(ConfigError::InvalidValue(flag, _)) if flag == "--interactive"
+        ));
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use futures::{
+    future::join_all,
+    stream::{self, Stream},
+};
+
+use crate::{line_contains, matching_lines, Config};
+
+/**
+ * 异步读取并搜索单个文件，返回所有匹配的行
+ *
+ * 使用 `tokio::fs` 读取文件，等待 I/O 时不会阻塞运行时的线程
+ */
+pub async fn search_file_async(path: &Path, config: &Config) -> io::Result<Vec<String>> {
+    let content = tokio::fs::read_to_string(path).await?;
+    Ok(matching_lines(config, &content)
+        .map(|(_, line)| line.to_string())
+        .collect())
+}
+
+/**
+ * 并发搜索多个文件，结果按照传入路径的顺序拼接
+ *
+ * `join_all` 会同时驱动所有的 Future，任意一个文件读取失败时返回第一个错误
+ */
+pub async fn search_paths_async<P: AsRef<Path>>(
+    paths: &[P],
+    config: &Config,
+) -> io::Result<Vec<String>> {
+    let results = join_all(
+        paths
+            .iter()
+            .map(|path| search_file_async(path.as_ref(), config)),
+    )
+    .await;
+
+    let mut lines = Vec::new();
+    for result in results {
+        lines.extend(result?);
+    }
+    Ok(lines)
+}
+
+/**
+ * 流式搜索产生的一个匹配
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// 匹配所在的文件，[`search_stream`] 搜索的是内存中的内容，没有路径，为空
+    pub path: PathBuf,
+    /// 行号，从 1 开始
+    pub line_number: usize,
+    pub line: String,
+}
+
+/**
+ * 以 `Stream` 的形式惰性地产生匹配结果
+ *
+ * 每次被 poll 时才继续向后扫描，直到找到下一个匹配的行，调用方可以直接使用 `take`、`filter`、`for_each_concurrent` 等组合器
+ */
+pub fn search_stream(
+    content: String,
+    query: String,
+    ignore_case: bool,
+) -> impl Stream<Item = Match> {
+    let query = if ignore_case {
+        query.to_lowercase()
+    } else {
+        query
+    };
+
+    // 状态：(内容, 下一行的起始位置, 已经扫描过的行数, 查询字符串)
+    stream::unfold(
+        (content, 0, 0, query),
+        move |(content, mut offset, mut line_number, query)| async move {
+            // 与 `str::lines` 一致：按 `\n` 切分，去掉行尾的 `\r`，末尾的换行符不会产生空行
+            while offset < content.len() {
+                let end = content[offset..]
+                    .find('\n')
+                    .map_or(content.len(), |i| offset + i);
+                let line = content[offset..end]
+                    .strip_suffix('\r')
+                    .unwrap_or(&content[offset..end]);
+                offset = end + 1;
+                line_number += 1;
+
+                if line_contains(line, &query, ignore_case) {
+                    let item = Match {
+                        path: PathBuf::new(),
+                        line_number,
+                        line: line.to_string(),
+                    };
+                    return Some((item, (content, offset, line_number, query)));
+                }
+            }
+            None
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{search_iter, tests::temp_dir};
+    use futures::StreamExt;
+    use std::fs;
+
+    #[tokio::test]
+    async fn async_search_matches_sync() {
+        let dir = temp_dir("async_search_matches_sync");
+        let contents = [
+            "Rust:\nsafe, fast, productive.\nPick three.\n",
+            "Trust me.\nnothing here\n",
+            "rust belt\nRUST\n",
+        ];
+        let paths: Vec<_> = contents
+            .iter()
+            .enumerate()
+            .map(|(i, content)| {
+                let path = dir.join(format!("{i}.txt"));
+                fs::write(&path, content).unwrap();
+                path
+            })
+            .collect();
+
+        let config = Config {
+            queries: vec![String::from("rust")],
+            ignore_case: true,
+            ..Config::default()
+        };
+        let expected: Vec<String> = contents
+            .iter()
+            .flat_map(|content| search_iter("rust", content, true).map(String::from))
+            .collect();
+        assert_eq!(expected, search_paths_async(&paths, &config).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn stream_matches_sync() {
+        let content = "Rust:\r\nsafe, fast, productive.\nPick three.\nTrust me.\n";
+        let matches: Vec<Match> = search_stream(content.to_string(), String::from("rUsT"), true)
+            .collect()
+            .await;
+
+        let lines: Vec<&str> = matches.iter().map(|m| m.line.as_str()).collect();
+        assert_eq!(
+            search_iter("rUsT", content, true).collect::<Vec<_>>(),
+            lines
+        );
+        assert_eq!(
+            vec![1, 4],
+            matches.iter().map(|m| m.line_number).collect::<Vec<_>>()
+        );
+
+        // 只取第一个匹配时后面的内容不会被扫描
+        let first: Vec<Match> = search_stream(content.to_string(), String::from("rust"), true)
+            .take(1)
+            .collect()
+            .await;
+        assert_eq!(1, first.len());
+    }
+}
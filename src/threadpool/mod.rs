@@ -1,12 +1,79 @@
 use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    cmp::Ordering as CmpOrdering,
+    collections::{BinaryHeap, VecDeque},
+    fmt,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
     sync::{
-        mpsc::{self, Receiver, Sender},
-        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError,
     },
+    task::{Context, Poll},
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
+use futures::channel::oneshot;
+
 // pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// 在任务队列中传递的任务，`label` 是提交时指定的名称，用于日志和统计，`execute` 提交的任务名称为空
+struct Task {
+    label: String,
+    job: Job,
+}
+
+impl Task {
+    fn unlabeled(job: Job) -> Self {
+        Task {
+            label: String::new(),
+            job,
+        }
+    }
+}
+
+/// 任务队列中的消息
+enum Message {
+    Run(Task),
+    /// 让取到它的工作线程退出，`resize` 缩小线程池时发送。线程退出前通过通道回复自己的 id，
+    /// 线程数量已经不多于 `min_threads` 时不退出，回复 `None`
+    Retire(Sender<Option<usize>>),
+}
+
+thread_local! {
+    /// 当前线程正在执行的任务名称
+    static CURRENT_LABEL: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/**
+ * 当前线程正在执行的任务的名称
+ *
+ * 在任务内部或者 panic 回调中调用，通过 `execute_named` 提交的任务返回它的名称，其它情况返回 `None`
+ */
+pub fn current_label() -> Option<String> {
+    CURRENT_LABEL.with(|label| label.borrow().clone())
+}
+
+/// 任务 panic 时的回调，参数是工作线程 id 和 panic 的内容
+pub type PanicHandler = dyn Fn(usize, &(dyn Any + Send)) + Send + Sync;
+
+/// 工作线程启动时的回调，参数是工作线程 id
+pub type WorkerInit = dyn Fn(usize) + Send + Sync;
+
+/// 默认的 panic 回调：把 panic 信息打印到标准错误
+fn default_panic_handler(id: usize, payload: &(dyn Any + Send)) {
+    // `panic!` 的参数是字面量时内容是 &str，带格式化参数时是 String
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>");
+    eprintln!("thread {id} panicked while running a job: {message}");
+}
 // pub struct ThreadPool {
 //     threads: Vec<JoinHandle<()>>,
 //     sender: Sender<Job>,
@@ -68,41 +135,605 @@ use std::{
 
 pub type Job = Box<dyn FnOnce() + Send + 'static>;
 
+/**
+ * 协作式取消的标记
+ *
+ * 线程无法被强制终止，长时间运行的任务需要自己定期检查 `is_cancelled`，发现被取消后尽快返回
+ */
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// `execute_with_timeout` 在限定时间内没有拿到结果
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("task timed out")]
+pub struct Timeout;
+
+/// 可伸缩线程池中空闲线程每次最多持有接收端这么长时间，超时后释放锁，检查自己空闲了多久
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/**
+ * 任务耗时的汇总信息
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TimingSummary {
+    pub count: u64,
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+}
+
+/// 用原子变量累计任务耗时，每个任务只增加几次原子操作，不需要加锁
+struct Timing {
+    count: AtomicU64,
+    total_nanos: AtomicU64,
+    min_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl Timing {
+    fn new() -> Self {
+        Timing {
+            count: AtomicU64::new(0),
+            total_nanos: AtomicU64::new(0),
+            min_nanos: AtomicU64::new(u64::MAX),
+            max_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        // 统计数据之间不需要同步顺序，Relaxed 就足够了
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.min_nanos.fetch_min(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    fn summary(&self) -> TimingSummary {
+        let count = self.count.load(Ordering::Relaxed);
+        if count == 0 {
+            return TimingSummary::default();
+        }
+        TimingSummary {
+            count,
+            min: Duration::from_nanos(self.min_nanos.load(Ordering::Relaxed)),
+            max: Duration::from_nanos(self.max_nanos.load(Ordering::Relaxed)),
+            mean: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed) / count),
+        }
+    }
+}
+
+/**
+ * 线程池和所有工作线程共享的状态
+ */
+struct Shared {
+    receiver: Mutex<Receiver<Message>>,
+    /// `resize` 会同时修改上下限，所以使用原子变量
+    min_threads: AtomicUsize,
+    max_threads: AtomicUsize,
+    idle_timeout: Duration,
+    /// 排队任务数量的上限，只限制 `try_execute` 和 `execute_retry`
+    queue_capacity: Option<usize>,
+    /// 已经提交但还没有被工作线程取走的任务数量
+    queued: AtomicUsize,
+    /// 存活的工作线程数量
+    threads: AtomicUsize,
+    /// 正在等待任务的工作线程数量
+    idle: AtomicUsize,
+    next_id: AtomicUsize,
+    workers: Mutex<Vec<Worker>>,
+    timing: Timing,
+    /// 存活的 `PoolHandle` 数量
+    handles: AtomicUsize,
+    /// 线程池创建的时间，心跳中记录的是相对于它的纳秒数
+    started: Instant,
+    panic_handler: Arc<PanicHandler>,
+    worker_init: Option<Arc<WorkerInit>>,
+    /// `shutdown_now` 之后设置，工作线程取到的任务直接丢弃，不再执行
+    discard: AtomicBool,
+    /// 是否把工作线程的活动输出到标准错误，见 [`ThreadPoolBuilder::log_events`]
+    log_events: bool,
+}
+
+impl Shared {
+    fn log(&self, message: fmt::Arguments<'_>) {
+        if self.log_events {
+            eprintln!("{message}");
+        }
+    }
+
+    fn submit(self: &Arc<Self>, sender: &Sender<Message>, task: Task) {
+        // 先计数再发送，保证工作线程取走任务时计数不会小于 0
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        self.send_reserved(sender, task);
+    }
+
+    /// 在队列中占一个位置，已经达到 `queue_capacity` 时返回错误，检查和计数是同一个原子操作
+    fn reserve_slot(&self) -> Result<(), ExecuteError> {
+        let capacity = self.queue_capacity.unwrap_or(usize::MAX);
+        self.queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < capacity).then_some(n + 1)
+            })
+            .map(|_| ())
+            .map_err(|_| ExecuteError::QueueFull)
+    }
+
+    /// 发送已经通过 `queued` 计数占好位置的任务
+    fn send_reserved(self: &Arc<Self>, sender: &Sender<Message>, task: Task) {
+        // 接收端保存在 Shared 中，只要还持有 Shared 发送就不会失败
+        sender
+            .send(Message::Run(task))
+            .expect("thread pool receiver dropped");
+        self.grow_if_backed_up();
+    }
+
+    /**
+     * 获取任务队列接收端的锁，锁已经中毒时照常使用
+     *
+     * 工作线程只在取任务时短暂持有这个锁，任务执行时并不持有，但如果有线程持有锁时 panic 了，锁会被标记为中毒。
+     * 这时直接 `unwrap` 会让之后每个工作线程都跟着 panic，一个出错的任务就能让整个线程池停止工作。
+     * 接收端本身没有需要维护的不变量，panic 时它也不会处于不一致的状态，所以直接取出锁中的值继续使用
+     */
+    fn lock_receiver(&self) -> MutexGuard<'_, Receiver<Message>> {
+        self.receiver.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn elapsed_nanos(&self) -> u64 {
+        u64::try_from(self.started.elapsed().as_nanos()).unwrap_or(u64::MAX)
+    }
+
+    /// 线程数量固定时不需要轮询，直接阻塞等待任务即可
+    fn scalable(&self) -> bool {
+        self.min_threads.load(Ordering::SeqCst) < self.max_threads.load(Ordering::SeqCst)
+    }
+
+    /// 批量提交：计数和扩容检查只做一次，而不是每个任务一次
+    fn submit_batch(self: &Arc<Self>, sender: &Sender<Message>, jobs: Vec<Job>) {
+        let count = jobs.len();
+        self.queued.fetch_add(count, Ordering::SeqCst);
+        for job in jobs {
+            sender
+                .send(Message::Run(Task::unlabeled(job)))
+                .expect("thread pool receiver dropped");
+        }
+        // 新线程要等真正运行起来才会计入空闲数量，最多为这一批任务每个启动一个线程
+        for _ in 0..count {
+            if !self.grow_if_backed_up() {
+                break;
+            }
+        }
+    }
+
+    /// 排队的任务比空闲的线程多，并且还没有达到上限时，启动一个新线程，返回是否启动了新线程
+    fn grow_if_backed_up(self: &Arc<Self>) -> bool {
+        if self.queued.load(Ordering::SeqCst) <= self.idle.load(Ordering::SeqCst) {
+            return false;
+        }
+        let reserved = self
+            .threads
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < self.max_threads.load(Ordering::SeqCst)).then_some(n + 1)
+            })
+            .is_ok();
+        if reserved {
+            self.spawn_worker();
+        }
+        reserved
+    }
+
+    /// 调用前需要先在 `threads` 中为新线程占好位置
+    fn spawn_worker(self: &Arc<Self>) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut workers = self.workers.lock().unwrap();
+        // 已经退出的线程不需要再保留
+        workers.retain(|worker| {
+            worker
+                .thread
+                .as_ref()
+                .is_some_and(|thread| !thread.is_finished())
+        });
+        workers.push(Worker::new(id, Arc::clone(self)));
+    }
+
+    /// 线程数量多于 `min_threads` 时才允许退出，返回是否可以退出
+    fn try_retire(&self) -> bool {
+        self.threads
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n > self.min_threads.load(Ordering::SeqCst)).then(|| n - 1)
+            })
+            .is_ok()
+    }
+}
+
+/// `try_execute` 和 `execute_retry` 提交失败的原因
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecuteError {
+    #[error("task queue is full")]
+    QueueFull,
+    #[error("thread pool is shut down")]
+    ShutDown,
+}
+
+/// 任务在产生返回值之前 panic 了，结果永远不会到达
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("task panicked before producing a result")]
+pub struct JoinError;
+
+/**
+ * `execute_with_result` 返回的任务句柄，类似于 `thread::JoinHandle<T>`
+ *
+ * 内部是一个 oneshot 通道的接收端：既可以调用 `join` 阻塞等待，也可以在异步代码中直接 `.await`
+ */
+pub struct TaskHandle<T> {
+    receiver: oneshot::Receiver<T>,
+}
+
+impl<T> TaskHandle<T> {
+    /// 阻塞当前线程直到任务返回
+    pub fn join(self) -> Result<T, JoinError> {
+        futures::executor::block_on(self)
+    }
+}
+
+impl<T> Future for TaskHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // 发送端随着闭包一起被丢弃而没有发送数据，说明任务 panic 了
+        Pin::new(&mut self.receiver).poll(cx).map_err(|_| JoinError)
+    }
+}
+
+/// 等待到期的延迟任务
+struct Delayed {
+    deadline: Instant,
+    /// 到期时间相同的任务按提交顺序执行
+    seq: u64,
+    job: Job,
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delayed {
+    /// BinaryHeap 是大顶堆，反过来比较让最早到期的任务排在堆顶
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (other.deadline, other.seq).cmp(&(self.deadline, self.seq))
+    }
+}
+
+#[derive(Default)]
+struct TimerState {
+    queue: BinaryHeap<Delayed>,
+    next_seq: u64,
+    stopped: bool,
+}
+
+/**
+ * 延迟任务的定时器
+ *
+ * 所有延迟任务都由同一个定时器线程管理：它在 Condvar 上等待到最早的截止时间，
+ * 到期后把任务放入普通的任务队列，等待期间不占用任何工作线程
+ */
+#[derive(Default)]
+struct Timer {
+    state: Mutex<TimerState>,
+    condvar: Condvar,
+}
+
+impl Timer {
+    fn run(&self, shared: &Arc<Shared>, sender: &Sender<Message>) {
+        let mut state = self.state.lock().unwrap();
+        while !state.stopped {
+            let now = Instant::now();
+            match state.queue.peek() {
+                Some(delayed) if delayed.deadline <= now => {
+                    let delayed = state.queue.pop().unwrap();
+                    shared.submit(sender, Task::unlabeled(delayed.job));
+                }
+                Some(delayed) => {
+                    let timeout = delayed.deadline - now;
+                    state = self.condvar.wait_timeout(state, timeout).unwrap().0;
+                }
+                None => state = self.condvar.wait(state).unwrap(),
+            }
+        }
+    }
+}
+
+/**
+ * 工作线程的心跳，用来发现卡在某个任务中的线程
+ *
+ * 开始和结束执行任务时各更新一次，空闲等待任务的线程不算卡住
+ */
+#[derive(Default)]
+struct Heartbeat {
+    /// 最近一次开始或结束任务的时间，相对于线程池创建时间的纳秒数
+    last_active: AtomicU64,
+    busy: AtomicBool,
+}
+
+impl Heartbeat {
+    fn beat(&self, shared: &Shared, busy: bool) {
+        self.last_active
+            .store(shared.elapsed_nanos(), Ordering::SeqCst);
+        self.busy.store(busy, Ordering::SeqCst);
+    }
+
+    fn is_stalled(&self, now: u64, threshold: Duration) -> bool {
+        let idle =
+            Duration::from_nanos(now.saturating_sub(self.last_active.load(Ordering::SeqCst)));
+        self.busy.load(Ordering::SeqCst) && idle >= threshold
+    }
+}
+
 pub struct Worker {
     id: usize,
     thread: Option<JoinHandle<()>>,
+    heartbeat: Arc<Heartbeat>,
 }
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<Receiver<Job>>>) -> Self {
+    fn new(id: usize, shared: Arc<Shared>) -> Self {
+        let heartbeat = Arc::new(Heartbeat::default());
+        let beat = Arc::clone(&heartbeat);
         // Mutex 没有提供显式的 unlock 方法，它依赖于作用域的结束去释放锁。`while let, for in` 他们形成的是作用域快，在当前用例中只有 job 结束之后才会释放锁。
         //
         // 这样导致的即使已经有新任务到达，但是因为 Mutex 锁住了 receiver，导致其他线程无法使用 receiver，无法接收运行任务，
         // 只有等当前线程结束后，离开作用域自动释放 Mutex，其他线程才有机会使用 receiver，才能运行任务。
         // 所以使用 `while let, for in` 这种方式还是类似单线程，同时运行的只有一个线程，因为接收者的锁没有正确的及时释放。
 
-        let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv();
-            match message {
-                Ok(job) => {
-                    println!("thread {id} got a job; executing.");
-                    job();
-                }
-                Err(_) => {
-                    println!("thread {id} disconnected; shutting down.");
-                    break;
+        let thread = thread::spawn(move || {
+            if let Some(init) = &shared.worker_init {
+                init(id);
+            }
+            let mut idle_since = Instant::now();
+            loop {
+                shared.idle.fetch_add(1, Ordering::SeqCst);
+                let message = if shared.scalable() {
+                    shared.lock_receiver().recv_timeout(POLL_INTERVAL)
+                } else {
+                    shared
+                        .lock_receiver()
+                        .recv()
+                        .map_err(|_| RecvTimeoutError::Disconnected)
+                };
+                shared.idle.fetch_sub(1, Ordering::SeqCst);
+
+                match message {
+                    Ok(Message::Run(Task { label, job })) => {
+                        shared.queued.fetch_sub(1, Ordering::SeqCst);
+                        if shared.discard.load(Ordering::SeqCst) {
+                            drop(job);
+                            continue;
+                        }
+                        if label.is_empty() {
+                            shared.log(format_args!("thread {id} got a job; executing."));
+                        } else {
+                            shared.log(format_args!("thread {id} running '{label}'"));
+                        }
+                        let start = Instant::now();
+                        beat.beat(&shared, true);
+                        CURRENT_LABEL.with(|current| {
+                            *current.borrow_mut() = (!label.is_empty()).then_some(label)
+                        });
+                        // 捕获任务中的 panic，避免一个任务出错就让工作线程退出
+                        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                            (shared.panic_handler)(id, payload.as_ref());
+                        }
+                        CURRENT_LABEL.with(|current| current.borrow_mut().take());
+                        beat.beat(&shared, false);
+                        shared.timing.record(start.elapsed());
+                        idle_since = Instant::now();
+                    }
+                    Ok(Message::Retire(reply)) => {
+                        // 其它线程可能已经因为空闲超时退出，线程数量达到目标之后就不再退出
+                        let retired = shared.try_retire();
+                        let _ = reply.send(retired.then_some(id));
+                        if retired {
+                            shared.log(format_args!("thread {id} retired by resize."));
+                            break;
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        if idle_since.elapsed() >= shared.idle_timeout && shared.try_retire() {
+                            shared.log(format_args!("thread {id} idle for too long; retiring."));
+                            break;
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        shared.threads.fetch_sub(1, Ordering::SeqCst);
+                        shared.log(format_args!("thread {id} disconnected; shutting down."));
+                        break;
+                    }
                 }
             }
         });
         Worker {
             id,
             thread: Some(thread),
+            heartbeat,
+        }
+    }
+}
+
+/**
+ * 线程池的构建器
+ *
+ * `min_threads` 和 `max_threads` 相同时就是固定大小的线程池；不同时线程池会自动伸缩：
+ * 任务积压时按需启动新线程，直到 `max_threads`，多出来的线程空闲超过 `idle_timeout` 后退出，直到只剩 `min_threads` 个
+ */
+#[derive(Clone)]
+pub struct ThreadPoolBuilder {
+    min_threads: usize,
+    max_threads: usize,
+    idle_timeout: Duration,
+    queue_capacity: Option<usize>,
+    panic_handler: Arc<PanicHandler>,
+    worker_init: Option<Arc<WorkerInit>>,
+    log_events: bool,
+}
+
+impl Default for ThreadPoolBuilder {
+    fn default() -> Self {
+        ThreadPoolBuilder {
+            min_threads: 1,
+            max_threads: thread::available_parallelism().map_or(4, |n| n.get()),
+            idle_timeout: Duration::from_secs(60),
+            queue_capacity: None,
+            panic_handler: Arc::new(default_panic_handler),
+            worker_init: None,
+            log_events: false,
+        }
+    }
+}
+
+/// 闭包没有实现 Debug，只输出其它字段
+impl fmt::Debug for ThreadPoolBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThreadPoolBuilder")
+            .field("min_threads", &self.min_threads)
+            .field("max_threads", &self.max_threads)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("queue_capacity", &self.queue_capacity)
+            .field("log_events", &self.log_events)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ThreadPoolBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn min_threads(mut self, min_threads: usize) -> Self {
+        self.min_threads = min_threads;
+        self
+    }
+
+    pub fn max_threads(mut self, max_threads: usize) -> Self {
+        self.max_threads = max_threads;
+        self
+    }
+
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// 把工作线程取到任务、退出，以及关闭时等待线程的过程输出到标准错误，默认关闭
+    ///
+    /// 线程池的使用者（例如 minigrep 的 `--threads`）通常把结果写到标准输出，日志不能混在其中
+    pub fn log_events(mut self, enabled: bool) -> Self {
+        self.log_events = enabled;
+        self
+    }
+
+    /// 最多排队这么多个还没有开始执行的任务，超出时 `try_execute` 返回 [`ExecuteError::QueueFull`]
+    ///
+    /// `execute` 等其它提交方式不受限制
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// 任务 panic 时调用的回调，参数是工作线程 id 和 panic 的内容，可以用来输出结构化的日志
+    ///
+    /// 回调在工作线程中执行，执行完之后线程会继续处理后面的任务
+    pub fn with_panic_handler<F>(mut self, f: F) -> Self
+    where
+        F: Fn(usize, &(dyn Any + Send)) + Send + Sync + 'static,
+    {
+        self.panic_handler = Arc::new(f);
+        self
+    }
+
+    /// 每个工作线程启动之后、取第一个任务之前调用一次，参数是工作线程 id
+    ///
+    /// 可以用来初始化线程局部的资源，例如每个线程复用的缓冲区，之后这个线程执行的所有任务都能使用它。
+    /// 自动伸缩时新启动的线程同样会调用
+    pub fn with_worker_init<F>(mut self, f: F) -> Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.worker_init = Some(Arc::new(f));
+        self
+    }
+
+    /// ## Panics
+    ///
+    /// `max_threads` 为 0 或者 `min_threads` 大于 `max_threads` 时 panic
+    pub fn build(self) -> ThreadPool {
+        assert!(self.max_threads > 0);
+        assert!(self.min_threads <= self.max_threads);
+
+        let (sender, receiver) = mpsc::channel::<Message>();
+        let shared = Arc::new(Shared {
+            receiver: Mutex::new(receiver),
+            min_threads: AtomicUsize::new(self.min_threads),
+            max_threads: AtomicUsize::new(self.max_threads),
+            idle_timeout: self.idle_timeout,
+            queue_capacity: self.queue_capacity,
+            queued: AtomicUsize::new(0),
+            threads: AtomicUsize::new(self.min_threads),
+            idle: AtomicUsize::new(0),
+            next_id: AtomicUsize::new(0),
+            workers: Mutex::new(Vec::with_capacity(self.max_threads)),
+            timing: Timing::new(),
+            handles: AtomicUsize::new(0),
+            started: Instant::now(),
+            panic_handler: self.panic_handler,
+            worker_init: self.worker_init,
+            discard: AtomicBool::new(false),
+            log_events: self.log_events,
+        });
+        for _ in 0..self.min_threads {
+            shared.spawn_worker();
+        }
+
+        ThreadPool {
+            shared,
+            sender: Some(sender),
+            tokens: Mutex::new(Vec::new()),
+            timer: Arc::new(Timer::default()),
+            timer_thread: OnceLock::new(),
         }
     }
 }
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<Sender<Job>>,
+    shared: Arc<Shared>,
+    sender: Option<Sender<Message>>,
+    /// 通过 `execute_cancellable` 提交的任务的取消标记，关闭线程池时统一取消
+    tokens: Mutex<Vec<CancellationToken>>,
+    timer: Arc<Timer>,
+    /// 第一次调用 `execute_after` 时才启动定时器线程
+    timer_thread: OnceLock<JoinHandle<()>>,
 }
 
 impl ThreadPool {
@@ -116,19 +747,96 @@ impl ThreadPool {
     pub fn new(size: usize) -> Self {
         assert!(size > 0);
 
-        let mut workers = Vec::with_capacity(size);
-        let (mut sender, mut receiver) = mpsc::channel::<Job>();
-        let receiver = Arc::new(Mutex::new(receiver));
+        ThreadPool::builder()
+            .min_threads(size)
+            .max_threads(size)
+            .build()
+    }
+
+    /// 每个 CPU 一个工作线程，无法获取 CPU 数量时只使用一个线程
+    pub fn with_available_parallelism() -> Self {
+        ThreadPool::new(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    pub fn builder() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::new()
+    }
+
+    /// 当前存活的工作线程数量
+    pub fn thread_count(&self) -> usize {
+        self.shared.threads.load(Ordering::SeqCst)
+    }
 
-        for i in 0..size {
-            let _receiver = Arc::clone(&receiver);
-            workers.push(Worker::new(i, _receiver));
+    /// 调整工作线程的数量，之后线程池固定为 `new_size` 个线程，不再自动伸缩
+    ///
+    /// 扩大时直接启动新的线程。缩小时向队列中发送与多余线程数量相同的退出消息，它们排在已经提交的任务之后，
+    /// 所以不会丢失任务；取到退出消息的线程执行完手上的任务后退出，这里会等待它们全部退出
+    ///
+    /// ## Panics
+    ///
+    /// `new_size` 为 0 时 panic
+    pub fn resize(&mut self, new_size: usize) {
+        assert!(new_size > 0);
+
+        let shared = &self.shared;
+        shared.min_threads.store(new_size, Ordering::SeqCst);
+        shared.max_threads.store(new_size, Ordering::SeqCst);
+        let current = shared.threads.load(Ordering::SeqCst);
+        if new_size >= current {
+            for _ in current..new_size {
+                shared.threads.fetch_add(1, Ordering::SeqCst);
+                shared.spawn_worker();
+            }
+            return;
         }
 
-        ThreadPool {
-            workers,
-            sender: Some(sender),
+        let surplus = current - new_size;
+        let (reply, retired) = mpsc::channel();
+        let sender = self.sender.as_ref().unwrap();
+        for _ in 0..surplus {
+            sender
+                .send(Message::Retire(reply.clone()))
+                .expect("thread pool receiver dropped");
         }
+        drop(reply);
+        let ids: Vec<usize> = retired.iter().take(surplus).flatten().collect();
+
+        let leaving: Vec<Worker> = {
+            let mut workers = shared.workers.lock().unwrap();
+            let (leaving, staying) = workers
+                .drain(..)
+                .partition(|worker| ids.contains(&worker.id));
+            *workers = staying;
+            leaving
+        };
+        for mut worker in leaving {
+            if let Some(thread) = worker.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+
+    /// 已经执行完成的任务的耗时统计
+    pub fn timing_summary(&self) -> TimingSummary {
+        self.shared.timing.summary()
+    }
+
+    /// 正在执行同一个任务超过 `threshold` 的工作线程 id，按 id 排序
+    ///
+    /// 只用于诊断：线程无法被强制终止，发现卡住的线程后如何处理由调用方决定
+    pub fn stalled_workers(&self, threshold: Duration) -> Vec<usize> {
+        let now = self.shared.elapsed_nanos();
+        let mut ids: Vec<usize> = self
+            .shared
+            .workers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|worker| worker.heartbeat.is_stalled(now, threshold))
+            .map(|worker| worker.id)
+            .collect();
+        ids.sort_unstable();
+        ids
     }
 
     pub fn execute<F>(&self, f: F)
@@ -140,19 +848,1088 @@ impl ThreadPool {
     {
         // 传递特征对象，因为函要求定长类型，特征属于非定长的类型
         let box_f = Box::new(f);
-        self.sender.as_ref().unwrap().send(box_f);
+        self.shared
+            .submit(self.sender.as_ref().unwrap(), Task::unlabeled(box_f));
+    }
+
+    /// 不阻塞地提交任务，排队的任务已经达到 `queue_capacity` 或者线程池已经关闭时返回错误，任务不会被执行
+    pub fn try_execute<F>(&self, f: F) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let sender = self.sender.as_ref().ok_or(ExecuteError::ShutDown)?;
+        self.shared.reserve_slot()?;
+        self.shared
+            .send_reserved(sender, Task::unlabeled(Box::new(f)));
+        Ok(())
+    }
+
+    /// 与 `try_execute` 相同，但队列已满时最多再重试 `max_retries` 次
+    ///
+    /// 第 n 次重试之前等待 `base_delay * 2^(n-1)`，队列只是暂时被占满时可以等到工作线程取走一些任务。
+    /// 所有尝试都失败时返回最后一次的错误
+    pub fn execute_retry<F>(
+        &self,
+        f: F,
+        max_retries: u32,
+        base_delay: Duration,
+    ) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let sender = self.sender.as_ref().ok_or(ExecuteError::ShutDown)?;
+        let mut delay = base_delay;
+        for attempt in 0..=max_retries {
+            // 先占位置再交出闭包，失败时闭包还在手上，可以用于下一次尝试
+            match self.shared.reserve_slot() {
+                Ok(()) => {
+                    self.shared
+                        .send_reserved(sender, Task::unlabeled(Box::new(f)));
+                    return Ok(());
+                }
+                Err(e) if attempt == max_retries => return Err(e),
+                Err(_) => {
+                    thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                }
+            }
+        }
+        unreachable!("the last attempt always returns")
+    }
+
+    /// 提交一个带名称的任务，工作线程会在日志中输出它的名称，任务内部和 panic 回调中可以通过 [`current_label`] 获取
+    pub fn execute_named<F>(&self, label: impl Into<String>, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let task = Task {
+            label: label.into(),
+            job: Box::new(f),
+        };
+        self.shared.submit(self.sender.as_ref().unwrap(), task);
+    }
+
+    /// 一次提交多个任务，效果与循环调用 `execute` 相同，但共享计数和扩容检查只做一次
+    pub fn execute_batch<I>(&self, jobs: I)
+    where
+        I: IntoIterator<Item = Job>,
+    {
+        let jobs: Vec<Job> = jobs.into_iter().collect();
+        self.shared
+            .submit_batch(self.sender.as_ref().unwrap(), jobs);
+    }
+
+    /// 执行有返回值的任务，通过返回的 `TaskHandle` 获取结果
+    ///
+    /// 只关心副作用、不需要结果时继续使用 `execute` 即可
+    pub fn execute_with_result<F, T>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        self.execute(move || {
+            // 调用方丢弃了 TaskHandle 时发送失败，结果直接丢弃即可
+            let _ = sender.send(f());
+        });
+        TaskHandle { receiver }
+    }
+
+    /// 供异步代码使用：把 CPU 密集的计算交给线程池执行，然后 `.await` 它的结果
+    ///
+    /// 与 `tokio::task::spawn_blocking` 类似，但使用的是这个线程池，计算期间不会占用异步运行时的线程。
+    /// 任务 panic 时，等待结果的一方也会 panic
+    pub fn spawn_blocking<F, T>(&self, f: F) -> impl Future<Output = T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let handle = self.execute_with_result(f);
+        async move {
+            handle
+                .await
+                .expect("task panicked before producing a result")
+        }
+    }
+
+    /// 在 `delay` 之后执行任务
+    ///
+    /// 无论提交多少延迟任务都只使用一个定时器线程，关闭线程池时还没有到期的任务会被丢弃
+    pub fn execute_after<F>(&self, delay: Duration, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.timer_thread.get_or_init(|| {
+            let timer = Arc::clone(&self.timer);
+            let shared = Arc::clone(&self.shared);
+            let sender = self.sender.as_ref().unwrap().clone();
+            thread::spawn(move || timer.run(&shared, &sender))
+        });
+
+        let mut state = self.timer.state.lock().unwrap();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.queue.push(Delayed {
+            deadline: Instant::now() + delay,
+            seq,
+            job: Box::new(f),
+        });
+        // 新任务可能比之前最早的任务更早到期，唤醒定时器线程重新计算等待时间
+        self.timer.condvar.notify_one();
+    }
+
+    /// 返回一个可以克隆、可以发送到其它线程的任务提交句柄
+    pub fn handle(&self) -> PoolHandle {
+        self.shared.handles.fetch_add(1, Ordering::SeqCst);
+        PoolHandle {
+            shared: Arc::clone(&self.shared),
+            sender: self.sender.as_ref().unwrap().clone(),
+        }
+    }
+
+    /// 提交一个可以被取消的任务，闭包会收到一个取消标记，返回值是同一个标记，调用方可以用它单独取消这个任务
+    pub fn execute_cancellable<F>(&self, f: F) -> CancellationToken
+    where
+        F: FnOnce(CancellationToken) + Send + 'static,
+    {
+        let token = CancellationToken::new();
+        {
+            let mut tokens = self.tokens.lock().unwrap();
+            // 任务结束后闭包持有的标记会被释放，只剩下列表中的一份时说明已经没有人关心它了
+            tokens.retain(|token| Arc::strong_count(&token.0) > 1);
+            tokens.push(token.clone());
+        }
+
+        let job_token = token.clone();
+        self.execute(move || f(job_token));
+        token
+    }
+
+    /// 在线程池中执行任务，并最多等待 `dur` 时间获取它的返回值
+    ///
+    /// 注意：闭包无法被抢占，超时只是让调用方不再阻塞等待，任务本身会继续在工作线程中运行直到结束，
+    /// 它的返回值会被丢弃。需要真正停止任务时请使用 `execute_cancellable`。
+    /// 任务 panic 时同样拿不到结果，也会返回 `Err(Timeout)`。
+    pub fn execute_with_timeout<F, T>(&self, f: F, dur: Duration) -> Result<T, Timeout>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        self.execute(move || {
+            // 调用方已经超时返回时接收端被丢弃，发送失败可以忽略
+            let _ = tx.send(f());
+        });
+        rx.recv_timeout(dur).map_err(|_| Timeout)
+    }
+
+    /// 取消所有未完成的可取消任务，然后与 `shutdown_graceful` 相同，`Drop` 时也使用这种方式
+    ///
+    /// 不检查取消标记的任务仍然会执行完，这里只负责通知
+    pub fn shutdown(&mut self) {
+        self.cancel_all();
+        self.shutdown_graceful();
+    }
+
+    /// 立即关闭：取消可取消的任务，正在执行的任务会执行完，队列中还没有开始的任务全部丢弃
+    ///
+    /// 之后通过 `PoolHandle` 提交的任务同样会被丢弃
+    pub fn shutdown_now(&mut self) {
+        self.shared.discard.store(true, Ordering::SeqCst);
+        self.cancel_all();
+        self.shutdown_graceful();
+    }
+
+    /// 执行完队列中已经提交的任务，然后等待所有线程退出，可取消的任务也不会被取消
+    ///
+    /// 还有 `PoolHandle` 存活时它们仍然可以提交任务，工作线程不会退出，所以这里不等待，
+    /// 最后一个句柄被丢弃后工作线程会自行退出
+    pub fn shutdown_graceful(&mut self) {
+        for mut worker in self.close() {
+            if let Some(thread) = worker.thread.take() {
+                let id = worker.id;
+                self.shared.log(format_args!("Shutting down worker {id}"));
+                thread.join().unwrap();
+                self.shared.log(format_args!("Shut down worker {id}"));
+            }
+        }
+    }
+
+    /**
+     * 与 `shutdown_graceful` 相同，但最多等待 `dur`，返回到期时还没有退出的工作线程 id，按 id 排序
+     *
+     * 线程无法被强制终止，没有退出的线程会被分离：它们可能仍在执行卡住的任务，直到任务结束或者进程退出。
+     * 之后释放线程池时不会再等待这些线程
+     *
+     * 还有 `PoolHandle` 存活时工作线程不会退出，这时立即返回所有工作线程的 id
+     */
+    pub fn shutdown_timeout(&mut self, dur: Duration) -> Result<(), Vec<usize>> {
+        let deadline = Instant::now() + dur;
+        let workers = self.close();
+        if self.shared.handles.load(Ordering::SeqCst) > 0 {
+            let mut live: Vec<usize> = self
+                .shared
+                .workers
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|worker| worker.id)
+                .collect();
+            live.sort_unstable();
+            return Err(live);
+        }
+        let mut detached = Vec::new();
+        for mut worker in workers {
+            let Some(thread) = worker.thread.take() else {
+                continue;
+            };
+            while !thread.is_finished() && Instant::now() < deadline {
+                thread::sleep(POLL_INTERVAL.min(deadline - Instant::now()));
+            }
+            if thread.is_finished() {
+                thread.join().unwrap();
+            } else {
+                // 丢弃 JoinHandle 就是分离线程
+                self.shared
+                    .log(format_args!("Detaching worker {}", worker.id));
+                detached.push(worker.id);
+            }
+        }
+        if detached.is_empty() {
+            Ok(())
+        } else {
+            detached.sort_unstable();
+            Err(detached)
+        }
+    }
+
+    fn cancel_all(&self) {
+        for token in self.tokens.lock().unwrap().drain(..) {
+            token.cancel();
+        }
+    }
+
+    /**
+     * 停止定时器并关闭任务队列，返回需要等待退出的工作线程
+     *
+     * 还有 `PoolHandle` 存活时它们仍然可以提交任务，工作线程不会退出，这时返回空列表
+     */
+    fn close(&mut self) -> Vec<Worker> {
+        // 定时器线程持有一个 Sender，需要先让它退出，工作线程才能收到断开的消息
+        if let Some(timer_thread) = self.timer_thread.take() {
+            self.timer.state.lock().unwrap().stopped = true;
+            self.timer.condvar.notify_one();
+            timer_thread.join().unwrap();
+        }
+
+        drop(self.sender.take());
+        if self.shared.handles.load(Ordering::SeqCst) > 0 {
+            return Vec::new();
+        }
+        std::mem::take(&mut *self.shared.workers.lock().unwrap())
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        drop(self.sender.take());
-        for worker in &mut self.workers {
-            if let Some(thread) = worker.thread.take() {
-                println!("Shutting down worker {}", worker.id);
-                thread.join().unwrap();
-                println!("Shut down worker {}", worker.id);
+        self.shutdown();
+    }
+}
+
+/**
+ * 线程池的任务提交句柄
+ *
+ * 内部只是克隆的 `Sender` 和共享状态，克隆的开销很小，可以分发给多个模块或线程，不需要到处传递 `&ThreadPool`
+ */
+pub struct PoolHandle {
+    shared: Arc<Shared>,
+    sender: Sender<Message>,
+}
+
+impl PoolHandle {
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared
+            .submit(&self.sender, Task::unlabeled(Box::new(f)));
+    }
+}
+
+impl Clone for PoolHandle {
+    fn clone(&self) -> Self {
+        self.shared.handles.fetch_add(1, Ordering::SeqCst);
+        PoolHandle {
+            shared: Arc::clone(&self.shared),
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl Drop for PoolHandle {
+    fn drop(&mut self) {
+        self.shared.handles.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Default for ThreadPool {
+    fn default() -> Self {
+        ThreadPool::with_available_parallelism()
+    }
+}
+
+/**
+ * 每个任务都返回一个 `T` 的线程池，通过 [`ResultPool::results`] 按完成的先后顺序逐个取出结果
+ *
+ * 适合“分发 - 汇总”的场景：先完成的结果可以先处理，不需要等待所有任务结束
+ */
+pub struct ResultPool<T> {
+    pool: ThreadPool,
+    sender: Sender<Option<T>>,
+    receiver: Receiver<Option<T>>,
+    /// 已经提交、但结果还没有被取出的任务数
+    pending: Cell<usize>,
+}
+
+impl<T: Send + 'static> ResultPool<T> {
+    pub fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        ResultPool {
+            pool: ThreadPool::new(size),
+            sender,
+            receiver,
+            pending: Cell::new(0),
+        }
+    }
+
+    pub fn submit<F>(&self, f: F)
+    where
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let sender = self.sender.clone();
+        self.pending.set(self.pending.get() + 1);
+        self.pool.execute(move || {
+            // 任务 panic 时也要发送一个 None，否则 results 会一直等待这个永远不会到达的结果
+            match panic::catch_unwind(AssertUnwindSafe(f)) {
+                Ok(value) => {
+                    let _ = sender.send(Some(value));
+                }
+                Err(payload) => {
+                    let _ = sender.send(None);
+                    // 继续 panic，交给线程池的 panic 回调处理
+                    panic::resume_unwind(payload);
+                }
+            }
+        });
+    }
+
+    /**
+     * 按完成顺序返回结果，下一个结果还没有完成时阻塞等待
+     *
+     * 已经提交的任务全部取出之后迭代结束，panic 的任务没有结果，直接跳过
+     */
+    pub fn results(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || {
+            while self.pending.get() > 0 {
+                self.pending.set(self.pending.get() - 1);
+                if let Some(value) = self.receiver.recv().ok()? {
+                    return Some(value);
+                }
+            }
+            None
+        })
+    }
+}
+
+/**
+ * 每个工作线程有自己的任务队列的线程池
+ *
+ * `execute` 按照轮询的顺序把任务依次放进各个工作线程的队列，工作线程先从自己队列的头部取任务，
+ * 自己的队列空了时从其它线程队列的尾部偷一个，某个线程被慢任务占住时，排在它后面的任务会被空闲的线程取走。
+ * 与 [`ThreadPool`] 相比只提供最基本的 `execute`，没有伸缩、定时和统计等功能。
+ * 释放时等待所有已经提交的任务执行完再退出
+ */
+pub struct RoundRobinPool {
+    shared: Arc<RoundRobinShared>,
+    workers: Vec<JoinHandle<()>>,
+    /// 下一个任务分配给的工作线程
+    next: AtomicUsize,
+}
+
+struct RoundRobinShared {
+    queues: Vec<Mutex<VecDeque<Job>>>,
+    /// 所有队列中还没有被取走的任务数，为 0 时工作线程在 `available` 上等待
+    pending: Mutex<usize>,
+    available: Condvar,
+    /// 只在持有 `pending` 的锁时修改，避免工作线程错过唤醒
+    shutdown: AtomicBool,
+}
+
+impl RoundRobinShared {
+    /// 先取自己队列的头部，再依次从其它队列的尾部偷
+    fn pop(&self, id: usize) -> Option<Job> {
+        let len = self.queues.len();
+        let job = (0..len).find_map(|offset| {
+            let mut queue = self.queues[(id + offset) % len].lock().unwrap();
+            if offset == 0 {
+                queue.pop_front()
+            } else {
+                queue.pop_back()
+            }
+        })?;
+        *self.pending.lock().unwrap() -= 1;
+        Some(job)
+    }
+
+    fn run(&self, id: usize) {
+        loop {
+            if let Some(job) = self.pop(id) {
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                    default_panic_handler(id, payload.as_ref());
+                }
+                continue;
+            }
+            // 计数和入队在同一把锁内完成，计数不为 0 时总能取到任务，或者任务刚被其它线程取走
+            let mut pending = self.pending.lock().unwrap();
+            while *pending == 0 {
+                if self.shutdown.load(Ordering::SeqCst) {
+                    return;
+                }
+                pending = self.available.wait(pending).unwrap();
+            }
+        }
+    }
+}
+
+impl RoundRobinPool {
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0);
+        let shared = Arc::new(RoundRobinShared {
+            queues: (0..size).map(|_| Mutex::new(VecDeque::new())).collect(),
+            pending: Mutex::new(0),
+            available: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+        let workers = (0..size)
+            .map(|id| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || shared.run(id))
+            })
+            .collect();
+        RoundRobinPool {
+            shared,
+            workers,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.shared.queues.len();
+        // 先计数再入队，并且入队期间一直持有计数的锁：工作线程取走任务之后才能减少计数，计数不会小于 0
+        let mut pending = self.shared.pending.lock().unwrap();
+        *pending += 1;
+        self.shared.queues[index]
+            .lock()
+            .unwrap()
+            .push_back(Box::new(f));
+        drop(pending);
+        self.shared.available.notify_one();
+    }
+}
+
+impl Drop for RoundRobinPool {
+    fn drop(&mut self) {
+        {
+            let _pending = self.shared.pending.lock().unwrap();
+            self.shared.shutdown.store(true, Ordering::SeqCst);
+        }
+        self.shared.available.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/**
+ * 从通道中收集 `expected` 个带序号的结果，按照序号从小到大返回
+ *
+ * 并行任务完成的先后顺序是不确定的，每个任务把自己的序号和结果一起发送回来，
+ * 收集完之后再排序即可恢复提交时的顺序。如果所有发送端提前被释放（比如某个任务 panic 了），
+ * 不会一直阻塞，而是返回已经收到的结果
+ */
+pub fn ordered_collect<T>(rx: Receiver<(usize, T)>, expected: usize) -> Vec<T> {
+    let mut results: Vec<(usize, T)> = rx.iter().take(expected).collect();
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, value)| value).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// 第一个任务运行期间提交 5 个计数任务，然后按照指定的方式关闭线程池，返回计数
+    fn count_after_shutdown(shutdown: fn(&mut ThreadPool)) -> usize {
+        let mut pool = ThreadPool::new(1);
+        let counter = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || {
+            tx.send(()).unwrap();
+            thread::sleep(Duration::from_millis(50));
+        });
+        rx.recv().unwrap();
+        for _ in 0..5 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        shutdown(&mut pool);
+        counter.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn default_pool_uses_available_parallelism() {
+        let pool = ThreadPool::default();
+        let expected = thread::available_parallelism().map_or(1, |n| n.get());
+        assert!(pool.thread_count() >= 1);
+        assert_eq!(expected, pool.thread_count());
+
+        let (tx, rx) = mpsc::channel();
+        for i in 0..4 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(i).unwrap());
+        }
+        drop(tx);
+        let mut received: Vec<i32> = rx.iter().collect();
+        received.sort_unstable();
+        assert_eq!(vec![0, 1, 2, 3], received);
+    }
+
+    #[test]
+    fn results_arrive_in_completion_order() {
+        let pool = ResultPool::new(3);
+        for delay in [150, 50, 100] {
+            pool.submit(move || {
+                thread::sleep(Duration::from_millis(delay));
+                delay
+            });
+        }
+        pool.submit(|| panic!("no result"));
+        assert_eq!(vec![50, 100, 150], pool.results().collect::<Vec<_>>());
+
+        // 所有结果都取出之后，之后提交的任务可以继续取
+        pool.submit(|| 7);
+        assert_eq!(vec![7], pool.results().collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn spawn_blocking_can_be_awaited() {
+        let pool = ThreadPool::new(2);
+        let (sum, squares) = tokio::join!(
+            pool.spawn_blocking(|| (1..=1_000_000u64).sum::<u64>()),
+            pool.spawn_blocking(|| (1..=100u64).map(|n| n * n).sum::<u64>()),
+        );
+        assert_eq!(500_000_500_000, sum);
+        assert_eq!(338_350, squares);
+    }
+
+    #[test]
+    fn graceful_shutdown_runs_queued_jobs() {
+        assert_eq!(5, count_after_shutdown(ThreadPool::shutdown_graceful));
+    }
+
+    #[test]
+    fn graceful_shutdown_does_not_cancel_jobs() {
+        let mut pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..3 {
+            let tx = tx.clone();
+            pool.execute_cancellable(move |token| tx.send(token.is_cancelled()).unwrap());
+        }
+        drop(tx);
+        pool.shutdown_graceful();
+        assert_eq!(vec![false; 3], rx.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn shutdown_now_discards_queued_jobs() {
+        assert_eq!(0, count_after_shutdown(ThreadPool::shutdown_now));
+    }
+
+    #[test]
+    fn worker_init_runs_once_per_worker() {
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&started);
+        let mut pool = ThreadPool::builder()
+            .min_threads(3)
+            .max_threads(3)
+            .with_worker_init(move |id| recorded.lock().unwrap().push(id))
+            .build();
+
+        let handles: Vec<_> = (0..10)
+            .map(|n| pool.execute_with_result(move || n))
+            .collect();
+        for (n, handle) in handles.into_iter().enumerate() {
+            assert_eq!(Ok(n), handle.join());
+        }
+        pool.shutdown();
+
+        let mut started = started.lock().unwrap().clone();
+        started.sort_unstable();
+        assert_eq!(vec![0, 1, 2], started);
+    }
+
+    #[test]
+    fn panic_handler_receives_worker_id() {
+        let panicked = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&panicked);
+        let pool = ThreadPool::builder()
+            .min_threads(1)
+            .max_threads(1)
+            .with_panic_handler(move |id, payload| {
+                let message = payload.downcast_ref::<&str>().copied().unwrap_or_default();
+                recorded.lock().unwrap().push((id, message.to_string()));
+            })
+            .build();
+
+        pool.execute(|| panic!("bad job"));
+        // 同一个工作线程在 panic 之后继续执行后面的任务
+        assert_eq!(Ok(1), pool.execute_with_result(|| 1).join());
+        assert_eq!(
+            vec![(0, String::from("bad job"))],
+            *panicked.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn panic_handler_sees_job_label() {
+        let labels = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&labels);
+        let pool = ThreadPool::builder()
+            .min_threads(1)
+            .max_threads(1)
+            .with_panic_handler(move |_, _| recorded.lock().unwrap().push(current_label()))
+            .build();
+
+        pool.execute_named("compress-report", || panic!("disk full"));
+        pool.execute(|| panic!("unnamed"));
+        let label = pool.execute_with_result(current_label).join();
+
+        assert_eq!(Ok(None), label);
+        assert_eq!(
+            vec![Some(String::from("compress-report")), None],
+            *labels.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn shutdown_timeout_detaches_stuck_worker() {
+        let mut pool = ThreadPool::new(2);
+        let release = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel();
+        let flag = Arc::clone(&release);
+        pool.execute(move || {
+            tx.send(()).unwrap();
+            // 除非测试最后放行，否则永远不会结束
+            while !flag.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(5));
+            }
+        });
+        rx.recv().unwrap();
+        thread::sleep(Duration::from_millis(50));
+        let stuck = pool.stalled_workers(Duration::from_millis(20));
+        assert_eq!(1, stuck.len());
+
+        let start = Instant::now();
+        assert_eq!(
+            Err(stuck),
+            pool.shutdown_timeout(Duration::from_millis(200))
+        );
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(200), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+        release.store(true, Ordering::SeqCst);
+
+        let mut pool = ThreadPool::new(2);
+        pool.execute(|| thread::sleep(Duration::from_millis(20)));
+        assert_eq!(Ok(()), pool.shutdown_timeout(Duration::from_secs(5)));
+
+        // 句柄还在时工作线程不会退出
+        let mut pool = ThreadPool::new(2);
+        let handle = pool.handle();
+        assert_eq!(
+            Err(vec![0, 1]),
+            pool.shutdown_timeout(Duration::from_millis(20))
+        );
+        drop(handle);
+    }
+
+    #[test]
+    fn long_job_is_reported_as_stalled() {
+        let pool = ThreadPool::new(2);
+        assert!(pool.stalled_workers(Duration::ZERO).is_empty());
+
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || {
+            tx.send(()).unwrap();
+            thread::sleep(Duration::from_millis(300));
+        });
+        rx.recv().unwrap();
+        pool.execute(|| {});
+        thread::sleep(Duration::from_millis(100));
+
+        // 只有执行长任务的线程被标记，空闲的线程不算
+        assert_eq!(1, pool.stalled_workers(Duration::from_millis(50)).len());
+        assert!(pool.stalled_workers(Duration::from_secs(10)).is_empty());
+    }
+
+    #[test]
+    fn ordered_collect_restores_order() {
+        let pool = ThreadPool::new(4);
+        let (tx, rx) = mpsc::channel();
+        for (index, millis) in [40, 0, 20, 10].into_iter().enumerate() {
+            let tx = tx.clone();
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(millis));
+                tx.send((index, index * 10)).unwrap();
+            });
+        }
+        drop(tx);
+        assert_eq!(vec![0, 10, 20, 30], ordered_collect(rx, 4));
+    }
+
+    #[test]
+    fn ordered_collect_stops_when_senders_drop() {
+        let (tx, rx) = mpsc::channel();
+        tx.send((2, "c")).unwrap();
+        tx.send((0, "a")).unwrap();
+        drop(tx);
+        assert_eq!(vec!["a", "c"], ordered_collect(rx, 3));
+    }
+
+    #[test]
+    fn shutdown_cancels_looping_job() {
+        let mut pool = ThreadPool::new(2);
+        let (tx, rx) = mpsc::channel();
+        pool.execute_cancellable(move |token| {
+            tx.send(()).unwrap();
+            while !token.is_cancelled() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            tx.send(()).unwrap();
+        });
+
+        // 确保任务已经开始运行
+        rx.recv().unwrap();
+        let start = Instant::now();
+        pool.shutdown();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(rx.try_recv().is_ok());
+    }
+
+    #[test]
+    fn slow_job_times_out() {
+        let pool = ThreadPool::new(1);
+        let result = pool.execute_with_timeout(
+            || {
+                thread::sleep(Duration::from_millis(300));
+                1
+            },
+            Duration::from_millis(20),
+        );
+        assert_eq!(Err(Timeout), result);
+
+        // 任务不会被终止，之后提交的任务在它结束后照常执行
+        let result = pool.execute_with_timeout(|| 2, Duration::from_secs(5));
+        assert_eq!(Ok(2), result);
+    }
+
+    #[test]
+    fn timing_summary_brackets_job_durations() {
+        let mut pool = ThreadPool::new(2);
+        assert_eq!(TimingSummary::default(), pool.timing_summary());
+
+        for millis in [20, 60] {
+            pool.execute(move || thread::sleep(Duration::from_millis(millis)));
+        }
+        // 等待所有任务执行完
+        pool.shutdown();
+
+        let summary = pool.timing_summary();
+        assert_eq!(2, summary.count);
+        assert!(
+            summary.min >= Duration::from_millis(20) && summary.min < Duration::from_millis(60)
+        );
+        assert!(summary.max >= Duration::from_millis(60));
+        assert!(summary.min <= summary.mean && summary.mean <= summary.max);
+    }
+
+    #[test]
+    fn handles_submit_from_several_threads() {
+        let mut pool = ThreadPool::new(2);
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let submitters: Vec<_> = (0..2)
+            .map(|_| {
+                let handle = pool.handle();
+                let count = Arc::clone(&count);
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        let count = Arc::clone(&count);
+                        handle.execute(move || {
+                            count.fetch_add(1, Ordering::SeqCst);
+                        });
+                    }
+                })
+            })
+            .collect();
+        for submitter in submitters {
+            submitter.join().unwrap();
+        }
+
+        // 句柄都已经随着线程结束被丢弃，shutdown 会等待所有任务执行完
+        pool.shutdown();
+        assert_eq!(20, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn task_handle_returns_value() {
+        let pool = ThreadPool::new(2);
+        let handle = pool.execute_with_result(|| format!("{}-{}", "hello", 42));
+        assert_eq!(Ok(String::from("hello-42")), handle.join());
+
+        // 也可以在异步代码中 await
+        let handle = pool.execute_with_result(|| 6 * 7);
+        assert_eq!(Ok(42), futures::executor::block_on(handle));
+    }
+
+    #[test]
+    fn delayed_jobs_wait_for_their_deadline() {
+        let pool = ThreadPool::new(2);
+        let (tx, rx) = mpsc::channel();
+        let start = Instant::now();
+        for (name, millis) in [("slow", 100), ("fast", 50)] {
+            let tx = tx.clone();
+            pool.execute_after(Duration::from_millis(millis), move || {
+                tx.send((name, start.elapsed())).unwrap();
+            });
+        }
+
+        let (name, elapsed) = rx.recv().unwrap();
+        assert_eq!("fast", name);
+        assert!(elapsed >= Duration::from_millis(50));
+        let (name, elapsed) = rx.recv().unwrap();
+        assert_eq!("slow", name);
+        assert!(elapsed >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn batch_runs_every_job() {
+        let mut pool = ThreadPool::new(4);
+        let count = Arc::new(AtomicUsize::new(0));
+        let jobs = (0..1000).map(|_| {
+            let count = Arc::clone(&count);
+            Box::new(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            }) as Job
+        });
+        pool.execute_batch(jobs);
+
+        pool.shutdown();
+        assert_eq!(1000, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn retry_succeeds_once_queue_drains() {
+        let pool = ThreadPool::builder()
+            .min_threads(1)
+            .max_threads(1)
+            .queue_capacity(1)
+            .build();
+
+        // 唯一的线程被第一个任务占住，第二个任务把队列占满
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+        });
+        started_rx.recv().unwrap();
+        pool.try_execute(|| {}).unwrap();
+        assert_eq!(Err(ExecuteError::QueueFull), pool.try_execute(|| {}));
+        assert_eq!(
+            Err(ExecuteError::QueueFull),
+            pool.execute_retry(|| {}, 2, Duration::from_millis(1))
+        );
+
+        // 稍后放行第一个任务，线程取走排队的任务之后重试就能成功
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(30));
+            release_tx.send(()).unwrap();
+        });
+        let (done_tx, done_rx) = mpsc::channel();
+        pool.execute_retry(
+            move || done_tx.send(()).unwrap(),
+            8,
+            Duration::from_millis(5),
+        )
+        .unwrap();
+        done_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+    }
+
+    #[test]
+    fn poisoned_receiver_lock_is_recovered() {
+        // 可伸缩的线程池中空闲线程会定期释放接收端的锁，任务才有机会拿到它
+        let pool = ThreadPool::builder()
+            .min_threads(1)
+            .max_threads(2)
+            .with_panic_handler(|_, _| {})
+            .build();
+        let shared = Arc::clone(&pool.shared);
+        pool.execute(move || {
+            let _guard = shared.lock_receiver();
+            panic!("poison the receiver lock");
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !pool.shared.receiver.is_poisoned() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(pool.shared.receiver.is_poisoned());
+
+        let (tx, rx) = mpsc::channel();
+        for i in 0..4 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(i).unwrap());
+        }
+        let mut received: Vec<i32> = (0..4)
+            .map(|_| rx.recv_timeout(Duration::from_secs(2)).unwrap())
+            .collect();
+        received.sort();
+        assert_eq!(vec![0, 1, 2, 3], received);
+    }
+
+    #[test]
+    fn round_robin_pool_steals_from_busy_workers() {
+        const JOBS: usize = 20;
+
+        let pool = RoundRobinPool::new(2);
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        // 第一个任务一直占住一个工作线程，直到测试放行
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+        });
+        started_rx.recv().unwrap();
+
+        // 轮流分配时一半的任务排在被占住的线程的队列中，不偷任务的话放行之前它们都不会执行
+        let (done_tx, done_rx) = mpsc::channel();
+        for n in 0..JOBS {
+            let done_tx = done_tx.clone();
+            pool.execute(move || done_tx.send(n).unwrap());
+        }
+        let mut done: Vec<usize> = (0..JOBS)
+            .map(|_| done_rx.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        done.sort_unstable();
+        assert_eq!((0..JOBS).collect::<Vec<_>>(), done);
+
+        release_tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn round_robin_pool_counts_jobs_under_contention() {
+        for _ in 0..200 {
+            let counter = Arc::new(AtomicUsize::new(0));
+            {
+                let pool = RoundRobinPool::new(4);
+                for _ in 0..50 {
+                    let counter = Arc::clone(&counter);
+                    pool.execute(move || {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
             }
+            assert_eq!(50, counter.load(Ordering::SeqCst));
+        }
+    }
+
+    #[test]
+    fn resize_grows_the_pool() {
+        let mut pool = ThreadPool::new(1);
+        pool.resize(3);
+        assert_eq!(3, pool.thread_count());
+
+        // 三个任务互相等待，只有三个线程同时运行时才能都看到计数达到 3
+        let started = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..3 {
+            let started = Arc::clone(&started);
+            let tx = tx.clone();
+            pool.execute(move || {
+                started.fetch_add(1, Ordering::SeqCst);
+                let deadline = Instant::now() + Duration::from_secs(2);
+                while started.load(Ordering::SeqCst) < 3 && Instant::now() < deadline {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                tx.send(started.load(Ordering::SeqCst)).unwrap();
+            });
+        }
+        drop(tx);
+        assert_eq!(vec![3, 3, 3], rx.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn resize_shrinks_without_losing_jobs() {
+        let mut pool = ThreadPool::new(4);
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..20 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(2));
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        pool.resize(1);
+        assert_eq!(1, pool.thread_count());
+        // 多出来的线程已经被 join，列表中只剩下一个
+        assert_eq!(1, pool.shared.workers.lock().unwrap().len());
+
+        pool.shutdown();
+        assert_eq!(20, done.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn pool_grows_under_burst_and_shrinks_when_idle() {
+        let pool = ThreadPool::builder()
+            .min_threads(1)
+            .max_threads(4)
+            .idle_timeout(Duration::from_millis(100))
+            .build();
+        assert_eq!(1, pool.thread_count());
+
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..8 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(50));
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        assert_eq!(4, pool.thread_count());
+
+        // 所有任务结束后，多出来的线程空闲超时退出
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.thread_count() > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
         }
+        assert_eq!(8, done.load(Ordering::SeqCst));
+        assert_eq!(1, pool.thread_count());
     }
 }
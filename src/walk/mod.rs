@@ -0,0 +1,369 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::Config;
+
+/// 递归搜索时需要跳过的版本库目录
+const GIT_DIR: &str = ".git";
+
+/**
+ * `.gitignore` 中的一条规则
+ *
+ * 只支持常见的写法：
+ * - `*.log`：不包含 `/` 的规则在任意层级匹配文件名
+ * - `dir/`：以 `/` 结尾的规则只匹配目录
+ * - `/build`：以 `/` 开头（或中间包含 `/`）的规则相对于 `.gitignore` 所在目录匹配
+ * - `!keep.log`：以 `!` 开头的规则表示重新包含
+ */
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    /// `.gitignore` 所在的目录，锚定规则相对于它匹配
+    base: PathBuf,
+    pattern: String,
+    negate: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl IgnoreRule {
+    fn parse(base: &Path, line: &str) -> Option<IgnoreRule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negate, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        // 开头的 `/` 只表示锚定，本身不参与匹配
+        let anchored = line.contains('/');
+        let pattern = line.trim_start_matches('/').to_string();
+        if pattern.is_empty() {
+            return None;
+        }
+
+        Some(IgnoreRule {
+            base: base.to_path_buf(),
+            pattern,
+            negate,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Ok(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+
+        if self.anchored {
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            glob_match(&self.pattern, &relative)
+        } else {
+            relative
+                .file_name()
+                .map(|name| glob_match(&self.pattern, &name.to_string_lossy()))
+                .unwrap_or(false)
+        }
+    }
+}
+
+/// 读取目录下的 `.gitignore`，不存在时返回空规则
+fn read_gitignore(dir: &Path) -> io::Result<Vec<IgnoreRule>> {
+    match fs::read_to_string(dir.join(".gitignore")) {
+        Ok(content) => Ok(content
+            .lines()
+            .filter_map(|line| IgnoreRule::parse(dir, line))
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// 后出现的规则优先级更高，与 git 的行为保持一致
+fn is_ignored(rules: &[IgnoreRule], path: &Path, is_dir: bool) -> bool {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.matches(path, is_dir))
+        .map(|rule| !rule.negate)
+        .unwrap_or(false)
+}
+
+/**
+ * 简单的通配符匹配，`*` 匹配任意个非 `/` 字符，`?` 匹配单个非 `/` 字符
+ */
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // 回溯法：记录最近一个 `*` 的位置，匹配失败时让 `*` 多吃一个字符
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' && text[t] != '/' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star.filter(|&(_, star_t)| text[star_t] != '/') {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/**
+ * 递归收集目录下需要搜索的文件，结果按路径排序保证输出稳定
+ *
+ * 同时返回遍历时出错的次数，见 [`walk_files`]
+ */
+pub fn collect_files(root: &Path, config: &Config) -> (Vec<PathBuf>, usize) {
+    let mut files = Vec::new();
+    let errors = walk_files(root, config, |path| files.push(path));
+    (files, errors)
+}
+
+/**
+ * 递归遍历目录，每发现一个需要搜索的文件就调用一次 `on_file`，顺序与 [`collect_files`] 相同
+ *
+ * 不需要等整个目录遍历完就可以开始处理已经发现的文件。
+ * 无法读取的目录、`.gitignore` 或条目与 grep 一样输出 `路径: 错误` 到标准错误后跳过，继续遍历其它条目，
+ * 返回值是出错的次数
+ */
+pub fn walk_files(root: &Path, config: &Config, mut on_file: impl FnMut(PathBuf)) -> usize {
+    let mut walker = Walker {
+        config,
+        visited: HashSet::new(),
+        on_file: &mut on_file,
+        errors: 0,
+    };
+    walker.walk_dir(root, &[], 0);
+    walker.errors
+}
+
+struct Walker<'a> {
+    config: &'a Config,
+    /// 已经进入过的目录（规范化后的真实路径），只在跟随符号链接时记录，用来避免死循环
+    visited: HashSet<PathBuf>,
+    on_file: &'a mut dyn FnMut(PathBuf),
+    /// 已经输出到标准错误的错误数量
+    errors: usize,
+}
+
+impl Walker<'_> {
+    fn report(&mut self, path: &Path, error: io::Error) {
+        eprintln!("{}: {error}", path.display());
+        self.errors += 1;
+    }
+
+    /// `depth` 是 `dir` 中条目的深度，起始目录下的条目深度为 0
+    fn walk_dir(&mut self, dir: &Path, parent_rules: &[IgnoreRule], depth: usize) {
+        // 不跟随符号链接时不会重复进入同一个目录，不需要为每个目录解析真实路径
+        if self.config.follow_symlinks {
+            match fs::canonicalize(dir) {
+                Ok(real) => {
+                    if !self.visited.insert(real) {
+                        return;
+                    }
+                }
+                // 无法解析真实路径时跳过这个目录，继续遍历其它目录
+                Err(e) => return self.report(dir, e),
+            }
+        }
+
+        // 子目录继承父目录的规则，再追加自己的 `.gitignore`，读取失败时只使用继承的规则
+        let mut rules = parent_rules.to_vec();
+        if !self.config.no_ignore {
+            match read_gitignore(dir) {
+                Ok(own) => rules.extend(own),
+                Err(e) => self.report(&dir.join(".gitignore"), e),
+            }
+        }
+
+        let read_dir = match fs::read_dir(dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) => return self.report(dir, e),
+        };
+        let mut entries = Vec::new();
+        for entry in read_dir {
+            match entry {
+                Ok(entry) => entries.push(entry.path()),
+                Err(e) => self.report(dir, e),
+            }
+        }
+        entries.sort();
+
+        for path in entries {
+            // symlink_metadata 不会跟随符号链接，可以区分链接本身和它指向的目标
+            let is_symlink = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata.file_type().is_symlink(),
+                Err(e) => {
+                    self.report(&path, e);
+                    continue;
+                }
+            };
+            let is_dir = path.is_dir();
+            if is_symlink && !path.exists() {
+                // 失效的符号链接
+                continue;
+            }
+
+            if !self.config.no_ignore {
+                if is_dir && path.file_name().is_some_and(|name| name == GIT_DIR) {
+                    continue;
+                }
+                if is_ignored(&rules, &path, is_dir) {
+                    continue;
+                }
+            }
+
+            if is_dir {
+                // 默认不进入指向目录的符号链接，普通目录总是会被遍历
+                if is_symlink && !self.config.follow_symlinks {
+                    continue;
+                }
+                if self.config.max_depth.is_some_and(|max| depth >= max) {
+                    continue;
+                }
+                self.walk_dir(&path, &rules, depth + 1);
+            } else if self.wants_file(&path) {
+                (self.on_file)(path);
+            }
+        }
+    }
+
+    /// 配置了扩展名时，只保留扩展名在列表中的文件，不读取其它文件的内容
+    fn wants_file(&self, path: &Path) -> bool {
+        let extensions = &self.config.extensions;
+        extensions.is_empty()
+            || path
+                .extension()
+                .is_some_and(|ext| extensions.iter().any(|wanted| ext == wanted.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_dir;
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("*.log", "debug.log"));
+        assert!(!glob_match("*.log", "debug.txt"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("src/*.rs", "src/walk/mod.rs"));
+    }
+
+    #[test]
+    fn gitignore_skips_matching_entries() {
+        let root = temp_dir("gitignore_skips_matching_entries");
+        fs::write(root.join(".gitignore"), "*.log\ntarget/\n/secret.txt\n").unwrap();
+        fs::write(root.join("main.rs"), "fn main() {}").unwrap();
+        fs::write(root.join("debug.log"), "fn main() {}").unwrap();
+        fs::write(root.join("secret.txt"), "fn main() {}").unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join("target").join("out.rs"), "fn main() {}").unwrap();
+        fs::create_dir_all(root.join("nested")).unwrap();
+        fs::write(root.join("nested").join("secret.txt"), "fn main() {}").unwrap();
+        fs::write(root.join("nested").join("trace.log"), "fn main() {}").unwrap();
+
+        // 默认遵守 `.gitignore`，与命令行一致
+        let config = Config::default();
+        let files = collect_files(&root, &config).0;
+        let mut names: Vec<_> = files
+            .iter()
+            .map(|p| p.strip_prefix(&root).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        names.sort();
+        assert_eq!(vec![".gitignore", "main.rs", "nested/secret.txt"], names);
+
+        let config = Config {
+            no_ignore: true,
+            ..Config::default()
+        };
+        assert_eq!(7, collect_files(&root, &config).0.len());
+    }
+
+    #[test]
+    fn max_depth_limits_descent() {
+        let root = temp_dir("max_depth_limits_descent");
+        fs::create_dir_all(root.join("one").join("two")).unwrap();
+        fs::write(root.join("top.txt"), "needle").unwrap();
+        fs::write(root.join("one").join("mid.txt"), "needle").unwrap();
+        fs::write(root.join("one").join("two").join("deep.txt"), "needle").unwrap();
+
+        let config = Config {
+            max_depth: Some(1),
+            ..Config::default()
+        };
+        assert_eq!(
+            vec![root.join("one").join("mid.txt"), root.join("top.txt")],
+            collect_files(&root, &config).0
+        );
+
+        let config = Config {
+            max_depth: Some(0),
+            ..Config::default()
+        };
+        assert_eq!(vec![root.join("top.txt")], collect_files(&root, &config).0);
+
+        assert_eq!(3, collect_files(&root, &Config::default()).0.len());
+    }
+
+    #[test]
+    fn unreadable_entries_are_counted_and_skipped() {
+        let root = temp_dir("unreadable_entries_are_counted_and_skipped");
+        // 目录不能作为 `.gitignore` 读取，出错之后仍然继续遍历
+        fs::create_dir_all(root.join(".gitignore")).unwrap();
+        fs::write(root.join("main.rs"), "fn main() {}").unwrap();
+
+        let (files, errors) = collect_files(&root, &Config::default());
+        assert_eq!((vec![root.join("main.rs")], 1), (files, errors));
+
+        let (files, errors) = collect_files(&root.join("missing"), &Config::default());
+        assert!(files.is_empty());
+        assert_eq!(1, errors);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loop_terminates() {
+        let root = temp_dir("symlink_loop_terminates");
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a").join("file.txt"), "hello").unwrap();
+        // a/loop -> 根目录，跟随链接会无限循环下去
+        std::os::unix::fs::symlink(&root, root.join("a").join("loop")).unwrap();
+
+        let config = Config {
+            follow_symlinks: true,
+            ..Config::default()
+        };
+        let files = collect_files(&root, &config).0;
+        assert_eq!(vec![root.join("a").join("file.txt")], files);
+
+        let config = Config {
+            follow_symlinks: false,
+            ..Config::default()
+        };
+        let files = collect_files(&root, &config).0;
+        assert_eq!(vec![root.join("a").join("file.txt")], files);
+    }
+}
@@ -0,0 +1,522 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use crate::threadpool::ThreadPool;
+
+/// 请求体的最大字节数，`Content-Length` 超过它时拒绝请求，避免按照客户端给出的长度分配任意大的内存
+pub const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/**
+ * 解析后的 HTTP 请求
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /**
+     * 从连接中读取一个请求
+     *
+     * 先按行读取请求行和请求头，直到遇到空行；请求头中有 `Content-Length` 时再精确读取这么多字节的请求体，
+     * 没有时请求体为空；超过 [`MAX_BODY_SIZE`] 时返回 `InvalidData` 错误。连接已经关闭、没有更多请求时返回 `None`
+     */
+    pub fn parse<R: BufRead>(reader: &mut R) -> io::Result<Option<Request>> {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line)? == 0 {
+            return Ok(None);
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (Some(method), Some(path), Some(version)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid_data(format!(
+                "malformed request line: {:?}",
+                request_line.trim_end()
+            )));
+        };
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed in the middle of headers",
+                ));
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            // 空行表示请求头结束
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid_data(format!("malformed header: {line:?}")))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        let mut request = Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            version: version.to_string(),
+            headers,
+            body: Vec::new(),
+        };
+
+        if let Some(length) = request.header("Content-Length") {
+            let length: usize = length
+                .parse()
+                .map_err(|_| invalid_data(format!("invalid Content-Length: {length:?}")))?;
+            if length > MAX_BODY_SIZE {
+                return Err(invalid_data(format!(
+                    "Content-Length {length} exceeds the limit of {MAX_BODY_SIZE} bytes"
+                )));
+            }
+            request.body = vec![0; length];
+            reader.read_exact(&mut request.body)?;
+        }
+
+        Ok(Some(request))
+    }
+
+    /// 按名称查找请求头，名称不区分大小写
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// 处理完这个请求后是否需要关闭连接：HTTP/1.1 默认保持连接，HTTP/1.0 默认关闭
+    pub fn wants_close(&self) -> bool {
+        match self.header("Connection") {
+            Some(value) if value.eq_ignore_ascii_case("close") => true,
+            Some(value) if value.eq_ignore_ascii_case("keep-alive") => false,
+            _ => self.version != "HTTP/1.1",
+        }
+    }
+}
+
+/**
+ * HTTP 响应，`Content-Length` 在序列化时根据响应体自动添加
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, reason: &str, body: impl Into<Vec<u8>>) -> Self {
+        Response {
+            status,
+            reason: reason.to_string(),
+            headers: vec![(String::from("Content-Type"), String::from("text/html"))],
+            body: body.into(),
+        }
+    }
+
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Response::new(200, "OK", body)
+    }
+
+    pub fn not_found() -> Self {
+        Response::new(404, "NOT FOUND", "404 Not Found")
+    }
+
+    /// 设置响应头，已经存在同名的响应头时覆盖它
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        match self
+            .headers
+            .iter_mut()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.headers.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, self.reason);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+pub type Handler = fn(&Request) -> Response;
+
+/**
+ * 按照请求方法和路径把请求分发给对应的处理函数，没有匹配的路由时返回 404
+ */
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<(String, String, Handler)>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(mut self, method: &str, path: &str, handler: Handler) -> Self {
+        self.routes
+            .push((method.to_string(), path.to_string(), handler));
+        self
+    }
+
+    pub fn dispatch(&self, request: &Request) -> Response {
+        self.routes
+            .iter()
+            .find(|(method, path, _)| *method == request.method && *path == request.path)
+            .map(|(_, _, handler)| handler(request))
+            .unwrap_or_else(Response::not_found)
+    }
+}
+
+/**
+ * 处理一个连接上的所有请求（HTTP/1.1 keep-alive）
+ *
+ * 循环读取请求并通过路由分发，直到客户端要求 `Connection: close` 或者关闭了连接，
+ * 每个响应都带上 `Connection` 响应头告诉客户端连接是否会继续保持
+ */
+pub fn handle_connection<S: Read + Write>(stream: S, router: &Router) -> io::Result<()> {
+    // 读取使用带缓冲的 reader，写入时通过 get_mut 拿到底层的连接
+    let mut reader = BufReader::new(stream);
+    while let Some(request) = Request::parse(&mut reader)? {
+        let close = request.wants_close();
+        let mut response = router.dispatch(&request);
+        response.set_header("Connection", if close { "close" } else { "keep-alive" });
+
+        let stream = reader.get_mut();
+        stream.write_all(&response.to_bytes())?;
+        stream.flush()?;
+        if close {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/**
+ * 基于线程池的 Web 服务器
+ *
+ * 每个连接都设置了读取超时：客户端建立连接后迟迟不发送数据时，读取会返回错误并关闭连接，
+ * 避免空闲的连接长期占用线程池中的工作线程。超时只针对单次读取，不是整个请求的期限，
+ * 每隔一段时间发送几个字节的客户端（slowloris）仍然可以一直占用工作线程
+ *
+ * 设置了 `max_concurrent_connections` 时，正在处理的连接达到上限后暂停接收新连接，
+ * 新连接留在操作系统的等待队列中，直到有连接处理完成
+ */
+pub struct Server {
+    router: Arc<Router>,
+    read_timeout: Option<Duration>,
+    threads: usize,
+    max_concurrent_connections: Option<usize>,
+}
+
+impl Server {
+    pub fn new(router: Router) -> Self {
+        Server {
+            router: Arc::new(router),
+            read_timeout: Some(Duration::from_secs(30)),
+            threads: 4,
+            max_concurrent_connections: None,
+        }
+    }
+
+    /// 每次读取的超时时间，`None` 表示一直等待
+    pub fn read_timeout(mut self, read_timeout: Option<Duration>) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// 同时处理的连接数上限，`None` 表示不限制
+    ///
+    /// ## Panics
+    ///
+    /// 上限为 `Some(0)` 时 panic，这样的服务器永远不会接收连接
+    pub fn max_concurrent_connections(mut self, limit: Option<usize>) -> Self {
+        assert_ne!(
+            Some(0),
+            limit,
+            "max_concurrent_connections must be at least 1"
+        );
+        self.max_concurrent_connections = limit;
+        self
+    }
+
+    /// 处理一个连接，读取超时时返回 `WouldBlock` 或者 `TimedOut` 错误（取决于平台）
+    pub fn serve_connection(&self, stream: TcpStream) -> io::Result<()> {
+        serve(stream, &self.router, self.read_timeout)
+    }
+
+    /// 接收连接并交给线程池处理，单个连接的错误只打印出来，不影响其它连接
+    pub fn run(&self, listener: TcpListener) -> io::Result<()> {
+        let pool = ThreadPool::new(self.threads);
+        let gate = self.max_concurrent_connections.map(ConnectionGate::new);
+        loop {
+            // 先拿到名额再接收连接，达到上限时阻塞在这里
+            let permit = gate.as_ref().map(ConnectionGate::acquire);
+            let (stream, _) = listener.accept()?;
+            let router = Arc::clone(&self.router);
+            let read_timeout = self.read_timeout;
+            pool.execute(move || {
+                // 连接处理完之后 permit 被 drop，名额归还给 gate
+                let _permit = permit;
+                if let Err(e) = serve(stream, &router, read_timeout) {
+                    eprintln!("connection error: {e}");
+                }
+            });
+        }
+    }
+}
+
+/**
+ * 计数信号量，限制同时处理的连接数
+ */
+struct ConnectionGate {
+    active: Mutex<usize>,
+    released: Condvar,
+    limit: usize,
+}
+
+impl ConnectionGate {
+    fn new(limit: usize) -> Arc<ConnectionGate> {
+        Arc::new(ConnectionGate {
+            active: Mutex::new(0),
+            released: Condvar::new(),
+            limit,
+        })
+    }
+
+    /// 获取一个名额，没有空闲名额时等待其它连接释放
+    fn acquire(gate: &Arc<ConnectionGate>) -> ConnectionPermit {
+        let mut active = gate.active.lock().unwrap();
+        while *active >= gate.limit {
+            active = gate.released.wait(active).unwrap();
+        }
+        *active += 1;
+        ConnectionPermit(Arc::clone(gate))
+    }
+}
+
+struct ConnectionPermit(Arc<ConnectionGate>);
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        *self.0.active.lock().unwrap() -= 1;
+        self.0.released.notify_one();
+    }
+}
+
+fn serve(stream: TcpStream, router: &Router, read_timeout: Option<Duration>) -> io::Result<()> {
+    stream.set_read_timeout(read_timeout)?;
+    handle_connection(stream, router)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::Cursor,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            mpsc,
+        },
+        thread,
+        time::Instant,
+    };
+
+    /// 内存中的连接：从 input 读取请求，响应写入 output
+    struct MemoryStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MemoryStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MemoryStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parse_post_body() {
+        let raw =
+            "POST /submit HTTP/1.1\r\nHost: localhost\r\ncontent-length: 11\r\n\r\nhello=worldGET";
+        let mut reader = raw.as_bytes();
+        let request = Request::parse(&mut reader).unwrap().unwrap();
+
+        assert_eq!("POST", request.method);
+        assert_eq!("/submit", request.path);
+        assert_eq!(Some("localhost"), request.header("host"));
+        assert_eq!(b"hello=world".to_vec(), request.body);
+        // 请求体之后的内容属于下一个请求，不会被读取
+        assert_eq!(b"GET", reader);
+    }
+
+    #[test]
+    fn parse_rejects_oversized_body() {
+        let mut reader = "POST / HTTP/1.1\r\nContent-Length: 99999999999\r\n\r\n".as_bytes();
+        let err = Request::parse(&mut reader).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn parse_request_without_body() {
+        let mut reader = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".as_bytes();
+        let request = Request::parse(&mut reader).unwrap().unwrap();
+        assert_eq!("GET", request.method);
+        assert!(request.body.is_empty());
+
+        assert_eq!(None, Request::parse(&mut reader).unwrap());
+    }
+
+    #[test]
+    fn keep_alive_serves_several_requests() {
+        let router = Router::new().route("GET", "/", |_| Response::ok("index"));
+        let input = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                     GET /missing HTTP/1.1\r\nConnection: close\r\n\r\n\
+                     GET / HTTP/1.1\r\n\r\n";
+        let mut stream = MemoryStream {
+            input: Cursor::new(input.as_bytes().to_vec()),
+            output: Vec::new(),
+        };
+
+        handle_connection(&mut stream, &router).unwrap();
+
+        let output = String::from_utf8(stream.output).unwrap();
+        let responses: Vec<&str> = output.split("HTTP/1.1 ").skip(1).collect();
+        // 第二个请求要求关闭连接，第三个请求不会被处理
+        assert_eq!(2, responses.len());
+        assert!(responses[0].starts_with("200 OK"));
+        assert!(responses[0].contains("Connection: keep-alive"));
+        assert!(responses[0].ends_with("index"));
+        assert!(responses[1].starts_with("404 NOT FOUND"));
+        assert!(responses[1].contains("Connection: close"));
+    }
+
+    #[test]
+    fn concurrent_connections_are_limited() {
+        static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+        static PEAK: AtomicUsize = AtomicUsize::new(0);
+        static RELEASED: AtomicBool = AtomicBool::new(false);
+
+        // 一直占着连接，直到测试放行
+        fn slow(_: &Request) -> Response {
+            let active = ACTIVE.fetch_add(1, Ordering::SeqCst) + 1;
+            PEAK.fetch_max(active, Ordering::SeqCst);
+            while !RELEASED.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            ACTIVE.fetch_sub(1, Ordering::SeqCst);
+            Response::ok("done")
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // 服务端一直运行到测试进程结束
+        thread::spawn(move || {
+            Server::new(Router::new().route("GET", "/slow", slow))
+                .threads(4)
+                .max_concurrent_connections(Some(2))
+                .run(listener)
+        });
+
+        let clients: Vec<_> = (0..5)
+            .map(|_| {
+                thread::spawn(move || {
+                    let mut stream = TcpStream::connect(addr).unwrap();
+                    stream
+                        .write_all(b"GET /slow HTTP/1.1\r\nConnection: close\r\n\r\n")
+                        .unwrap();
+                    let mut response = String::new();
+                    stream.read_to_string(&mut response).unwrap();
+                    response
+                })
+            })
+            .collect();
+
+        // 等到两个连接都在处理中，其它连接只能等待名额
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while ACTIVE.load(Ordering::SeqCst) < 2 {
+            assert!(Instant::now() < deadline, "connections were not served");
+            thread::sleep(Duration::from_millis(1));
+        }
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(2, ACTIVE.load(Ordering::SeqCst));
+        RELEASED.store(true, Ordering::SeqCst);
+
+        for client in clients {
+            assert!(client.join().unwrap().ends_with("done"));
+        }
+        // 线程池有 4 个线程，但同时处理的连接不会超过 2 个
+        assert_eq!(2, PEAK.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    #[should_panic(expected = "max_concurrent_connections must be at least 1")]
+    fn zero_connection_limit_is_rejected() {
+        let _ = Server::new(Router::new()).max_concurrent_connections(Some(0));
+    }
+
+    #[test]
+    fn idle_connection_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // 客户端只建立连接，不发送任何数据，直到服务端处理结束
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let client = thread::spawn(move || {
+            let _stream = TcpStream::connect(addr).unwrap();
+            let _ = done_rx.recv();
+        });
+
+        let server = Server::new(Router::new()).read_timeout(Some(Duration::from_millis(100)));
+        let (stream, _) = listener.accept().unwrap();
+        let start = Instant::now();
+        let err = server.serve_connection(stream).unwrap_err();
+
+        assert!(matches!(
+            err.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        done_tx.send(()).unwrap();
+        client.join().unwrap();
+    }
+}
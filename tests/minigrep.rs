@@ -0,0 +1,27 @@
+use std::{env, fs, process::Command};
+
+/**
+ * `--threads` 在线程池中搜索，线程池的日志不能混进标准输出中的搜索结果
+ *
+ * `run_with_writer` 写入的是缓冲区，只有运行真正的程序才能检查标准输出
+ */
+#[test]
+fn threaded_search_prints_only_results() {
+    let root = env::temp_dir().join(format!("ilearn-cli-threads-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    for i in 0..8 {
+        fs::write(root.join(format!("{i}.txt")), format!("needle {i}\nhay\n")).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_minigrep"))
+        .args(["--threads", "3", "-h", "-c"])
+        .arg(&root)
+        .arg("needle")
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&root).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!("1\n".repeat(8), String::from_utf8(output.stdout).unwrap());
+}